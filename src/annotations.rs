@@ -0,0 +1,180 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Annotation files.
+//!
+//! Annotations are boxes, circles, arrows, polylines, and text labels drawn on
+//! top of a plot, given in layout coordinates in micrometers. They are read
+//! either from a text file with one annotation per line,
+//!
+//! ```text
+//! box CLASS X0 Y0 X1 Y1
+//! circle CLASS X Y RADIUS
+//! arrow CLASS X0 Y0 X1 Y1
+//! polyline CLASS X0 Y0 X1 Y1 ...
+//! text CLASS X Y TEXT...
+//! ```
+//!
+//! where CLASS is a comma-separated list of stylesheet classes or `-`, or from
+//! a JSON array of objects with the fields `type`, `class`, `points`, and
+//! depending on the type `radius`, `text`, and `size`.
+
+use std::fs::File;
+use std::io::Read;
+use Point;
+use json;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+	Box,
+	Circle,
+	Arrow,
+	Polyline,
+	Text,
+}
+
+#[derive(Debug)]
+pub struct Annotation {
+	pub kind: Kind,
+	pub classes: Vec<String>,
+	pub points: Vec<Point>,
+	/// The radius of a circle.
+	pub radius: f64,
+	pub text: String,
+	/// The font size of a text label, in pixels.
+	pub size: f64,
+}
+
+pub fn load_annotations(filename: &str) -> Result<Vec<Annotation>, String> {
+	let mut text = String::new();
+	if let Err(e) = File::open(filename).and_then(|mut f| f.read_to_string(&mut text)) {
+		return Err(format!("Unable to read annotations {}: {}", filename, e));
+	}
+	let result = if text.trim_left().starts_with('[') {
+		parse_json(&text)
+	} else {
+		parse_text(&text)
+	};
+	result.map_err(|e| format!("{}: {}", filename, e))
+}
+
+fn parse_kind(s: &str) -> Option<Kind> {
+	match s {
+		"box" => Some(Kind::Box),
+		"circle" => Some(Kind::Circle),
+		"arrow" => Some(Kind::Arrow),
+		"polyline" => Some(Kind::Polyline),
+		"text" => Some(Kind::Text),
+		_ => None,
+	}
+}
+
+/// Check that an annotation has the right number of points.
+fn validate(a: Annotation) -> Result<Annotation, String> {
+	let n = a.points.len();
+	let ok = match a.kind {
+		Kind::Box | Kind::Arrow => n == 2,
+		Kind::Circle => n == 1 && a.radius > 0.0,
+		Kind::Polyline => n >= 2,
+		Kind::Text => n == 1,
+	};
+	if ok {
+		Ok(a)
+	} else {
+		Err(format!("invalid coordinates for {:?}", a.kind))
+	}
+}
+
+fn parse_text(text: &str) -> Result<Vec<Annotation>, String> {
+	let mut annotations = Vec::new();
+	for (i, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+			continue;
+		}
+		let err = |msg: &str| format!("line {}: {}", i + 1, msg);
+		let mut it = line.split_whitespace();
+		let kind = match it.next().and_then(parse_kind) {
+			Some(k) => k,
+			None => return Err(err("expected box, circle, arrow, polyline, or text")),
+		};
+		let classes = match it.next() {
+			Some("-") => Vec::new(),
+			Some(c) => c.split(',').map(|c| c.to_owned()).collect(),
+			None => return Err(err("expected a class")),
+		};
+
+		// Coordinates follow, up to the text of a label.
+		let mut coords = Vec::new();
+		let mut label = Vec::new();
+		for token in it {
+			match token.parse::<f64>() {
+				Ok(v) if label.is_empty() && !(kind == Kind::Text && coords.len() == 2) => coords.push(v * 1e-6),
+				_ => label.push(token),
+			}
+		}
+		let radius = if kind == Kind::Circle { coords.pop().unwrap_or(0.0) } else { 0.0 };
+		if coords.len() % 2 != 0 || (kind != Kind::Text && !label.is_empty()) {
+			return Err(err("invalid coordinates"));
+		}
+		let mut label = label.join(" ");
+		if label.len() >= 2 && label.starts_with('"') && label.ends_with('"') {
+			label = label[1..label.len()-1].to_owned();
+		}
+		let a = Annotation {
+			kind: kind,
+			classes: classes,
+			points: coords.chunks(2).map(|c| Point { x: c[0], y: c[1] }).collect(),
+			radius: radius,
+			text: label,
+			size: 12.0,
+		};
+		annotations.push(try!(validate(a).map_err(|e| err(&e))));
+	}
+	Ok(annotations)
+}
+
+fn parse_json(text: &str) -> Result<Vec<Annotation>, String> {
+	let doc = try!(json::parse(text));
+	let items = match doc.as_array() {
+		Some(a) => a,
+		None => return Err(String::from("expected an array of annotations")),
+	};
+	let mut annotations = Vec::new();
+	for (i, item) in items.iter().enumerate() {
+		let err = |msg: &str| format!("annotation {}: {}", i, msg);
+		let kind = match item.get("type").and_then(|t| t.as_str()).and_then(parse_kind) {
+			Some(k) => k,
+			None => return Err(err("expected a type of box, circle, arrow, polyline, or text")),
+		};
+		let classes = match item.get("class") {
+			Some(&json::Value::String(ref c)) => vec![c.clone()],
+			Some(&json::Value::Array(ref cs)) => cs.iter().filter_map(|c| c.as_str()).map(|c| c.to_owned()).collect(),
+			_ => Vec::new(),
+		};
+		let points: Option<Vec<Point>> = match item.get("points").and_then(|p| p.as_array()) {
+			Some(ps) => ps.iter().map(|p| {
+				match p.as_array() {
+					Some(xy) if xy.len() == 2 => match (xy[0].as_f64(), xy[1].as_f64()) {
+						(Some(x), Some(y)) => Some(Point { x: x * 1e-6, y: y * 1e-6 }),
+						_ => None,
+					},
+					_ => None,
+				}
+			}).collect(),
+			None => None,
+		};
+		let a = Annotation {
+			kind: kind,
+			classes: classes,
+			points: match points {
+				Some(p) => p,
+				None => return Err(err("expected points as an array of [x, y] pairs")),
+			},
+			radius: item.get("radius").and_then(|r| r.as_f64()).unwrap_or(0.0) * 1e-6,
+			text: item.get("text").and_then(|t| t.as_str()).unwrap_or("").to_owned(),
+			size: item.get("size").and_then(|s| s.as_f64()).unwrap_or(12.0),
+		};
+		annotations.push(try!(validate(a).map_err(|e| err(&e))));
+	}
+	Ok(annotations)
+}
@@ -0,0 +1,155 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Drawing backends.
+//!
+//! The plotting code walks the prepared hierarchy and describes what to draw
+//! through the `RenderBackend` trait, such that it does not depend on how the
+//! plot is eventually produced. Geometry is handed over in layout coordinates
+//! together with the transformation into surface coordinates, in pixels.
+
+use cairo;
+use {ColorRgb, Point, Transform};
+
+/// A surface the plot is drawn onto.
+pub trait RenderBackend {
+	/// Paint the entire surface in a color.
+	fn clear(&mut self, color: ColorRgb);
+	/// Begin a layer. Everything drawn up to the matching `end_layer` is
+	/// composited with the given opacity, such that overlapping shapes within
+	/// the layer do not add up.
+	fn begin_layer(&mut self, alpha: f64);
+	fn end_layer(&mut self);
+	/// Set the color and line style used for everything drawn afterwards.
+	fn set_style(&mut self, style: &Style);
+	/// Fill a ring and the holes within it according to the even-odd rule.
+	/// The last point of each ring repeats the first.
+	fn fill_polygon(&mut self, ring: &[Point], holes: &[Vec<Point>], tx: Transform);
+	/// Stroke a line through a sequence of points. A closed line is a ring
+	/// whose last point repeats the first.
+	fn stroke_polyline(&mut self, points: &[Point], closed: bool, tx: Transform);
+	/// Draw text starting at a point on its baseline, in surface coordinates.
+	fn text(&mut self, at: Point, size: f64, text: &str);
+}
+
+/// The color and line style of drawing operations.
+#[derive(Debug, Clone)]
+pub struct Style {
+	pub color: ColorRgb,
+	pub alpha: f64,
+	/// The line width in pixels.
+	pub width: f64,
+	pub dashes: Option<Vec<f64>>,
+}
+
+impl Style {
+	pub fn solid(color: ColorRgb) -> Style {
+		Style {
+			color: color,
+			alpha: 1.0,
+			width: 1.0,
+			dashes: None,
+		}
+	}
+}
+
+/// Draws onto a Cairo surface.
+///
+/// Cairo keeps the first error that occurs in the context and ignores all
+/// drawing operations after it, so the individual operations are not checked.
+/// Instead `status` reports whether the plot was drawn completely.
+pub struct CairoBackend {
+	cr: cairo::Context,
+	/// The opacity of each layer begun but not yet ended.
+	layers: Vec<f64>,
+}
+
+impl CairoBackend {
+	pub fn new(surface: &cairo::Surface) -> Result<CairoBackend, cairo::Error> {
+		let cr = try!(cairo::Context::new(surface));
+		cr.set_fill_rule(cairo::FillRule::EvenOdd);
+		Ok(CairoBackend {
+			cr: cr,
+			layers: Vec::new(),
+		})
+	}
+
+	/// Report the first error that occurred while drawing, if any.
+	pub fn status(&self) -> Result<(), cairo::Error> {
+		self.cr.status()
+	}
+
+	/// Add a ring to the current path.
+	fn trace_ring(&mut self, points: &[Point], tx: Transform) {
+		let mut it = points.iter();
+		it.next();
+
+		if let Some(pt) = it.next() {
+			let p = tx * *pt;
+			self.cr.move_to(p.x, p.y);
+		}
+
+		for pt in it {
+			let p = tx * *pt;
+			self.cr.line_to(p.x, p.y);
+		}
+
+		self.cr.close_path();
+	}
+}
+
+impl RenderBackend for CairoBackend {
+	fn clear(&mut self, color: ColorRgb) {
+		self.cr.set_source_rgb(color.r, color.g, color.b);
+		let _ = self.cr.paint();
+	}
+
+	fn begin_layer(&mut self, alpha: f64) {
+		self.layers.push(alpha);
+		self.cr.push_group();
+	}
+
+	fn end_layer(&mut self) {
+		let alpha = self.layers.pop().expect("end_layer without begin_layer");
+		let _ = self.cr.pop_group_to_source();
+		let _ = self.cr.paint_with_alpha(alpha);
+	}
+
+	fn set_style(&mut self, style: &Style) {
+		let c = style.color;
+		self.cr.set_source_rgba(c.r, c.g, c.b, style.alpha);
+		match style.dashes {
+			Some(ref d) => self.cr.set_dash(d, 0.0),
+			None => self.cr.set_dash(&[], 0.0),
+		}
+		self.cr.set_line_width(style.width);
+	}
+
+	fn fill_polygon(&mut self, ring: &[Point], holes: &[Vec<Point>], tx: Transform) {
+		self.trace_ring(ring, tx);
+		for hole in holes {
+			self.trace_ring(hole, tx);
+		}
+		let _ = self.cr.fill();
+	}
+
+	fn stroke_polyline(&mut self, points: &[Point], closed: bool, tx: Transform) {
+		if closed {
+			self.trace_ring(points, tx);
+		} else {
+			let mut it = points.iter().map(|p| tx * *p);
+			if let Some(p) = it.next() {
+				self.cr.move_to(p.x, p.y);
+			}
+			for p in it {
+				self.cr.line_to(p.x, p.y);
+			}
+		}
+		let _ = self.cr.stroke();
+	}
+
+	fn text(&mut self, at: Point, size: f64, text: &str) {
+		self.cr.set_font_size(size);
+		self.cr.move_to(at.x, at.y);
+		let _ = self.cr.show_text(text);
+	}
+}
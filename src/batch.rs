@@ -0,0 +1,98 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Lists of clips to be rendered in one go.
+//!
+//! A clip list is either a CSV file with one `name,x0,y0,x1,y1[,cell]` line
+//! per clip, or a JSON array of objects with the same fields or of arrays in
+//! the same order. Coordinates are given in micrometers. A leading CSV header
+//! line and lines starting with `#` are ignored. Since clips are written to
+//! files named after them, clip names must not contain path separators or be
+//! `.` or `..`.
+
+use std::fs::File;
+use std::io::Read;
+use {Rect, Point};
+use json;
+
+pub struct Clip {
+	pub name: String,
+	pub window: Rect,
+	/// The cell to be clipped, if other than the default one.
+	pub cell: Option<String>,
+}
+
+pub fn load_clips(filename: &str) -> Result<Vec<Clip>, String> {
+	let mut text = String::new();
+	if let Err(e) = File::open(filename).and_then(|mut f| f.read_to_string(&mut text)) {
+		return Err(format!("Unable to read clip list {}: {}", filename, e));
+	}
+	let result = if text.trim_left().starts_with('[') {
+		parse_json(&text)
+	} else {
+		parse_csv(&text)
+	};
+	result.map_err(|e| format!("{}: {}", filename, e))
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Clip>, String> {
+	let mut clips = Vec::new();
+	let mut first = true;
+	for (i, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let header = first;
+		first = false;
+		let fields: Vec<&str> = line.split(',').map(|f| f.trim().trim_matches('"')).collect();
+		let coords: Result<Vec<f64>, _> = fields.iter().skip(1).take(4).map(|f| f.parse()).collect();
+		let coords = match coords {
+			Ok(ref c) if c.len() == 4 => c.clone(),
+			_ if header => continue,
+			_ => return Err(format!("line {}: expected `name,x0,y0,x1,y1[,cell]`", i + 1)),
+		};
+		let clip = try!(make_clip(fields[0], &coords, fields.get(5).map(|c| *c)).map_err(|e| format!("line {}: {}", i + 1, e)));
+		clips.push(clip);
+	}
+	Ok(clips)
+}
+
+fn parse_json(text: &str) -> Result<Vec<Clip>, String> {
+	let doc = try!(json::parse(text));
+	let items = match doc.as_array() {
+		Some(a) => a,
+		None => return Err(String::from("expected an array of clips")),
+	};
+	let mut clips = Vec::new();
+	for (i, item) in items.iter().enumerate() {
+		let fields: Vec<Option<&json::Value>> = match item.as_array() {
+			Some(a) => (0..6).map(|j| a.get(j)).collect(),
+			None => ["name", "x0", "y0", "x1", "y1", "cell"].iter().map(|k| item.get(k)).collect(),
+		};
+		let name = fields[0].and_then(|v| v.as_str());
+		let coords: Option<Vec<f64>> = fields[1..5].iter().map(|v| v.and_then(|v| v.as_f64())).collect();
+		match (name, coords) {
+			(Some(name), Some(coords)) => {
+				let clip = try!(make_clip(name, &coords, fields[5].and_then(|v| v.as_str())).map_err(|e| format!("clip {}: {}", i, e)));
+				clips.push(clip);
+			},
+			_ => return Err(format!("clip {}: expected a name and coordinates x0, y0, x1, y1", i)),
+		}
+	}
+	Ok(clips)
+}
+
+fn make_clip(name: &str, coords: &[f64], cell: Option<&str>) -> Result<Clip, String> {
+	if name.is_empty() || name == "." || name == ".." || name.contains(|c| c == '/' || c == '\\' || c == ':' || c == '\0') {
+		return Err(format!("`{}` cannot be used as a file name", name));
+	}
+	let (x0, y0, x1, y1) = (coords[0] * 1e-6, coords[1] * 1e-6, coords[2] * 1e-6, coords[3] * 1e-6);
+	Ok(Clip {
+		name: name.to_owned(),
+		window: Rect {
+			min: Point { x: x0.min(x1), y: y0.min(y1) },
+			max: Point { x: x0.max(x1), y: y0.max(y1) },
+		},
+		cell: cell.and_then(|c| if c.is_empty() { None } else { Some(c.to_owned()) }),
+	})
+}
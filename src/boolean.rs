@@ -0,0 +1,533 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Boolean operations on polygons.
+//!
+//! The operands are cut into vertical slabs at the x coordinates of all
+//! vertices and edge intersections. Within a slab no two edges cross, such
+//! that the area covered by the result is a list of trapezoids stacked on top
+//! of each other. The outline of the result is traced along the boundaries of
+//! these trapezoids and then chained into rings.
+
+use std;
+use std::collections::HashMap;
+use Point;
+
+/// A polygon with holes. The outline runs counter-clockwise, the holes run
+/// clockwise. Rings are not closed, i.e. the first point is not repeated at
+/// the end.
+#[derive(Debug, Clone)]
+pub struct Polygon {
+	pub outline: Vec<Point>,
+	pub holes: Vec<Vec<Point>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+	And,
+	Or,
+	Xor,
+	AndNot,
+}
+
+impl Op {
+	fn apply(&self, a: bool, b: bool) -> bool {
+		match *self {
+			Op::And => a && b,
+			Op::Or => a || b,
+			Op::Xor => a != b,
+			Op::AndNot => a && !b,
+		}
+	}
+}
+
+/// The grid onto which the vertices of the result are snapped, in meters.
+const GRID: f64 = 1e-12;
+
+/// A non-vertical edge of an operand, oriented from left to right. The winding
+/// is +1 if the edge originally pointed to the right, and -1 otherwise.
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+	a: Point,
+	b: Point,
+	wind: i32,
+	operand: usize,
+}
+
+impl Edge {
+	fn y_at(&self, x: f64) -> f64 {
+		if x <= self.a.x {
+			self.a.y
+		} else if x >= self.b.x {
+			self.b.y
+		} else {
+			self.a.y + (self.b.y - self.a.y) * (x - self.a.x) / (self.b.x - self.a.x)
+		}
+	}
+}
+
+/// A trapezoid covered by the result within one slab, given by the y
+/// coordinates of its lower and upper edge at the left and right end of the
+/// slab.
+struct Interval {
+	lo: (f64, f64),
+	hi: (f64, f64),
+}
+
+
+/// Combine two sets of rings. A point is considered inside an operand if the
+/// winding number of the operand's rings around it is non-zero.
+pub fn boolean(a: &[Vec<Point>], b: &[Vec<Point>], op: Op) -> Vec<Polygon> {
+	let mut edges = Vec::new();
+	collect_edges(a, 0, &mut edges);
+	collect_edges(b, 1, &mut edges);
+	let outline = trace(edges, op);
+	assemble(chain(outline))
+}
+
+/// Merge a set of rings into non-overlapping polygons.
+pub fn merge(rings: &[Vec<Point>]) -> Vec<Polygon> {
+	boolean(rings, &[], Op::Or)
+}
+
+/// Grow a set of rings by a distance, or shrink it if the distance is
+/// negative. The rings are dilated with a square, such that corners stay
+/// sharp.
+pub fn grow(rings: &[Vec<Point>], d: f64) -> Vec<Polygon> {
+	// Sweep the square along every edge. Together the bands cover all points
+	// that lie within the distance of the operand's outline.
+	let r = d.abs();
+	let mut bands = Vec::new();
+	for ring in rings {
+		for i in 0..ring.len() {
+			let p = ring[i];
+			let q = ring[(i+1) % ring.len()];
+			let mut corners = Vec::with_capacity(8);
+			for &(dx,dy) in &[(-r,-r), (r,-r), (r,r), (-r,r)] {
+				corners.push(Point { x: p.x + dx, y: p.y + dy });
+				corners.push(Point { x: q.x + dx, y: q.y + dy });
+			}
+			bands.push(convex_hull(corners));
+		}
+	}
+	if d >= 0.0 {
+		boolean(rings, &bands, Op::Or)
+	} else {
+		boolean(rings, &bands, Op::AndNot)
+	}
+}
+
+/// Flatten polygons into a list of rings which can be fed back into the other
+/// operations.
+pub fn rings(polys: &[Polygon]) -> Vec<Vec<Point>> {
+	let mut rings = Vec::new();
+	for p in polys {
+		rings.push(p.outline.clone());
+		for h in &p.holes {
+			rings.push(h.clone());
+		}
+	}
+	rings
+}
+
+/// Orient a ring counter-clockwise.
+pub fn ccw(mut ring: Vec<Point>) -> Vec<Point> {
+	if signed_area(&ring) < 0.0 {
+		ring.reverse();
+	}
+	ring
+}
+
+
+fn collect_edges(rings: &[Vec<Point>], operand: usize, into: &mut Vec<Edge>) {
+	for ring in rings {
+		for i in 0..ring.len() {
+			let p = ring[i];
+			let q = ring[(i+1) % ring.len()];
+			if p.x < q.x {
+				into.push(Edge { a: p, b: q, wind: 1, operand: operand });
+			} else if p.x > q.x {
+				into.push(Edge { a: q, b: p, wind: -1, operand: operand });
+			}
+		}
+	}
+}
+
+/// Calculate the directed outline edges of the area covered by the result.
+fn trace(mut edges: Vec<Edge>, op: Op) -> Vec<(Point, Point)> {
+	let mut xs: Vec<f64> = Vec::with_capacity(edges.len() * 2);
+	for e in &edges {
+		xs.push(e.a.x);
+		xs.push(e.b.x);
+	}
+	xs.sort_by(|a,b| a.partial_cmp(b).unwrap());
+	xs.dedup();
+	edges.sort_by(|a,b| a.a.x.partial_cmp(&b.a.x).unwrap());
+
+	let mut outline = Vec::new();
+	let mut next = 0;
+	let mut active: Vec<Edge> = Vec::new();
+	let mut prev: Vec<(f64,f64)> = Vec::new();
+
+	for w in xs.windows(2) {
+		let (x0, x1) = (w[0], w[1]);
+
+		// Update the list of edges spanning the slab.
+		active.retain(|e| e.b.x > x0);
+		while next < edges.len() && edges[next].a.x <= x0 {
+			active.push(edges[next]);
+			next += 1;
+		}
+
+		// Split the slab wherever two edges cross.
+		let mut splits = vec![x0, x1];
+		let mut ends: Vec<(f64,f64)> = active.iter().map(|e| (e.y_at(x0), e.y_at(x1))).collect();
+		ends.sort_by(|a,b| a.partial_cmp(b).unwrap());
+		if ends.windows(2).any(|w| w[0].1 > w[1].1) {
+			for i in 0..ends.len() {
+				for j in i+1..ends.len() {
+					let dl = ends[i].0 - ends[j].0;
+					let dr = ends[i].1 - ends[j].1;
+					if dl * dr < 0.0 {
+						splits.push(x0 + (x1 - x0) * dl / (dl - dr));
+					}
+				}
+			}
+			splits.sort_by(|a,b| a.partial_cmp(b).unwrap());
+			splits.dedup();
+		}
+
+		for s in splits.windows(2) {
+			let (sa, sb) = (s[0], s[1]);
+			let intervals = cover(&active, sa, sb, op);
+
+			// Vertical outline edges where the coverage changes.
+			let right: Vec<(f64,f64)> = intervals.iter().map(|iv| (iv.lo.0, iv.hi.0)).collect();
+			vertical_edges(sa, &prev, &right, &mut outline);
+
+			// Lower and upper outline edges of each trapezoid.
+			for iv in &intervals {
+				outline.push((Point { x: sa, y: iv.lo.0 }, Point { x: sb, y: iv.lo.1 }));
+				outline.push((Point { x: sb, y: iv.hi.1 }, Point { x: sa, y: iv.hi.0 }));
+			}
+			prev = intervals.iter().map(|iv| (iv.lo.1, iv.hi.1)).collect();
+		}
+	}
+	if let Some(&x) = xs.last() {
+		vertical_edges(x, &prev, &[], &mut outline);
+	}
+
+	outline
+}
+
+/// Calculate the trapezoids covered by the result within a slab.
+fn cover(active: &[Edge], sa: f64, sb: f64, op: Op) -> Vec<Interval> {
+	let xm = 0.5 * (sa + sb);
+	let mut order: Vec<&Edge> = active.iter().collect();
+	order.sort_by(|a,b| a.y_at(xm).partial_cmp(&b.y_at(xm)).unwrap());
+
+	let mut intervals = Vec::new();
+	let mut wind = [0i32; 2];
+	let mut inside = false;
+	let mut lo = (0.0, 0.0);
+	for e in order {
+		wind[e.operand] += e.wind;
+		let now = op.apply(wind[0] != 0, wind[1] != 0);
+		if now && !inside {
+			lo = (e.y_at(sa), e.y_at(sb));
+		} else if !now && inside {
+			let hi = (e.y_at(sa), e.y_at(sb));
+			if hi.0 > lo.0 || hi.1 > lo.1 {
+				intervals.push(Interval { lo: lo, hi: hi });
+			}
+		}
+		inside = now;
+	}
+	intervals
+}
+
+/// Emit the vertical outline edges at a slab boundary. Parts covered only on
+/// the right run downwards, parts covered only on the left run upwards.
+fn vertical_edges(x: f64, left: &[(f64,f64)], right: &[(f64,f64)], into: &mut Vec<(Point, Point)>) {
+	for (lo, hi) in subtract(right, left) {
+		into.push((Point { x: x, y: hi }, Point { x: x, y: lo }));
+	}
+	for (lo, hi) in subtract(left, right) {
+		into.push((Point { x: x, y: lo }, Point { x: x, y: hi }));
+	}
+}
+
+/// Subtract one sorted list of disjoint intervals from another.
+fn subtract(a: &[(f64,f64)], b: &[(f64,f64)]) -> Vec<(f64,f64)> {
+	let mut out = Vec::new();
+	for &(lo, hi) in a {
+		let mut cur = lo;
+		for &(blo, bhi) in b {
+			if bhi <= cur {
+				continue;
+			}
+			if blo >= hi {
+				break;
+			}
+			if blo > cur {
+				out.push((cur, blo));
+			}
+			cur = bhi;
+			if cur >= hi {
+				break;
+			}
+		}
+		if cur < hi {
+			out.push((cur, hi));
+		}
+	}
+	out
+}
+
+fn key(p: Point) -> (i64, i64) {
+	((p.x / GRID).round() as i64, (p.y / GRID).round() as i64)
+}
+
+fn snap(p: Point) -> Point {
+	Point {
+		x: (p.x / GRID).round() * GRID,
+		y: (p.y / GRID).round() * GRID,
+	}
+}
+
+/// Chain directed outline edges into closed rings.
+fn chain(edges: Vec<(Point, Point)>) -> Vec<Vec<Point>> {
+	let edges: Vec<(Point, Point)> = edges.into_iter()
+		.map(|(a,b)| (snap(a), snap(b)))
+		.filter(|&(a,b)| key(a) != key(b))
+		.collect();
+
+	let mut outgoing = HashMap::<(i64,i64), Vec<usize>>::new();
+	for (i, e) in edges.iter().enumerate() {
+		outgoing.entry(key(e.0)).or_insert_with(Vec::new).push(i);
+	}
+
+	let mut used = vec![false; edges.len()];
+	let mut rings = Vec::new();
+	for start in 0..edges.len() {
+		if used[start] {
+			continue;
+		}
+		let first = key(edges[start].0);
+		let mut ring = Vec::new();
+		let mut i = start;
+		loop {
+			used[i] = true;
+			ring.push(edges[i].0);
+			let end = key(edges[i].1);
+			if end == first {
+				break;
+			}
+			match outgoing.get(&end).and_then(|v| v.iter().cloned().find(|&j| !used[j])) {
+				Some(j) => i = j,
+				None => break,
+			}
+		}
+		let ring = simplify(ring);
+		if ring.len() >= 3 {
+			rings.push(ring);
+		}
+	}
+	rings
+}
+
+/// Remove collinear points from a ring.
+fn simplify(ring: Vec<Point>) -> Vec<Point> {
+	let mut out: Vec<Point> = Vec::with_capacity(ring.len());
+	for p in ring {
+		while out.len() >= 2 && collinear(out[out.len()-2], out[out.len()-1], p) {
+			out.pop();
+		}
+		out.push(p);
+	}
+	while out.len() >= 3 && collinear(out[out.len()-2], out[out.len()-1], out[0]) {
+		out.pop();
+	}
+	while out.len() >= 3 && collinear(out[out.len()-1], out[0], out[1]) {
+		out.remove(0);
+	}
+	out
+}
+
+fn collinear(a: Point, b: Point, c: Point) -> bool {
+	let u = b - a;
+	let v = c - b;
+	let cross = u.x * v.y - u.y * v.x;
+	let norm = (u.x * u.x + u.y * u.y).sqrt() * (v.x * v.x + v.y * v.y).sqrt();
+	norm == 0.0 || cross.abs() <= norm * 1e-9
+}
+
+/// Calculate the area enclosed by a ring, which is positive if the ring runs
+/// counter-clockwise.
+pub fn signed_area(ring: &[Point]) -> f64 {
+	let mut area = 0.0;
+	for i in 0..ring.len() {
+		let p = ring[i];
+		let q = ring[(i+1) % ring.len()];
+		area += p.x * q.y - q.x * p.y;
+	}
+	0.5 * area
+}
+
+fn contains(ring: &[Point], p: Point) -> bool {
+	let mut inside = false;
+	for i in 0..ring.len() {
+		let a = ring[i];
+		let b = ring[(i+1) % ring.len()];
+		if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (b.x - a.x) * (p.y - a.y) / (b.y - a.y) {
+			inside = !inside;
+		}
+	}
+	inside
+}
+
+/// Assign each clockwise ring as a hole to the smallest counter-clockwise ring
+/// that contains it.
+fn assemble(rings: Vec<Vec<Point>>) -> Vec<Polygon> {
+	let mut polys = Vec::new();
+	let mut areas = Vec::new();
+	let mut holes = Vec::new();
+	for ring in rings {
+		let area = signed_area(&ring);
+		if area > 0.0 {
+			polys.push(Polygon { outline: ring, holes: Vec::new() });
+			areas.push(area);
+		} else if area < 0.0 {
+			holes.push(ring);
+		}
+	}
+
+	for hole in holes {
+		let probe = Point {
+			x: 0.5 * (hole[0].x + hole[1].x),
+			y: 0.5 * (hole[0].y + hole[1].y),
+		};
+		let mut best: Option<usize> = None;
+		for (i, p) in polys.iter().enumerate() {
+			if contains(&p.outline, probe) && best.map(|b| areas[i] < areas[b]).unwrap_or(true) {
+				best = Some(i);
+			}
+		}
+		if let Some(i) = best {
+			polys[i].holes.push(hole);
+		}
+	}
+	polys
+}
+
+/// Calculate the counter-clockwise convex hull of a set of points.
+fn convex_hull(mut pts: Vec<Point>) -> Vec<Point> {
+	pts.sort_by(|a,b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap_or(std::cmp::Ordering::Equal));
+	let turn = |a: Point, b: Point, c: Point| (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+	let mut lower: Vec<Point> = Vec::new();
+	for &p in &pts {
+		while lower.len() >= 2 && turn(lower[lower.len()-2], lower[lower.len()-1], p) <= 0.0 {
+			lower.pop();
+		}
+		lower.push(p);
+	}
+	let mut upper: Vec<Point> = Vec::new();
+	for &p in pts.iter().rev() {
+		while upper.len() >= 2 && turn(upper[upper.len()-2], upper[upper.len()-1], p) <= 0.0 {
+			upper.pop();
+		}
+		upper.push(p);
+	}
+	lower.pop();
+	upper.pop();
+	lower.extend(upper);
+	lower
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A rectangle given in micrometers.
+	fn rect(x0: f64, y0: f64, x1: f64, y1: f64) -> Vec<Point> {
+		[(x0, y0), (x1, y0), (x1, y1), (x0, y1)].iter()
+			.map(|&(x, y)| Point { x: x * 1e-6, y: y * 1e-6 })
+			.collect()
+	}
+
+	/// The area covered by polygons in square micrometers.
+	fn area(polys: &[Polygon]) -> f64 {
+		rings(polys).iter().map(|r| signed_area(r)).sum::<f64>() * 1e12
+	}
+
+	fn assert_area(polys: &[Polygon], expected: f64) {
+		let a = area(polys);
+		assert!((a - expected).abs() < 1e-6, "area {} instead of {}", a, expected);
+	}
+
+	#[test]
+	fn operations() {
+		let a = vec![rect(0.0, 0.0, 10.0, 10.0)];
+		let b = vec![rect(5.0, 0.0, 15.0, 10.0)];
+		assert_area(&boolean(&a, &b, Op::And), 50.0);
+		assert_area(&boolean(&a, &b, Op::Or), 150.0);
+		assert_area(&boolean(&a, &b, Op::Xor), 100.0);
+		assert_area(&boolean(&a, &b, Op::AndNot), 50.0);
+		assert_area(&boolean(&b, &a, Op::AndNot), 50.0);
+		assert_eq!(boolean(&a, &b, Op::Or).len(), 1);
+		assert_eq!(boolean(&a, &b, Op::Xor).len(), 2);
+	}
+
+	#[test]
+	fn disjoint_and_empty() {
+		let a = vec![rect(0.0, 0.0, 1.0, 1.0)];
+		let b = vec![rect(5.0, 5.0, 6.0, 6.0)];
+		assert!(boolean(&a, &b, Op::And).is_empty());
+		assert_eq!(boolean(&a, &b, Op::Or).len(), 2);
+		assert_area(&boolean(&a, &[], Op::Or), 1.0);
+		assert!(boolean(&[], &[], Op::Or).is_empty());
+	}
+
+	#[test]
+	fn holes() {
+		let outer = vec![rect(0.0, 0.0, 10.0, 10.0)];
+		let inner = vec![rect(2.0, 2.0, 8.0, 8.0)];
+		let frame = boolean(&outer, &inner, Op::AndNot);
+		assert_eq!(frame.len(), 1);
+		assert_eq!(frame[0].holes.len(), 1);
+		assert!(signed_area(&frame[0].outline) > 0.0);
+		assert!(signed_area(&frame[0].holes[0]) < 0.0);
+		assert_area(&frame, 64.0);
+
+		// Filling the hole again yields a single solid square.
+		let filled = boolean(&rings(&frame), &inner, Op::Or);
+		assert_eq!(filled.len(), 1);
+		assert!(filled[0].holes.is_empty());
+		assert_area(&filled, 100.0);
+	}
+
+	#[test]
+	fn merge_overlapping() {
+		let merged = merge(&[rect(0.0, 0.0, 10.0, 10.0), rect(5.0, 5.0, 15.0, 15.0)]);
+		assert_eq!(merged.len(), 1);
+		assert_area(&merged, 175.0);
+
+		// Clockwise rings count the same as counter-clockwise ones.
+		let mut cw = rect(0.0, 0.0, 10.0, 10.0);
+		cw.reverse();
+		assert_area(&merge(&[cw]), 100.0);
+	}
+
+	#[test]
+	fn grow_and_shrink() {
+		let square = vec![rect(0.0, 0.0, 10.0, 10.0)];
+		assert_area(&grow(&square, 1e-6), 144.0);
+		assert_area(&grow(&square, -1e-6), 64.0);
+		assert!(grow(&square, -6e-6).is_empty());
+
+		// Shrinking closes gaps narrower than twice the distance after growing.
+		let pair = vec![rect(0.0, 0.0, 10.0, 10.0), rect(11.0, 0.0, 21.0, 10.0)];
+		let closed = grow(&rings(&grow(&pair, 1e-6)), -1e-6);
+		assert_eq!(closed.len(), 1);
+		assert_area(&closed, 210.0);
+	}
+}
@@ -0,0 +1,92 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Decompression of gzip, bzip2, and xz compressed input files.
+
+use std::io::Read;
+use flate2::bufread::GzDecoder;
+use bzip2::bufread::MultiBzDecoder;
+use xz2::bufread::XzDecoder;
+
+/// A compression format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	Gzip,
+	Bzip2,
+	Xz,
+}
+
+impl Format {
+	/// Recognize the format of compressed data by its first bytes.
+	pub fn detect(data: &[u8]) -> Option<Format> {
+		if data.starts_with(&[0x1f, 0x8b]) {
+			Some(Format::Gzip)
+		} else if data.starts_with(b"BZh") {
+			Some(Format::Bzip2)
+		} else if data.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+			Some(Format::Xz)
+		} else {
+			None
+		}
+	}
+
+	/// Guess the format from the extension of a file name.
+	pub fn from_filename(filename: &str) -> Option<Format> {
+		if filename.ends_with(".gz") {
+			Some(Format::Gzip)
+		} else if filename.ends_with(".bz2") {
+			Some(Format::Bzip2)
+		} else if filename.ends_with(".xz") {
+			Some(Format::Xz)
+		} else {
+			None
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Format::Gzip => "gzip",
+			Format::Bzip2 => "bzip2",
+			Format::Xz => "xz",
+		}
+	}
+
+	pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>, String> {
+		match self {
+			Format::Gzip => gunzip(data),
+			Format::Bzip2 => bunzip2(data),
+			Format::Xz => unxz(data),
+		}
+	}
+}
+
+/// Decompress a gzip file, which may consist of several members.
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, String> {
+	let mut out = Vec::new();
+	let mut rest = data;
+	while !rest.is_empty() {
+		let pos = data.len() - rest.len();
+		let mut decoder = GzDecoder::new(rest);
+		try!(decoder.read_to_end(&mut out).map_err(|e| format!("corrupt gzip member at byte {}, {}", pos, e)));
+		rest = decoder.into_inner();
+
+		// Some tools pad the file with zeros.
+		if rest.iter().all(|&b| b == 0) {
+			break;
+		}
+	}
+	Ok(out)
+}
+
+/// Decompress a bzip2 file, which may consist of several streams.
+fn bunzip2(data: &[u8]) -> Result<Vec<u8>, String> {
+	let mut out = Vec::new();
+	try!(MultiBzDecoder::new(data).read_to_end(&mut out).map_err(|e| format!("corrupt bzip2 data, {}", e)));
+	Ok(out)
+}
+
+/// Decompress an xz file, which may consist of several streams.
+fn unxz(data: &[u8]) -> Result<Vec<u8>, String> {
+	let mut out = Vec::new();
+	try!(XzDecoder::new_multi_decoder(data).read_to_end(&mut out).map_err(|e| format!("corrupt xz data, {}", e)));
+	Ok(out)
+}
@@ -0,0 +1,48 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! The errors reported by the library.
+
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+	/// A file could not be read or written. Carries a description of what was
+	/// being attempted.
+	Io(String, io::Error),
+	/// A stylesheet line is invalid. Carries the file name, the line number,
+	/// and a message.
+	Stylesheet(String, usize, String),
+	/// A cell is missing from the library.
+	UnknownCell(String),
+	/// Some other input is invalid, such as a malformed marker database.
+	Invalid(String),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Error::Io(ref what, ref e) => write!(f, "{}: {}", what, e),
+			Error::Stylesheet(ref file, line, ref msg) => write!(f, "{}:{}: {}", file, line, msg),
+			Error::UnknownCell(ref name) => write!(f, "Unable to find cell {}", name),
+			Error::Invalid(ref msg) => write!(f, "{}", msg),
+		}
+	}
+}
+
+impl ::std::error::Error for Error {
+	fn description(&self) -> &str {
+		match *self {
+			Error::Io(..) => "I/O error",
+			Error::Stylesheet(..) => "invalid stylesheet",
+			Error::UnknownCell(..) => "unknown cell",
+			Error::Invalid(ref msg) => msg,
+		}
+	}
+}
+
+impl From<String> for Error {
+	fn from(msg: String) -> Error {
+		Error::Invalid(msg)
+	}
+}
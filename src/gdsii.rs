@@ -0,0 +1,886 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! A GDSII stream reader, used in place of libgds when the `gdsii` feature is
+//! enabled.
+//!
+//! A stream is a sequence of records, each starting with its length in bytes,
+//! its type, and the type of the data it carries. The library is read into
+//! memory in full and offers the same interface as the `gds` crate, such that
+//! the rest of the plotter does not care which reader is used. Errors name
+//! the offending record and its byte offset within the file. Other layout
+//! formats are read into the same structures.
+//!
+//! Paths and boxes are read as the boundaries outlining them, since the
+//! plotter only fills boundaries.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub struct Library {
+	name: String,
+	/// The size of a database unit in user units and in meters.
+	units: (f64, f64),
+	structs: HashMap<String, Struct>,
+}
+
+/// A struct, or cell. Cloning it is cheap.
+#[derive(Clone)]
+pub struct Struct(Arc<StructData>);
+
+struct StructData {
+	name: String,
+	elems: Vec<Elem>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ElemKind {
+	Boundary,
+	Path,
+	Sref,
+	Aref,
+	Text,
+	Node,
+	Box,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Xy {
+	pub x: i32,
+	pub y: i32,
+}
+
+/// The transformation of a reference or text. Bit 15 of the flags reflects
+/// about the x axis, the angle is given in degrees.
+#[derive(Debug, Clone, Copy)]
+pub struct Strans {
+	pub flags: u16,
+	pub mag: f64,
+	pub angle: f64,
+}
+
+/// The displacements of the copies of a repeated element, which other layout
+/// formats can describe without listing each copy.
+#[derive(Debug, Clone)]
+pub enum Repetition {
+	/// A lattice of columns and rows along two vectors.
+	Grid(u32, u32, Xy, Xy),
+	List(Vec<Xy>),
+}
+
+impl Repetition {
+	/// The number of copies.
+	pub fn len(&self) -> usize {
+		match *self {
+			Repetition::Grid(cols, rows, _, _) => cols as usize * rows as usize,
+			Repetition::List(ref v) => v.len(),
+		}
+	}
+
+	/// The displacement of a copy. The copies of a grid are numbered row by
+	/// row.
+	pub fn offset(&self, i: usize) -> Xy {
+		match *self {
+			Repetition::Grid(cols, _, c, r) => {
+				let (col, row) = ((i % cols as usize) as i64, (i / cols as usize) as i64);
+				Xy {
+					x: (col * c.x as i64 + row * r.x as i64) as i32,
+					y: (col * c.y as i64 + row * r.y as i64) as i32,
+				}
+			},
+			Repetition::List(ref v) => v[i],
+		}
+	}
+}
+
+/// An element of a struct. The fields are public such that other readers can
+/// assemble elements; the getters mirror the interface of the gds crate.
+#[derive(Debug, Clone)]
+pub struct Elem {
+	pub kind: ElemKind,
+	pub layer: u16,
+	/// The datatype, text type, node type, or box type.
+	pub datatype: u16,
+	pub xy: Vec<Xy>,
+	pub sname: String,
+	pub strans: Option<Strans>,
+	pub colrow: (u16, u16),
+	pub width: i32,
+	pub pathtype: u16,
+	pub extensions: (i32, i32),
+	pub string: String,
+	pub presentation: u16,
+	pub properties: Vec<(String, String)>,
+	/// The copies of the element, if it is repeated. GDSII streams do not
+	/// repeat elements other than through array references.
+	pub repetition: Option<Repetition>,
+}
+
+impl Library {
+	/// Assemble a library from the elements of each struct.
+	pub fn new(name: String, units: (f64, f64), structs: Vec<(String, Vec<Elem>)>) -> Library {
+		Library {
+			name: name,
+			units: units,
+			structs: structs.into_iter().map(|(name, elems)| {
+				(name.clone(), Struct(Arc::new(StructData { name: name, elems: elems })))
+			}).collect(),
+		}
+	}
+
+	/// Parse a GDSII stream.
+	pub fn read(data: &[u8]) -> Result<Library, String> {
+		let mut rd = RecordReader { data: data, pos: 0 };
+		try!(try!(rd.next()).expect(HEADER));
+		try!(try!(rd.next()).expect(BGNLIB));
+		let name = try!(try!(try!(rd.next()).expect(LIBNAME)).string());
+
+		// Skip the optional records up to the units.
+		let units = loop {
+			let rec = try!(rd.next());
+			match rec.kind {
+				UNITS => {
+					let v = try!(rec.real8s());
+					if v.len() != 2 {
+						return Err(rec.error("expected two values"));
+					}
+					break (v[0], v[1]);
+				},
+				REFLIBS | FONTS | ATTRTABLE | GENERATIONS | FORMAT | MASK | ENDMASKS | LIBDIRSIZE | SRFNAME | LIBSECUR => (),
+				_ => return Err(rec.unexpected("before UNITS")),
+			}
+		};
+
+		let mut structs = HashMap::new();
+		loop {
+			let rec = try!(rd.next());
+			match rec.kind {
+				BGNSTR => {
+					let s = try!(read_struct(&mut rd));
+					structs.insert(s.name.clone(), Struct(Arc::new(s)));
+				},
+				ENDLIB => break,
+				_ => return Err(rec.unexpected("between structures")),
+			}
+		}
+
+		Ok(Library {
+			name: name,
+			units: units,
+			structs: structs,
+		})
+	}
+
+	pub fn get_name(&self) -> &str {
+		&self.name
+	}
+
+	/// The size of a database unit in meters.
+	pub fn get_units_in_m(&self) -> f64 {
+		self.units.1
+	}
+
+	/// The size of a database unit in user units.
+	pub fn get_units_in_user(&self) -> f64 {
+		self.units.0
+	}
+
+	pub fn find_struct(&self, name: &str) -> Option<Struct> {
+		self.structs.get(name).cloned()
+	}
+
+	/// The names of all structs in the library, sorted.
+	pub fn struct_names(&self) -> Vec<&str> {
+		let mut names: Vec<&str> = self.structs.keys().map(|n| n.as_str()).collect();
+		names.sort();
+		names
+	}
+}
+
+impl Struct {
+	pub fn get_name(&self) -> String {
+		self.0.name.clone()
+	}
+
+	pub fn elems(&self) -> ::std::slice::Iter<'_, Elem> {
+		self.0.elems.iter()
+	}
+}
+
+impl Elem {
+	pub fn new(kind: ElemKind) -> Elem {
+		Elem {
+			kind: kind,
+			layer: 0,
+			datatype: 0,
+			xy: Vec::new(),
+			sname: String::new(),
+			strans: None,
+			colrow: (1, 1),
+			width: 0,
+			pathtype: 0,
+			extensions: (0, 0),
+			string: String::new(),
+			presentation: 0,
+			properties: Vec::new(),
+			repetition: None,
+		}
+	}
+
+	pub fn get_kind(&self) -> ElemKind {
+		self.kind
+	}
+
+	pub fn get_layer(&self) -> u16 {
+		self.layer
+	}
+
+	pub fn get_type(&self) -> u16 {
+		self.datatype
+	}
+
+	pub fn get_xy(&self) -> &[Xy] {
+		&self.xy
+	}
+
+	pub fn get_sname(&self) -> String {
+		self.sname.clone()
+	}
+
+	pub fn get_strans(&self) -> Option<Strans> {
+		self.strans
+	}
+
+	pub fn get_colrow(&self) -> (u16, u16) {
+		self.colrow
+	}
+
+	/// The width of a path or text. Negative widths are absolute, that is
+	/// not affected by magnification.
+	pub fn get_width(&self) -> i32 {
+		self.width
+	}
+
+	pub fn get_pathtype(&self) -> u16 {
+		self.pathtype
+	}
+
+	/// How far a path of type 4 extends beyond its first and last point.
+	pub fn get_extensions(&self) -> (i32, i32) {
+		self.extensions
+	}
+
+	pub fn get_string(&self) -> &str {
+		&self.string
+	}
+
+	pub fn get_presentation(&self) -> u16 {
+		self.presentation
+	}
+
+	/// The names and values of the element's properties. GDS properties are
+	/// named by their attribute number.
+	pub fn get_properties(&self) -> &[(String, String)] {
+		&self.properties
+	}
+
+	pub fn get_repetition(&self) -> Option<&Repetition> {
+		self.repetition.as_ref()
+	}
+
+	/// Turn a path or box into the boundary outlining it. Returns `None` for
+	/// paths which cover no area. Other elements are returned unchanged.
+	pub fn outlined(mut self) -> Option<Elem> {
+		match self.kind {
+			ElemKind::Path => {
+				let halfwidth = (self.width as f64).abs() / 2.0;
+				let extensions = match self.pathtype {
+					// Round ends are approximated by square ones.
+					1 | 2 => (halfwidth, halfwidth),
+					4 => (self.extensions.0 as f64, self.extensions.1 as f64),
+					_ => (0.0, 0.0),
+				};
+				self.xy = path_outline(&self.xy, halfwidth, extensions);
+				if self.xy.is_empty() {
+					return None;
+				}
+				self.kind = ElemKind::Boundary;
+			},
+			ElemKind::Box => self.kind = ElemKind::Boundary,
+			_ => (),
+		}
+		Some(self)
+	}
+}
+
+/// The outline of a path as a closed ring, given its half-width and how far it
+/// extends beyond its first and last point. Corners are mitred, except for
+/// very sharp ones which are cut off. Returns an empty ring if the path covers
+/// no area.
+pub fn path_outline(points: &[Xy], halfwidth: f64, extensions: (f64, f64)) -> Vec<Xy> {
+	let mut p: Vec<(f64, f64)> = Vec::with_capacity(points.len());
+	for xy in points {
+		let q = (xy.x as f64, xy.y as f64);
+		if p.last() != Some(&q) {
+			p.push(q);
+		}
+	}
+	if p.len() < 2 || halfwidth <= 0.0 {
+		return Vec::new();
+	}
+	let n = p.len();
+	let dirs: Vec<(f64, f64)> = p.windows(2).map(|w| {
+		let (dx, dy) = (w[1].0 - w[0].0, w[1].1 - w[0].1);
+		let l = (dx * dx + dy * dy).sqrt();
+		(dx / l, dy / l)
+	}).collect();
+	p[0] = (p[0].0 - dirs[0].0 * extensions.0, p[0].1 - dirs[0].1 * extensions.0);
+	p[n - 1] = (p[n - 1].0 + dirs[n - 2].0 * extensions.1, p[n - 1].1 + dirs[n - 2].1 * extensions.1);
+
+	// Offset each point to the left and right, along the bisector of the
+	// normals of the adjacent segments.
+	let mut left = Vec::with_capacity(n);
+	let mut right = Vec::with_capacity(n);
+	for i in 0..n {
+		let a = dirs[if i > 0 { i - 1 } else { 0 }];
+		let b = dirs[if i < n - 1 { i } else { n - 2 }];
+		let (na, nb) = ((-a.1, a.0), (-b.1, b.0));
+		// The mitre grows as one plus the cosine of the turn shrinks; limit
+		// it to about three half-widths.
+		let k = 1.0 + na.0 * nb.0 + na.1 * nb.1;
+		if k > 0.25 {
+			let m = ((na.0 + nb.0) / k * halfwidth, (na.1 + nb.1) / k * halfwidth);
+			left.push((p[i].0 + m.0, p[i].1 + m.1));
+			right.push((p[i].0 - m.0, p[i].1 - m.1));
+		} else {
+			for nv in &[na, nb] {
+				left.push((p[i].0 + nv.0 * halfwidth, p[i].1 + nv.1 * halfwidth));
+				right.push((p[i].0 - nv.0 * halfwidth, p[i].1 - nv.1 * halfwidth));
+			}
+		}
+	}
+	let mut ring: Vec<Xy> = left.into_iter().chain(right.into_iter().rev()).map(|(x, y)| Xy {
+		x: x.round() as i32,
+		y: y.round() as i32,
+	}).collect();
+	let first = ring[0];
+	ring.push(first);
+	ring
+}
+
+/// Read the records of a structure following its BGNSTR.
+fn read_struct(rd: &mut RecordReader) -> Result<StructData, String> {
+	let name = try!(try!(try!(rd.next()).expect(STRNAME)).string());
+	let mut elems = Vec::new();
+	loop {
+		let rec = try!(rd.next());
+		let kind = match rec.kind {
+			BOUNDARY => ElemKind::Boundary,
+			PATH => ElemKind::Path,
+			SREF => ElemKind::Sref,
+			AREF => ElemKind::Aref,
+			TEXT => ElemKind::Text,
+			NODE => ElemKind::Node,
+			BOX => ElemKind::Box,
+			STRCLASS => continue,
+			ENDSTR => break,
+			_ => return Err(rec.unexpected(&format!("in structure {}", name))),
+		};
+		let elem = try!(read_elem(rd, kind).map_err(|e| format!("{} (in structure {})", e, name)));
+		elems.extend(elem.outlined());
+	}
+	Ok(StructData {
+		name: name,
+		elems: elems,
+	})
+}
+
+/// Read the records of an element up to and including its ENDEL.
+fn read_elem(rd: &mut RecordReader, kind: ElemKind) -> Result<Elem, String> {
+	let mut elem = Elem::new(kind);
+	loop {
+		let rec = try!(rd.next());
+		match rec.kind {
+			ENDEL => break,
+			LAYER => elem.layer = try!(rec.int2()) as u16,
+			DATATYPE | TEXTTYPE | NODETYPE | BOXTYPE => elem.datatype = try!(rec.int2()) as u16,
+			XY => {
+				let v = try!(rec.int4s());
+				if v.len() % 2 != 0 {
+					return Err(rec.error("odd number of coordinates"));
+				}
+				elem.xy = v.chunks(2).map(|c| Xy { x: c[0], y: c[1] }).collect();
+			},
+			SNAME => elem.sname = try!(rec.string()),
+			STRANS => {
+				elem.strans = Some(Strans {
+					flags: try!(rec.bits()),
+					mag: 1.0,
+					angle: 0.0,
+				});
+			},
+			MAG | ANGLE => {
+				let v = try!(rec.real8());
+				let strans = match elem.strans {
+					Some(ref mut s) => s,
+					None => return Err(rec.unexpected("without STRANS")),
+				};
+				if rec.kind == MAG {
+					strans.mag = v;
+				} else {
+					strans.angle = v;
+				}
+			},
+			COLROW => {
+				let v = try!(rec.int2s());
+				if v.len() != 2 {
+					return Err(rec.error("expected two values"));
+				}
+				elem.colrow = (v[0] as u16, v[1] as u16);
+			},
+			WIDTH => elem.width = try!(rec.int4()),
+			PATHTYPE => elem.pathtype = try!(rec.int2()) as u16,
+			BGNEXTN => elem.extensions.0 = try!(rec.int4()),
+			ENDEXTN => elem.extensions.1 = try!(rec.int4()),
+			STRING => elem.string = try!(rec.string()),
+			PRESENTATION => elem.presentation = try!(rec.bits()),
+			PROPATTR => {
+				let attr = try!(rec.int2()) as u16;
+				let value = try!(try!(rd.next()).expect(PROPVALUE));
+				elem.properties.push((attr.to_string(), try!(value.string())));
+			},
+			ELFLAGS | PLEX | ELKEY | SPACING => (),
+			_ => return Err(rec.unexpected(&format!("in {} element", record_name(kind_record(kind))))),
+		}
+	}
+	Ok(elem)
+}
+
+fn kind_record(kind: ElemKind) -> u8 {
+	match kind {
+		ElemKind::Boundary => BOUNDARY,
+		ElemKind::Path => PATH,
+		ElemKind::Sref => SREF,
+		ElemKind::Aref => AREF,
+		ElemKind::Text => TEXT,
+		ElemKind::Node => NODE,
+		ElemKind::Box => BOX,
+	}
+}
+
+struct RecordReader<'a> {
+	data: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> RecordReader<'a> {
+	/// Read the next record. Running out of records is an error, since a
+	/// stream ends with ENDLIB.
+	fn next(&mut self) -> Result<Record<'a>, String> {
+		let rest = &self.data[self.pos..];
+		if rest.is_empty() {
+			return Err(format!("unexpected end of file at byte {}, expected ENDLIB", self.pos));
+		}
+		if rest.len() < 4 {
+			return Err(format!("truncated record header at byte {}", self.pos));
+		}
+		let len = (rest[0] as usize) << 8 | rest[1] as usize;
+		if len < 4 || len % 2 != 0 {
+			return Err(format!("invalid record length {} at byte {}", len, self.pos));
+		}
+		if len > rest.len() {
+			return Err(format!("truncated {} record at byte {}, {} of {} bytes present", record_name(rest[2]), self.pos, rest.len(), len));
+		}
+		let rec = Record {
+			offset: self.pos,
+			kind: rest[2],
+			datatype: rest[3],
+			data: &rest[4..len],
+		};
+		self.pos += len;
+		Ok(rec)
+	}
+}
+
+struct Record<'a> {
+	offset: usize,
+	kind: u8,
+	datatype: u8,
+	data: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+	fn error(&self, msg: &str) -> String {
+		format!("{} in {} record at byte {}", msg, record_name(self.kind), self.offset)
+	}
+
+	fn unexpected(&self, context: &str) -> String {
+		format!("unexpected {} record {} at byte {}", record_name(self.kind), context, self.offset)
+	}
+
+	fn expect(self, kind: u8) -> Result<Record<'a>, String> {
+		if self.kind == kind {
+			Ok(self)
+		} else {
+			Err(format!("expected {} record at byte {}, found {}", record_name(kind), self.offset, record_name(self.kind)))
+		}
+	}
+
+	/// Check the data type and that the data is a whole number of values of
+	/// the given size.
+	fn values(&self, datatype: u8, size: usize) -> Result<&'a [u8], String> {
+		if self.datatype != datatype {
+			return Err(self.error(&format!("data type {} instead of {}", self.datatype, datatype)));
+		}
+		if self.data.len() % size != 0 {
+			return Err(self.error(&format!("{} bytes of data, not a multiple of {}", self.data.len(), size)));
+		}
+		Ok(self.data)
+	}
+
+	fn bits(&self) -> Result<u16, String> {
+		let d = try!(self.values(DT_BITS, 2));
+		match d.len() {
+			2 => Ok((d[0] as u16) << 8 | d[1] as u16),
+			_ => Err(self.error("expected a single value")),
+		}
+	}
+
+	fn int2s(&self) -> Result<Vec<i16>, String> {
+		let d = try!(self.values(DT_INT2, 2));
+		Ok(d.chunks(2).map(|c| ((c[0] as u16) << 8 | c[1] as u16) as i16).collect())
+	}
+
+	fn int2(&self) -> Result<i16, String> {
+		let v = try!(self.int2s());
+		match v.len() {
+			1 => Ok(v[0]),
+			_ => Err(self.error("expected a single value")),
+		}
+	}
+
+	fn int4s(&self) -> Result<Vec<i32>, String> {
+		let d = try!(self.values(DT_INT4, 4));
+		Ok(d.chunks(4).map(|c| {
+			((c[0] as u32) << 24 | (c[1] as u32) << 16 | (c[2] as u32) << 8 | c[3] as u32) as i32
+		}).collect())
+	}
+
+	fn int4(&self) -> Result<i32, String> {
+		let v = try!(self.int4s());
+		match v.len() {
+			1 => Ok(v[0]),
+			_ => Err(self.error("expected a single value")),
+		}
+	}
+
+	fn real8s(&self) -> Result<Vec<f64>, String> {
+		let d = try!(self.values(DT_REAL8, 8));
+		Ok(d.chunks(8).map(real8).collect())
+	}
+
+	fn real8(&self) -> Result<f64, String> {
+		let v = try!(self.real8s());
+		match v.len() {
+			1 => Ok(v[0]),
+			_ => Err(self.error("expected a single value")),
+		}
+	}
+
+	/// Read an ASCII string, which is padded with a null byte to an even
+	/// length.
+	fn string(&self) -> Result<String, String> {
+		let d = try!(self.values(DT_ASCII, 1));
+		let end = d.iter().position(|&b| b == 0).unwrap_or(d.len());
+		Ok(String::from_utf8_lossy(&d[..end]).into_owned())
+	}
+}
+
+/// Convert an 8 byte real, which has a sign bit, a 7 bit exponent of 16 in
+/// excess 64 notation, and a 56 bit mantissa in [1/16, 1).
+fn real8(b: &[u8]) -> f64 {
+	let mut mantissa = 0u64;
+	for &byte in &b[1..8] {
+		mantissa = mantissa << 8 | byte as u64;
+	}
+	let exponent = (b[0] & 0x7f) as i32 - 64;
+	let v = mantissa as f64 / (1u64 << 56) as f64 * 16f64.powi(exponent);
+	if b[0] & 0x80 != 0 { -v } else { v }
+}
+
+const DT_BITS: u8 = 1;
+const DT_INT2: u8 = 2;
+const DT_INT4: u8 = 3;
+const DT_REAL8: u8 = 5;
+const DT_ASCII: u8 = 6;
+
+const HEADER: u8 = 0x00;
+const BGNLIB: u8 = 0x01;
+const LIBNAME: u8 = 0x02;
+const UNITS: u8 = 0x03;
+const ENDLIB: u8 = 0x04;
+const BGNSTR: u8 = 0x05;
+const STRNAME: u8 = 0x06;
+const ENDSTR: u8 = 0x07;
+const BOUNDARY: u8 = 0x08;
+const PATH: u8 = 0x09;
+const SREF: u8 = 0x0a;
+const AREF: u8 = 0x0b;
+const TEXT: u8 = 0x0c;
+const LAYER: u8 = 0x0d;
+const DATATYPE: u8 = 0x0e;
+const WIDTH: u8 = 0x0f;
+const XY: u8 = 0x10;
+const ENDEL: u8 = 0x11;
+const SNAME: u8 = 0x12;
+const COLROW: u8 = 0x13;
+const NODE: u8 = 0x15;
+const TEXTTYPE: u8 = 0x16;
+const PRESENTATION: u8 = 0x17;
+const SPACING: u8 = 0x18;
+const STRING: u8 = 0x19;
+const STRANS: u8 = 0x1a;
+const MAG: u8 = 0x1b;
+const ANGLE: u8 = 0x1c;
+const REFLIBS: u8 = 0x1f;
+const FONTS: u8 = 0x20;
+const PATHTYPE: u8 = 0x21;
+const GENERATIONS: u8 = 0x22;
+const ATTRTABLE: u8 = 0x23;
+const ELFLAGS: u8 = 0x26;
+const ELKEY: u8 = 0x27;
+const NODETYPE: u8 = 0x2a;
+const PROPATTR: u8 = 0x2b;
+const PROPVALUE: u8 = 0x2c;
+const BOX: u8 = 0x2d;
+const BOXTYPE: u8 = 0x2e;
+const PLEX: u8 = 0x2f;
+const BGNEXTN: u8 = 0x30;
+const ENDEXTN: u8 = 0x31;
+const STRCLASS: u8 = 0x34;
+const FORMAT: u8 = 0x36;
+const MASK: u8 = 0x37;
+const ENDMASKS: u8 = 0x38;
+const LIBDIRSIZE: u8 = 0x39;
+const SRFNAME: u8 = 0x3a;
+const LIBSECUR: u8 = 0x3b;
+
+/// The name of a record type, for error messages.
+fn record_name(kind: u8) -> String {
+	const NAMES: [&'static str; 60] = [
+		"HEADER", "BGNLIB", "LIBNAME", "UNITS", "ENDLIB", "BGNSTR", "STRNAME", "ENDSTR",
+		"BOUNDARY", "PATH", "SREF", "AREF", "TEXT", "LAYER", "DATATYPE", "WIDTH",
+		"XY", "ENDEL", "SNAME", "COLROW", "TEXTNODE", "NODE", "TEXTTYPE", "PRESENTATION",
+		"SPACING", "STRING", "STRANS", "MAG", "ANGLE", "UINTEGER", "USTRING", "REFLIBS",
+		"FONTS", "PATHTYPE", "GENERATIONS", "ATTRTABLE", "STYPTABLE", "STRTYPE", "ELFLAGS", "ELKEY",
+		"LINKTYPE", "LINKKEYS", "NODETYPE", "PROPATTR", "PROPVALUE", "BOX", "BOXTYPE", "PLEX",
+		"BGNEXTN", "ENDEXTN", "TAPENUM", "TAPECODE", "STRCLASS", "RESERVED", "FORMAT", "MASK",
+		"ENDMASKS", "LIBDIRSIZE", "SRFNAME", "LIBSECUR",
+	];
+	match NAMES.get(kind as usize) {
+		Some(n) => String::from(*n),
+		None => format!("unknown record type 0x{:02x}", kind),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn record(out: &mut Vec<u8>, kind: u8, datatype: u8, data: &[u8]) {
+		let len = data.len() + 4;
+		out.extend_from_slice(&[(len >> 8) as u8, len as u8, kind, datatype]);
+		out.extend_from_slice(data);
+	}
+
+	fn int2(out: &mut Vec<u8>, kind: u8, values: &[i16]) {
+		let data: Vec<u8> = values.iter().flat_map(|&v| vec![(v >> 8) as u8, v as u8]).collect();
+		record(out, kind, DT_INT2, &data);
+	}
+
+	fn int4(out: &mut Vec<u8>, kind: u8, values: &[i32]) {
+		let data: Vec<u8> = values.iter().flat_map(|&v| vec![(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]).collect();
+		record(out, kind, DT_INT4, &data);
+	}
+
+	fn ascii(out: &mut Vec<u8>, kind: u8, s: &str) {
+		let mut data = s.as_bytes().to_vec();
+		if data.len() % 2 != 0 {
+			data.push(0);
+		}
+		record(out, kind, DT_ASCII, &data);
+	}
+
+	/// 1.0 and 1e-3 as 8 byte reals.
+	const ONE: [u8; 8] = [0x41, 0x10, 0, 0, 0, 0, 0, 0];
+	const MILLI: [u8; 8] = [0x3e, 0x41, 0x89, 0x37, 0x4b, 0xc6, 0xa7, 0xef];
+
+	/// A stream with a single structure `TOP` holding the given element
+	/// records.
+	fn stream(elems: &[u8]) -> Vec<u8> {
+		let mut s = Vec::new();
+		int2(&mut s, HEADER, &[600]);
+		int2(&mut s, BGNLIB, &[0; 12]);
+		ascii(&mut s, LIBNAME, "LIB");
+		record(&mut s, UNITS, DT_REAL8, &[MILLI, [0x39, 0x44, 0xb8, 0x2f, 0xa0, 0x9b, 0x5a, 0x53]].concat());
+		int2(&mut s, BGNSTR, &[0; 12]);
+		ascii(&mut s, STRNAME, "TOP");
+		s.extend_from_slice(elems);
+		record(&mut s, ENDSTR, 0, &[]);
+		record(&mut s, ENDLIB, 0, &[]);
+		s
+	}
+
+	fn boundary() -> Vec<u8> {
+		let mut e = Vec::new();
+		record(&mut e, BOUNDARY, 0, &[]);
+		int2(&mut e, LAYER, &[5]);
+		int2(&mut e, DATATYPE, &[2]);
+		int4(&mut e, XY, &[0, 0, 10, 0, 10, 10, 0, 10, 0, 0]);
+		record(&mut e, ENDEL, 0, &[]);
+		e
+	}
+
+	fn read_error(data: &[u8]) -> String {
+		match Library::read(data) {
+			Ok(_) => panic!("stream accepted"),
+			Err(e) => e,
+		}
+	}
+
+	fn xy(elem: &Elem) -> Vec<(i32, i32)> {
+		elem.get_xy().iter().map(|p| (p.x, p.y)).collect()
+	}
+
+	#[test]
+	fn real8_values() {
+		assert_eq!(real8(&ONE), 1.0);
+		assert_eq!(real8(&[0xc1, 0x10, 0, 0, 0, 0, 0, 0]), -1.0);
+		assert_eq!(real8(&[0x42, 0x19, 0, 0, 0, 0, 0, 0]), 25.0);
+		assert_eq!(real8(&[0; 8]), 0.0);
+		assert!((real8(&MILLI) - 1e-3).abs() < 1e-18);
+	}
+
+	#[test]
+	fn read_elements() {
+		let mut elems = boundary();
+		record(&mut elems, SREF, 0, &[]);
+		ascii(&mut elems, SNAME, "SUB");
+		record(&mut elems, STRANS, DT_BITS, &[0x80, 0]);
+		record(&mut elems, MAG, DT_REAL8, &ONE);
+		record(&mut elems, ANGLE, DT_REAL8, &[0x42, 0x5a, 0, 0, 0, 0, 0, 0]);
+		int4(&mut elems, XY, &[100, 200]);
+		record(&mut elems, ENDEL, 0, &[]);
+		record(&mut elems, AREF, 0, &[]);
+		ascii(&mut elems, SNAME, "SUB");
+		int2(&mut elems, COLROW, &[3, 2]);
+		int4(&mut elems, XY, &[0, 0, 30, 0, 0, 20]);
+		record(&mut elems, ENDEL, 0, &[]);
+
+		let lib = Library::read(&stream(&elems)).unwrap();
+		assert_eq!(lib.get_name(), "LIB");
+		assert!((lib.get_units_in_m() - 1e-9).abs() < 1e-24);
+		let top = lib.find_struct("TOP").unwrap();
+		let elems: Vec<&Elem> = top.elems().collect();
+		assert_eq!(elems.len(), 3);
+
+		assert_eq!(elems[0].get_kind(), ElemKind::Boundary);
+		assert_eq!((elems[0].get_layer(), elems[0].get_type()), (5, 2));
+		assert_eq!(xy(elems[0]), vec![(0, 0), (10, 0), (10, 10), (0, 10), (0, 0)]);
+
+		assert_eq!(elems[1].get_kind(), ElemKind::Sref);
+		assert_eq!(elems[1].get_sname(), "SUB");
+		let strans = elems[1].get_strans().unwrap();
+		assert_eq!((strans.flags, strans.mag, strans.angle), (0x8000, 1.0, 90.0));
+		assert_eq!(xy(elems[1]), vec![(100, 200)]);
+
+		assert_eq!(elems[2].get_kind(), ElemKind::Aref);
+		assert_eq!(elems[2].get_colrow(), (3, 2));
+	}
+
+	#[test]
+	fn paths_and_boxes_become_boundaries() {
+		let mut elems = Vec::new();
+		record(&mut elems, PATH, 0, &[]);
+		int2(&mut elems, LAYER, &[1]);
+		int2(&mut elems, DATATYPE, &[0]);
+		int2(&mut elems, PATHTYPE, &[2]);
+		int4(&mut elems, WIDTH, &[4]);
+		int4(&mut elems, XY, &[0, 0, 10, 0, 10, 10]);
+		record(&mut elems, ENDEL, 0, &[]);
+		record(&mut elems, BOX, 0, &[]);
+		int2(&mut elems, LAYER, &[2]);
+		int2(&mut elems, BOXTYPE, &[0]);
+		int4(&mut elems, XY, &[0, 0, 5, 0, 5, 5, 0, 5, 0, 0]);
+		record(&mut elems, ENDEL, 0, &[]);
+		// A path of a single point covers nothing and is dropped.
+		record(&mut elems, PATH, 0, &[]);
+		int2(&mut elems, LAYER, &[1]);
+		int2(&mut elems, DATATYPE, &[0]);
+		int4(&mut elems, XY, &[0, 0]);
+		record(&mut elems, ENDEL, 0, &[]);
+
+		let lib = Library::read(&stream(&elems)).unwrap();
+		let top = lib.find_struct("TOP").unwrap();
+		let elems: Vec<&Elem> = top.elems().collect();
+		assert_eq!(elems.len(), 2);
+		assert_eq!(elems[0].get_kind(), ElemKind::Boundary);
+		assert_eq!(xy(elems[0]), vec![(-2, 2), (8, 2), (8, 12), (12, 12), (12, -2), (-2, -2), (-2, 2)]);
+		assert_eq!(elems[1].get_kind(), ElemKind::Boundary);
+		assert_eq!(elems[1].get_layer(), 2);
+	}
+
+	#[test]
+	fn path_outline_ends() {
+		let points = [Xy { x: 0, y: 0 }, Xy { x: 10, y: 0 }];
+		let flush: Vec<(i32, i32)> = path_outline(&points, 1.0, (0.0, 0.0)).iter().map(|p| (p.x, p.y)).collect();
+		assert_eq!(flush, vec![(0, 1), (10, 1), (10, -1), (0, -1), (0, 1)]);
+		let extended: Vec<(i32, i32)> = path_outline(&points, 1.0, (2.0, 3.0)).iter().map(|p| (p.x, p.y)).collect();
+		assert_eq!(extended, vec![(-2, 1), (13, 1), (13, -1), (-2, -1), (-2, 1)]);
+		assert!(path_outline(&points, 0.0, (0.0, 0.0)).is_empty());
+	}
+
+	#[test]
+	fn truncated_streams() {
+		// Every prefix of a valid stream fails with an error rather than a
+		// panic.
+		let data = stream(&boundary());
+		for len in 0..data.len() {
+			assert!(Library::read(&data[..len]).is_err(), "prefix of {} bytes accepted", len);
+		}
+		let e = read_error(&data[..data.len() - 4]);
+		assert!(e.contains("expected ENDLIB"), "{}", e);
+		let e = read_error(&data[..data.len() - 6]);
+		assert!(e.contains("truncated"), "{}", e);
+	}
+
+	#[test]
+	fn corrupt_records() {
+		// An odd record length.
+		let mut data = stream(&boundary());
+		data[1] = 5;
+		let e = read_error(&data);
+		assert_eq!(e, "invalid record length 5 at byte 0");
+
+		// A layer stored with the wrong data type.
+		let mut elems = Vec::new();
+		record(&mut elems, BOUNDARY, 0, &[]);
+		int4(&mut elems, LAYER, &[1]);
+		let e = read_error(&stream(&elems));
+		assert!(e.starts_with("data type 3 instead of 2 in LAYER record at byte"), "{}", e);
+
+		// A magnification without STRANS.
+		let mut elems = Vec::new();
+		record(&mut elems, SREF, 0, &[]);
+		record(&mut elems, MAG, DT_REAL8, &ONE);
+		let e = read_error(&stream(&elems));
+		assert!(e.contains("unexpected MAG record without STRANS"), "{}", e);
+		assert!(e.ends_with("(in structure TOP)"), "{}", e);
+
+		// An odd number of coordinates.
+		let mut elems = Vec::new();
+		record(&mut elems, BOUNDARY, 0, &[]);
+		int4(&mut elems, XY, &[1, 2, 3]);
+		let e = read_error(&stream(&elems));
+		assert!(e.contains("odd number of coordinates"), "{}", e);
+	}
+}
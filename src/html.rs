@@ -0,0 +1,271 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Standalone interactive HTML output.
+//!
+//! The geometry is embedded as inline SVG in micrometers, with the y axis
+//! flipped. Unlike the plain SVG output the hierarchy is flattened, such that
+//! every shape can carry the layer, datatype, and cell it came from for the
+//! hover tooltip. The page needs no external resources.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use {Context, Overlay, Struct, Extents, Transform, Point, Rect};
+
+pub fn write_html(ctx: &Context, overlays: &[Overlay], output_name: &str) -> io::Result<()> {
+	let file = try!(File::create(output_name));
+	let mut w = BufWriter::new(file);
+
+	// Determine the visible area in micrometers.
+	let mut extents = Extents::new();
+	match ctx.window {
+		Some(win) => {
+			extents.add_point(&win.min);
+			extents.add_point(&win.max);
+		},
+		None => for o in overlays {
+			extents.add_extents(&o.strukt.extents, o.offset);
+		},
+	}
+	let r = extents.rect;
+	let margin = 0.02 * (r.max.x - r.min.x).max(r.max.y - r.min.y);
+	let visible = Rect {
+		min: Point { x: r.min.x - margin, y: r.min.y - margin },
+		max: Point { x: r.max.x + margin, y: r.max.y + margin },
+	};
+	let view = (
+		(r.min.x - margin) * 1e6,
+		-(r.max.y + margin) * 1e6,
+		(r.max.x - r.min.x + 2.0 * margin) * 1e6,
+		(r.max.y - r.min.y + 2.0 * margin) * 1e6,
+	);
+
+	// Name the layers after their aliases where possible.
+	let mut names = HashMap::<u16, &str>::new();
+	for (alias, &id) in &ctx.aliases {
+		let better = match names.get(&id) {
+			Some(n) => **alias < **n,
+			None => true,
+		};
+		if better {
+			names.insert(id, &**alias);
+		}
+	}
+
+	let title = match overlays.first() {
+		Some(o) => escape(&o.strukt.name),
+		None => String::new(),
+	};
+	let background = match ctx.bg_color {
+		Some(c) => c.to_hex(),
+		None => String::from("#ffffff"),
+	};
+	try!(write!(w, "{}", HEADER.replace("{{TITLE}}", &title).replace("{{BACKGROUND}}", &background)));
+
+	// The layer panel.
+	let mut listed = Vec::new();
+	try!(writeln!(w, "<div id=\"layers\"><b>{}</b>", title));
+	for o in overlays {
+		for layer in &o.strukt.layers {
+			if listed.contains(&layer.id) {
+				continue;
+			}
+			listed.push(layer.id);
+			let swatch = match (layer.style.get_fill_style(), layer.style.get_stroke_style()) {
+				(Some(fs), _) => fs.color.to_hex(),
+				(None, Some(ss)) => ss.color.to_hex(),
+				(None, None) => String::from("transparent"),
+			};
+			let name = match names.get(&layer.id) {
+				Some(n) => format!("{} ({})", escape(n), layer.id),
+				None => format!("{}", layer.id),
+			};
+			try!(writeln!(w,
+				"<label><input type=\"checkbox\" data-layer=\"{}\" checked><span class=\"swatch\" style=\"background:{}\"></span>{}</label>",
+				layer.id, swatch, name
+			));
+		}
+	}
+	try!(writeln!(w, "</div>"));
+
+	// The geometry, one group per layer and overlay.
+	let mut cells = Vec::<String>::new();
+	let mut cell_ids = HashMap::<*const Struct, usize>::new();
+	try!(writeln!(w,
+		"<svg id=\"plot\" xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\" fill-rule=\"evenodd\">",
+		view.0, view.1, view.2, view.3
+	));
+	for o in overlays {
+		let mut tx = Transform::identity();
+		tx.trans(o.offset.x, o.offset.y);
+		for layer in &o.strukt.layers {
+			let mut attrs = String::new();
+			match layer.style.get_fill_style() {
+				Some(fs) => attrs.push_str(&format!(" fill=\"{}\" fill-opacity=\"{}\"", fs.color.to_hex(), fs.alpha)),
+				None => attrs.push_str(" fill=\"none\""),
+			}
+			if let Some(ss) = layer.style.get_stroke_style() {
+				attrs.push_str(&format!(" stroke=\"{}\" stroke-opacity=\"{}\" stroke-width=\"{}\"", ss.color.to_hex(), ss.alpha, ss.width));
+				if let Some(ref d) = ss.dashes {
+					attrs.push_str(&format!(" stroke-dasharray=\"{}\"", d.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")));
+				}
+			}
+			try!(writeln!(w, "<g data-layer=\"{}\"{}>", layer.id, attrs));
+			try!(write_flat(&mut w, o.strukt, layer.key, tx, &visible, &mut cells, &mut cell_ids));
+			try!(writeln!(w, "</g>"));
+		}
+	}
+	try!(writeln!(w, "</svg>"));
+
+	let cells: Vec<String> = cells.iter().map(|c| format!("\"{}\"", c.replace('\\', "\\\\").replace('"', "\\\"").replace('<', "\\u003c"))).collect();
+	try!(write!(w, "{}", FOOTER.replace("{{CELLS}}", &format!("[{}]", cells.join(",")))));
+	Ok(())
+}
+
+/// Write the shapes on a layer of a struct and all its instances which
+/// intersect the visible area, transformed into the top-level coordinates.
+fn write_flat<W: Write>(w: &mut W, strukt: &Struct, layer_key: u16, tx: Transform, visible: &Rect, cells: &mut Vec<String>, cell_ids: &mut HashMap<*const Struct, usize>) -> io::Result<()> {
+	let key = strukt as *const Struct;
+	let cell = match cell_ids.get(&key) {
+		Some(&id) => id,
+		None => {
+			cells.push(strukt.name.to_string());
+			cell_ids.insert(key, cells.len() - 1);
+			cells.len() - 1
+		}
+	};
+
+	// Find the shapes and instances in view through the spatial indices, in
+	// the struct's own coordinates.
+	let window = visible.transformed(tx.inverse());
+	let mut shapes = Vec::new();
+	if let Some(index) = strukt.shapes.get(&layer_key) {
+		index.query(&window, |_, &i| shapes.push(i));
+	}
+	shapes.sort();
+	let mut instances = Vec::new();
+	strukt.placements.query(&window, |_, &i| instances.push(i));
+	instances.sort();
+
+	for i in shapes {
+		let b = &strukt.boundaries[i];
+		let mut d = String::new();
+		path_data(&mut d, &b.points, tx);
+		for hole in &b.holes {
+			path_data(&mut d, hole, tx);
+		}
+		try!(writeln!(w, "<path d=\"{}\" data-dt=\"{}\" data-cell=\"{}\"/>", d, b.datatype, cell));
+	}
+	for i in instances {
+		let inst = &strukt.instances[i];
+		if inst.strukt.has_layer(layer_key) {
+			try!(write_flat(w, &inst.strukt, layer_key, tx * inst.tx, visible, cells, cell_ids));
+		}
+	}
+	Ok(())
+}
+
+/// Append a closed ring of points, where the last point repeats the first, to
+/// path data in micrometers with the y axis pointing down.
+fn path_data(d: &mut String, points: &[Point], tx: Transform) {
+	for (i, p) in points.iter().skip(1).enumerate() {
+		let p = tx * *p;
+		d.push_str(&format!("{}{:.4} {:.4}", if i == 0 { "M" } else { "L" }, p.x * 1e6, -p.y * 1e6));
+	}
+	d.push('Z');
+}
+
+pub fn escape(s: &str) -> String {
+	s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+const HEADER: &'static str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{TITLE}}</title>
+<style>
+html, body { margin: 0; height: 100%; overflow: hidden; font: 12px sans-serif; }
+#plot { position: absolute; left: 0; top: 0; width: 100%; height: 100%; background: {{BACKGROUND}}; cursor: crosshair; }
+#plot path { vector-effect: non-scaling-stroke; }
+#plot path:hover { stroke: #000; stroke-width: 2; stroke-opacity: 1; }
+#layers { position: absolute; z-index: 1; right: 8px; top: 8px; max-height: 80%; overflow: auto; padding: 6px 8px; background: rgba(255,255,255,0.9); border: 1px solid #aaa; }
+#layers label { display: block; white-space: nowrap; }
+.swatch { display: inline-block; width: 10px; height: 10px; margin: 0 4px; border: 1px solid #666; }
+#coords { position: absolute; z-index: 1; left: 8px; bottom: 8px; padding: 2px 6px; background: rgba(255,255,255,0.9); border: 1px solid #aaa; }
+#tip { position: absolute; z-index: 2; display: none; padding: 2px 6px; background: #ffd; border: 1px solid #aa8; pointer-events: none; }
+</style>
+</head>
+<body>
+"##;
+
+const FOOTER: &'static str = r##"<div id="coords"></div>
+<div id="tip"></div>
+<script>
+var cells = {{CELLS}};
+var svg = document.getElementById("plot");
+var coords = document.getElementById("coords");
+var tip = document.getElementById("tip");
+var vb = svg.viewBox.baseVal;
+
+// Convert a mouse position to layout coordinates in micrometers.
+function layout(e) {
+	var p = svg.createSVGPoint();
+	p.x = e.clientX;
+	p.y = e.clientY;
+	return p.matrixTransform(svg.getScreenCTM().inverse());
+}
+
+svg.addEventListener("wheel", function(e) {
+	e.preventDefault();
+	var p = layout(e);
+	var k = Math.pow(2, e.deltaY / 300);
+	vb.x = p.x - (p.x - vb.x) * k;
+	vb.y = p.y - (p.y - vb.y) * k;
+	vb.width *= k;
+	vb.height *= k;
+}, { passive: false });
+
+var drag = null;
+svg.addEventListener("mousedown", function(e) {
+	drag = { x: e.clientX, y: e.clientY };
+});
+window.addEventListener("mouseup", function() { drag = null; });
+window.addEventListener("mousemove", function(e) {
+	if (drag) {
+		var s = Math.max(vb.width / svg.clientWidth, vb.height / svg.clientHeight);
+		vb.x -= (e.clientX - drag.x) * s;
+		vb.y -= (e.clientY - drag.y) * s;
+		drag = { x: e.clientX, y: e.clientY };
+	}
+});
+
+svg.addEventListener("mousemove", function(e) {
+	var p = layout(e);
+	coords.textContent = p.x.toFixed(3) + ", " + (-p.y).toFixed(3) + " µm";
+	var t = e.target;
+	if (t.tagName == "path") {
+		tip.textContent = "layer " + t.parentNode.getAttribute("data-layer") + "/" + t.getAttribute("data-dt") +
+			" in " + cells[+t.getAttribute("data-cell")];
+		tip.style.left = (e.clientX + 12) + "px";
+		tip.style.top = (e.clientY + 12) + "px";
+		tip.style.display = "block";
+	} else {
+		tip.style.display = "none";
+	}
+});
+svg.addEventListener("mouseleave", function() { tip.style.display = "none"; });
+
+var boxes = document.querySelectorAll("#layers input");
+for (var i = 0; i < boxes.length; i++) {
+	boxes[i].addEventListener("change", function() {
+		var groups = svg.querySelectorAll("g[data-layer=\"" + this.getAttribute("data-layer") + "\"]");
+		for (var j = 0; j < groups.length; j++) {
+			groups[j].style.display = this.checked ? "" : "none";
+		}
+	});
+}
+</script>
+</body>
+</html>
+"##;
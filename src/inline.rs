@@ -0,0 +1,154 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Inline image output for terminals.
+//!
+//! The plot is rendered in memory and written to stdout using either the
+//! Kitty graphics protocol, which accepts a PNG image as is, or as Sixel
+//! graphics, for which the pixels of the plot are reduced to a palette of 216
+//! colors.
+
+use std::env;
+use std::io::{self, BufWriter, Write};
+use {Context, Image, Overlay, plot_png, plot_rgba};
+
+/// The terminal graphics protocols.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+	Sixel,
+	Kitty,
+}
+
+/// Guess the graphics protocol supported by the terminal from the environment.
+/// Terminals known to implement the Kitty protocol use it, all others Sixel.
+pub fn detect() -> Protocol {
+	let var = |name: &str| env::var(name).unwrap_or_default().to_lowercase();
+	let term = var("TERM");
+	let program = var("TERM_PROGRAM");
+	if env::var("KITTY_WINDOW_ID").is_ok() || term.contains("kitty") || term.contains("ghostty") ||
+		program == "wezterm" || program == "ghostty" {
+		Protocol::Kitty
+	} else {
+		Protocol::Sixel
+	}
+}
+
+pub fn write_inline(ctx: &Context, overlays: &[Overlay], protocol: Protocol) -> io::Result<()> {
+	let stdout = io::stdout();
+	let mut w = BufWriter::new(stdout.lock());
+	match protocol {
+		Protocol::Kitty => try!(write_kitty(&mut w, &try!(plot_png(ctx, overlays)))),
+		Protocol::Sixel => try!(write_sixel(&mut w, &try!(plot_rgba(ctx, overlays)))),
+	}
+	try!(writeln!(w));
+	Ok(())
+}
+
+/// Transmit and display a PNG image, split into chunks of at most 4096 bytes
+/// of base64 as required by the protocol.
+fn write_kitty<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+	let encoded = base64(data);
+	let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(4096).collect();
+	for (i, chunk) in chunks.iter().enumerate() {
+		let more = if i + 1 < chunks.len() { 1 } else { 0 };
+		if i == 0 {
+			try!(write!(w, "\x1b_Ga=T,f=100,m={};", more));
+		} else {
+			try!(write!(w, "\x1b_Gm={};", more));
+		}
+		try!(w.write_all(chunk));
+		try!(write!(w, "\x1b\\"));
+	}
+	Ok(())
+}
+
+/// Write an image as Sixel graphics. Colors are mapped to a 6x6x6 color cube;
+/// pixels which are mostly transparent are left unpainted.
+fn write_sixel<W: Write>(w: &mut W, image: &Image) -> io::Result<()> {
+	let level = |v: u8| (v as usize * 5 + 127) / 255;
+	let colors: Vec<Option<usize>> = image.rgba.chunks(4).map(|px| {
+		if px[3] < 128 {
+			None
+		} else {
+			Some(level(px[0]) * 36 + level(px[1]) * 6 + level(px[2]))
+		}
+	}).collect();
+
+	try!(write!(w, "\x1bP0;1;0q\"1;1;{};{}", image.width, image.height));
+	for i in 0..216 {
+		try!(write!(w, "#{};2;{};{};{}", i, i / 36 * 20, i / 6 % 6 * 20, i % 6 * 20));
+	}
+
+	// Each band covers six rows of pixels, painted one color at a time.
+	let mut sixels = vec![0u8; image.width];
+	for y0 in (0..image.height).step_by(6) {
+		let rows = (image.height - y0).min(6);
+		let mut used = [false; 216];
+		for c in &colors[y0 * image.width..(y0 + rows) * image.width] {
+			if let Some(c) = *c {
+				used[c] = true;
+			}
+		}
+		let mut first = true;
+		for color in (0..216).filter(|&c| used[c]) {
+			for x in 0..image.width {
+				let mut bits = 0;
+				for dy in 0..rows {
+					if colors[(y0 + dy) * image.width + x] == Some(color) {
+						bits |= 1 << dy;
+					}
+				}
+				sixels[x] = bits;
+			}
+			if !first {
+				try!(write!(w, "$"));
+			}
+			first = false;
+			try!(write!(w, "#{}", color));
+			try!(write_sixel_run(w, &sixels));
+		}
+		try!(write!(w, "-"));
+	}
+	try!(write!(w, "\x1b\\"));
+	Ok(())
+}
+
+/// Write a row of sixels, compressing runs of repeated values.
+fn write_sixel_run<W: Write>(w: &mut W, sixels: &[u8]) -> io::Result<()> {
+	// Trailing empty sixels need not be written.
+	let end = sixels.iter().rposition(|&s| s != 0).map(|i| i + 1).unwrap_or(0);
+	let mut i = 0;
+	while i < end {
+		let mut n = 1;
+		while i + n < end && sixels[i + n] == sixels[i] {
+			n += 1;
+		}
+		let c = (sixels[i] + 63) as char;
+		if n > 3 {
+			try!(write!(w, "!{}{}", n, c));
+		} else {
+			for _ in 0..n {
+				try!(write!(w, "{}", c));
+			}
+		}
+		i += n;
+	}
+	Ok(())
+}
+
+fn base64(data: &[u8]) -> String {
+	const ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+	let mut s = String::with_capacity((data.len() + 2) / 3 * 4);
+	for chunk in data.chunks(3) {
+		let n = (chunk[0] as u32) << 16
+			| (*chunk.get(1).unwrap_or(&0) as u32) << 8
+			| *chunk.get(2).unwrap_or(&0) as u32;
+		for i in 0..4 {
+			if i <= chunk.len() {
+				s.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+			} else {
+				s.push('=');
+			}
+		}
+	}
+	s
+}
@@ -0,0 +1,259 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! A small JSON reader for input files such as clip lists.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Null,
+	Bool(bool),
+	Number(f64),
+	String(String),
+	Array(Vec<Value>),
+	/// The members of an object, in the order they appear in the input.
+	Object(Vec<(String, Value)>),
+}
+
+impl Value {
+	/// Look up a member of an object.
+	pub fn get(&self, key: &str) -> Option<&Value> {
+		match *self {
+			Value::Object(ref members) => members.iter().find(|m| m.0 == key).map(|m| &m.1),
+			_ => None,
+		}
+	}
+
+	pub fn as_f64(&self) -> Option<f64> {
+		match *self {
+			Value::Number(v) => Some(v),
+			_ => None,
+		}
+	}
+
+	pub fn as_str(&self) -> Option<&str> {
+		match *self {
+			Value::String(ref s) => Some(s),
+			_ => None,
+		}
+	}
+
+	pub fn as_array(&self) -> Option<&[Value]> {
+		match *self {
+			Value::Array(ref a) => Some(a),
+			_ => None,
+		}
+	}
+}
+
+/// Parse a JSON document.
+pub fn parse(s: &str) -> Result<Value, String> {
+	let mut p = Parser { s: s.as_bytes(), pos: 0 };
+	let v = try!(p.value());
+	p.skip_whitespace();
+	if p.pos < p.s.len() {
+		return Err(p.error("trailing characters"));
+	}
+	Ok(v)
+}
+
+struct Parser<'a> {
+	s: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Parser<'a> {
+	fn error(&self, msg: &str) -> String {
+		let line = self.s[..self.pos.min(self.s.len())].iter().filter(|&&c| c == b'\n').count() + 1;
+		format!("{} on line {}", msg, line)
+	}
+
+	fn skip_whitespace(&mut self) {
+		while self.pos < self.s.len() && (self.s[self.pos] as char).is_whitespace() {
+			self.pos += 1;
+		}
+	}
+
+	fn peek(&mut self) -> Option<u8> {
+		self.skip_whitespace();
+		self.s.get(self.pos).cloned()
+	}
+
+	fn expect(&mut self, c: u8) -> Result<(), String> {
+		if self.peek() == Some(c) {
+			self.pos += 1;
+			Ok(())
+		} else {
+			Err(self.error(&format!("expected `{}`", c as char)))
+		}
+	}
+
+	fn keyword(&mut self, word: &str, v: Value) -> Result<Value, String> {
+		if self.s[self.pos..].starts_with(word.as_bytes()) {
+			self.pos += word.len();
+			Ok(v)
+		} else {
+			Err(self.error("invalid value"))
+		}
+	}
+
+	fn value(&mut self) -> Result<Value, String> {
+		match self.peek() {
+			Some(b'{') => {
+				self.pos += 1;
+				let mut members = Vec::new();
+				if self.peek() == Some(b'}') {
+					self.pos += 1;
+					return Ok(Value::Object(members));
+				}
+				loop {
+					if self.peek() != Some(b'"') {
+						return Err(self.error("expected member name"));
+					}
+					let key = try!(self.string());
+					try!(self.expect(b':'));
+					members.push((key, try!(self.value())));
+					match self.peek() {
+						Some(b',') => self.pos += 1,
+						Some(b'}') => { self.pos += 1; return Ok(Value::Object(members)); },
+						_ => return Err(self.error("expected `,` or `}`")),
+					}
+				}
+			},
+			Some(b'[') => {
+				self.pos += 1;
+				let mut items = Vec::new();
+				if self.peek() == Some(b']') {
+					self.pos += 1;
+					return Ok(Value::Array(items));
+				}
+				loop {
+					items.push(try!(self.value()));
+					match self.peek() {
+						Some(b',') => self.pos += 1,
+						Some(b']') => { self.pos += 1; return Ok(Value::Array(items)); },
+						_ => return Err(self.error("expected `,` or `]`")),
+					}
+				}
+			},
+			Some(b'"') => self.string().map(Value::String),
+			Some(b't') => self.keyword("true", Value::Bool(true)),
+			Some(b'f') => self.keyword("false", Value::Bool(false)),
+			Some(b'n') => self.keyword("null", Value::Null),
+			Some(c) if c == b'-' || (c as char).is_digit(10) => {
+				let start = self.pos;
+				while self.pos < self.s.len() && b"+-.eE0123456789".contains(&self.s[self.pos]) {
+					self.pos += 1;
+				}
+				let text = String::from_utf8_lossy(&self.s[start..self.pos]).into_owned();
+				match text.parse() {
+					Ok(v) => Ok(Value::Number(v)),
+					Err(_) => Err(self.error(&format!("invalid number `{}`", text))),
+				}
+			},
+			Some(_) => Err(self.error("invalid value")),
+			None => Err(self.error("unexpected end of input")),
+		}
+	}
+
+	fn string(&mut self) -> Result<String, String> {
+		self.pos += 1;
+		let mut bytes = Vec::new();
+		loop {
+			let c = match self.s.get(self.pos) {
+				Some(&c) => c,
+				None => return Err(self.error("unterminated string")),
+			};
+			self.pos += 1;
+			match c {
+				b'"' => break,
+				b'\\' => {
+					let e = match self.s.get(self.pos) {
+						Some(&e) => e,
+						None => return Err(self.error("unterminated string")),
+					};
+					self.pos += 1;
+					let ch = match e {
+						b'n' => '\n',
+						b't' => '\t',
+						b'r' => '\r',
+						b'b' => '\u{8}',
+						b'f' => '\u{c}',
+						b'u' => {
+							let hex = String::from_utf8_lossy(&self.s[self.pos..(self.pos + 4).min(self.s.len())]).into_owned();
+							self.pos += 4;
+							match u32::from_str_radix(&hex, 16) {
+								Ok(v) => ::std::char::from_u32(v).unwrap_or('\u{fffd}'),
+								Err(_) => return Err(self.error("invalid unicode escape")),
+							}
+						},
+						e => e as char,
+					};
+					let mut buf = [0u8; 4];
+					bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+				},
+				c => bytes.push(c),
+			}
+		}
+		String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8 in string"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn values() {
+		assert_eq!(parse(" null "), Ok(Value::Null));
+		assert_eq!(parse("true"), Ok(Value::Bool(true)));
+		assert_eq!(parse("false"), Ok(Value::Bool(false)));
+		assert_eq!(parse("-1.5e3"), Ok(Value::Number(-1500.0)));
+		assert_eq!(parse("0"), Ok(Value::Number(0.0)));
+		assert_eq!(parse("[]"), Ok(Value::Array(vec![])));
+		assert_eq!(parse("{}"), Ok(Value::Object(vec![])));
+	}
+
+	#[test]
+	fn nested_documents() {
+		let doc = parse("{\n\t\"cell\": \"TOP\",\n\t\"clips\": [[0, 0, 10.5, 20], {\"x\": -3}],\n\t\"cell\": null\n}").unwrap();
+		assert_eq!(doc.get("cell").and_then(Value::as_str), Some("TOP"));
+		assert_eq!(doc.get("missing"), None);
+		let clips = doc.get("clips").and_then(Value::as_array).unwrap();
+		assert_eq!(clips.len(), 2);
+		let first: Vec<f64> = clips[0].as_array().unwrap().iter().filter_map(Value::as_f64).collect();
+		assert_eq!(first, vec![0.0, 0.0, 10.5, 20.0]);
+		assert_eq!(clips[1].get("x").and_then(Value::as_f64), Some(-3.0));
+		assert_eq!(clips[0].get("x"), None);
+
+		// Members keep their order, and the first of duplicates is found.
+		match doc {
+			Value::Object(ref members) => {
+				let keys: Vec<&str> = members.iter().map(|m| &m.0[..]).collect();
+				assert_eq!(keys, vec!["cell", "clips", "cell"]);
+			},
+			_ => panic!("not an object"),
+		}
+	}
+
+	#[test]
+	fn string_escapes() {
+		assert_eq!(parse(r#""a\"b\\c\/d\n\t\r\b\f""#), Ok(Value::String(String::from("a\"b\\c/d\n\t\r\u{8}\u{c}"))));
+		assert_eq!(parse(r#""\u00b5m \u2264 1""#), Ok(Value::String(String::from("\u{b5}m \u{2264} 1"))));
+		assert_eq!(parse("\"\u{b5}m\""), Ok(Value::String(String::from("\u{b5}m"))));
+	}
+
+	#[test]
+	fn errors_name_the_line() {
+		assert_eq!(parse(""), Err(String::from("unexpected end of input on line 1")));
+		assert_eq!(parse("[1,\n2,\n]"), Err(String::from("invalid value on line 3")));
+		assert_eq!(parse("[1 2]"), Err(String::from("expected `,` or `]` on line 1")));
+		assert_eq!(parse("{\"a\" 1}"), Err(String::from("expected `:` on line 1")));
+		assert_eq!(parse("{\"a\": 1 \"b\": 2}"), Err(String::from("expected `,` or `}` on line 1")));
+		assert_eq!(parse("{a: 1}"), Err(String::from("expected member name on line 1")));
+		assert_eq!(parse("[1]\n[2]"), Err(String::from("trailing characters on line 2")));
+		assert_eq!(parse("nul"), Err(String::from("invalid value on line 1")));
+		assert_eq!(parse("1.2.3"), Err(String::from("invalid number `1.2.3` on line 1")));
+		assert_eq!(parse("\"abc"), Err(String::from("unterminated string on line 1")));
+		assert_eq!(parse("\"abc\\"), Err(String::from("unterminated string on line 1")));
+		assert_eq!(parse(r#""\uzzzz""#), Err(String::from("invalid unicode escape on line 1")));
+	}
+}
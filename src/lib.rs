@@ -0,0 +1,3306 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Plotting of GDSII layouts.
+//!
+//! A plot is made by loading a library, setting up a `Context` with
+//! stylesheets and other options, preparing the cells to be plotted, and
+//! rendering them in one of the output formats:
+//!
+//! ```no_run
+//! # fn plot() -> Result<(), gdsplot::Error> {
+//! let lib = try!(gdsplot::load_library("chip.gds"));
+//! let mut ctx = gdsplot::Context::new(&lib);
+//! try!(gdsplot::load_stylesheet(&mut ctx, "default.style"));
+//! let top = try!(gdsplot::prepare_cell(&ctx, "TOP"));
+//! let overlays = [gdsplot::Overlay::new(&top)];
+//! try!(gdsplot::render(&ctx, &overlays, gdsplot::Format::Png, "TOP.png"));
+//! # Ok(())
+//! # }
+//! ```
+
+extern crate cairo;
+extern crate regex;
+extern crate flate2;
+extern crate bzip2;
+extern crate xz2;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(all(feature = "libgds", not(feature = "gdsii")))]
+extern crate gds;
+
+#[cfg(not(any(feature = "gdsii", feature = "libgds")))]
+compile_error!("either the `gdsii` or the `libgds` feature is needed to read GDS files");
+// mod parser;
+mod error;
+mod backend;
+mod boolean;
+mod rtree;
+mod svg;
+mod pyramid;
+mod html;
+mod raster;
+mod term;
+mod png;
+mod decompress;
+mod inline;
+mod masks;
+mod json;
+mod batch;
+mod xml;
+mod markers;
+mod annotations;
+mod pattern;
+#[cfg(feature = "gdsii")]
+mod gdsii;
+#[cfg(feature = "gdsii")]
+mod oasis;
+
+use std::io::{self, Read, BufRead, BufReader, stdin, Write};
+use std::fs::File;
+use std::collections::{HashMap, HashSet, BTreeMap};
+use std::sync::Arc;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use rtree::RTree;
+#[cfg(feature = "gdsii")]
+use gdsii as gds;
+// use parser::{Parser, ByteIter, Error};
+pub use error::Error;
+pub use gds::Library;
+pub use backend::{CairoBackend, RenderBackend, Style};
+
+
+/// Load a GDS file, or standard input if the file name is `-`.
+#[cfg(all(feature = "libgds", not(feature = "gdsii")))]
+pub fn load_library(filename: &str) -> Result<gds::Library, Error> {
+	// libgds only reads uncompressed files, so anything else is decompressed
+	// into a temporary file first.
+	let mut magic = Vec::new();
+	if filename != "-" {
+		if let Err(e) = File::open(filename).and_then(|f| f.take(16).read_to_end(&mut magic)) {
+			return Err(Error::Io(format!("Unable to read `{}`", filename), e));
+		}
+		if decompress::Format::detect(&magic).is_none() && decompress::Format::from_filename(filename).is_none() {
+			if magic.starts_with(OASIS_MAGIC) {
+				return Err(oasis_unsupported(filename));
+			}
+			return read_gds_file(filename, filename);
+		}
+	}
+	let data = try!(read_input(filename));
+	if data.starts_with(OASIS_MAGIC) {
+		return Err(oasis_unsupported(filename));
+	}
+	let dir = match TempDir::new() {
+		Ok(d) => d,
+		Err(e) => return Err(Error::Io(String::from("Unable to create temporary directory"), e)),
+	};
+	let path = dir.0.join("input.gds");
+	let written = std::fs::OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(&path)
+		.and_then(|mut f| f.write_all(&data));
+	if let Err(e) = written {
+		return Err(Error::Io(String::from("Unable to write temporary GDS file"), e));
+	}
+	read_gds_file(&path.to_string_lossy(), filename)
+}
+
+#[cfg(all(feature = "libgds", not(feature = "gdsii")))]
+fn read_gds_file(path: &str, filename: &str) -> Result<gds::Library, Error> {
+	let mut rd = match gds::Reader::open_file(path, 0) {
+		Ok(rd) => rd,
+		Err(_) => return Err(Error::Invalid(format!("Unable to open GDS file `{}`", filename))),
+	};
+	match gds::Library::read(&mut rd) {
+		Ok(lib) => Ok(lib),
+		Err(_) => Err(Error::Invalid(format!("Unable to read GDS file `{}`", filename))),
+	}
+}
+
+#[cfg(all(feature = "libgds", not(feature = "gdsii")))]
+const OASIS_MAGIC: &'static [u8] = b"%SEMI-OASIS";
+
+#[cfg(all(feature = "libgds", not(feature = "gdsii")))]
+fn oasis_unsupported(filename: &str) -> Error {
+	Error::Invalid(format!("Unable to read `{}`, OASIS files require gdsplot built with the gdsii feature", filename))
+}
+
+/// Load a GDS or OASIS file, or standard input if the file name is `-`,
+/// telling them apart by their contents.
+#[cfg(feature = "gdsii")]
+pub fn load_library(filename: &str) -> Result<gds::Library, Error> {
+	let data = try!(read_input(filename));
+	let lib = if oasis::is_oasis(&data) {
+		oasis::read(&data)
+	} else {
+		gds::Library::read(&data)
+	};
+	lib.map_err(|e| Error::Invalid(format!("{}: {}", filename, e)))
+}
+
+/// Read an input file, or standard input if the file name is `-`. Files
+/// compressed with gzip, bzip2, or xz are decompressed.
+fn read_input(filename: &str) -> Result<Vec<u8>, Error> {
+	let mut data = Vec::new();
+	let read = if filename == "-" {
+		stdin().read_to_end(&mut data)
+	} else {
+		File::open(filename).and_then(|mut f| f.read_to_end(&mut data))
+	};
+	if let Err(e) = read {
+		return Err(Error::Io(format!("Unable to read `{}`", filename), e));
+	}
+	match decompress::Format::detect(&data) {
+		Some(format) => format.decompress(&data).map_err(|e| Error::Invalid(format!("{}: {}", filename, e))),
+		None => match decompress::Format::from_filename(filename) {
+			Some(format) => Err(Error::Invalid(format!("{}: not a {} compressed file", filename, format.name()))),
+			None => Ok(data),
+		},
+	}
+}
+
+/// Find a cell in the context's library and prepare it for plotting.
+pub fn prepare_cell(ctx: &Context, name: &str) -> Result<Struct, Error> {
+	match ctx.lib.find_struct(name) {
+		Some(s) => prepare(ctx, s),
+		None => Err(Error::UnknownCell(name.to_owned())),
+	}
+}
+
+/// Render each clip of a clip list to its own file, named after the clip. Each
+/// cell is prepared only once for all clips. Clips which do not name a cell
+/// are taken from the default cell. Returns the warnings of the prepared cells.
+pub fn render_batch(ctx: &mut Context, clips_file: &str, default_cell: Option<&str>, format: Format) -> Result<Vec<String>, Error> {
+	let clips = try!(batch::load_clips(clips_file));
+	let window = ctx.window;
+	let mut prepared = HashMap::<String, Struct>::new();
+	for clip in &clips {
+		let name = match clip.cell.as_ref().map(|c| c.as_str()).or(default_cell) {
+			Some(n) => n,
+			None => return Err(Error::Invalid(format!("No cell given for clip {}", clip.name))),
+		};
+		if !prepared.contains_key(name) {
+			let s = try!(prepare_cell(ctx, name));
+			prepared.insert(name.to_owned(), s);
+		}
+
+		ctx.window = Some(clip.window);
+		let output_name = format!("{}.{}", clip.name, format.extension());
+		try!(render(ctx, &[Overlay::new(&prepared[name])], format, output_name.as_str()));
+	}
+	ctx.window = window;
+	Ok(prepared.values().flat_map(|s| s.warnings.iter().cloned()).collect())
+}
+
+/// Render a clip centered on each marker, named after the plot, the marker's
+/// category, and its index. Clips are enlarged to fit markers larger than the
+/// clip size.
+pub fn render_marker_clips(ctx: &mut Context, overlays: &[Overlay], format: Format, stem: &str) -> Result<(), Error> {
+	let size = ctx.marker_clips.unwrap_or(0.0);
+	let window = ctx.window;
+	for set in ctx.markers.clone() {
+		let o = match set.find_overlay(overlays) {
+			Some(o) => o,
+			None => continue,
+		};
+		for (i, marker) in set.db.markers.iter().enumerate() {
+			let b = &marker.bbox;
+			let center = Point {
+				x: 0.5 * (b.min.x + b.max.x) + o.offset.x,
+				y: 0.5 * (b.min.y + b.max.y) + o.offset.y,
+			};
+			let half = Vector {
+				x: 0.5 * size.max(1.2 * (b.max.x - b.min.x)),
+				y: 0.5 * size.max(1.2 * (b.max.y - b.min.y)),
+			};
+			ctx.window = Some(Rect {
+				min: center + half * -1.0,
+				max: center + half,
+			});
+			let category: String = marker.category.chars().map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' }).collect();
+			let output_name = format!("{}_{}_{}.{}", stem, category, i, format.extension());
+			if let Err(e) = render(ctx, overlays, format, output_name.as_str()) {
+				ctx.window = window;
+				return Err(e);
+			}
+		}
+	}
+	ctx.window = window;
+	Ok(())
+}
+
+
+/// The library being plotted along with the stylesheets and options that
+/// control the plot.
+pub struct Context<'a> {
+	lib_units: f64,
+	lib: &'a gds::Library,
+	scale: ScaleMode,
+	aliases: HashMap<Box<str>, u16>,
+	only_layers: HashSet<u16>,
+	boundary_layers: HashSet<u16>,
+	assignments: HashMap<u16, Vec<Box<str>>>,
+	classes: HashMap<Box<str>, LayerClass>,
+	bg_color: Option<ColorRgb>,
+	orders: HashMap<u16, i32>,
+	margin: i32,
+	tint: Option<(ColorRgb, f64)>,
+	derived: Vec<(u16, LayerExpr)>,
+	merge_all: bool,
+	merge_layers: HashSet<u16>,
+	window: Option<Rect>,
+	lod: Option<LevelOfDetail>,
+	tile: Option<(i32, i32)>,
+	jobs: usize,
+	mask: MaskOptions,
+	/// The classes assigned to marker categories.
+	marker_classes: HashMap<Box<str>, Vec<Box<str>>>,
+	markers: Vec<Arc<MarkerSet>>,
+	/// The size of the clips rendered around each marker, if any.
+	marker_clips: Option<f64>,
+	annotations: Vec<Arc<AnnotationSet>>,
+	/// The paths of cell names whose instances are highlighted.
+	highlights: Vec<Vec<String>>,
+	highlight_style: HighlightStyle,
+	/// The number of hierarchy levels expanded below the plotted cell, if
+	/// limited.
+	depth: Option<usize>,
+	/// The classes of the boxes drawn for instances that are not expanded.
+	unexpanded_classes: Vec<Box<str>>,
+	/// How cells are expanded, by name pattern. The last matching rule
+	/// applies.
+	cell_rules: Vec<(CellRule, pattern::Pattern)>,
+	/// The rule found for each cell name looked up so far, since a cell is
+	/// looked up for every one of its instances.
+	cell_rule_cache: RefCell<HashMap<String, Option<CellRule>>>,
+	/// Class overrides for parts of the hierarchy.
+	style_scopes: Vec<StyleScope>,
+}
+
+impl<'a> Context<'a> {
+	pub fn new(lib: &'a gds::Library) -> Context<'a> {
+		Context {
+			lib_units: lib.get_units_in_m(),
+			lib: lib,
+			scale: ScaleMode::Size(512,512),
+			aliases: HashMap::new(),
+			only_layers: HashSet::new(),
+			boundary_layers: HashSet::new(),
+			assignments: HashMap::new(),
+			classes: HashMap::new(),
+			bg_color: None,
+			orders: HashMap::new(),
+			margin: 0,
+			tint: None,
+			derived: Vec::new(),
+			merge_all: false,
+			merge_layers: HashSet::new(),
+			window: None,
+			lod: None,
+			tile: None,
+			jobs: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+			mask: MaskOptions { depth: 8, samples: 1 },
+			marker_classes: HashMap::new(),
+			markers: Vec::new(),
+			marker_clips: None,
+			annotations: Vec::new(),
+			highlights: Vec::new(),
+			highlight_style: HighlightStyle {
+				outline: true,
+				color: ColorRgb(1.0, 0.0, 0.0),
+				tint: 0.0,
+				dim: 0.75,
+			},
+			depth: None,
+			unexpanded_classes: Vec::new(),
+			cell_rules: Vec::new(),
+			cell_rule_cache: RefCell::new(HashMap::new()),
+			style_scopes: Vec::new(),
+		}
+	}
+
+	/// Draw the markers of a KLayout report database on top of the plot.
+	pub fn add_markers(&mut self, filename: &str) -> Result<(), Error> {
+		let db = try!(markers::load_lyrdb(filename));
+		let set = MarkerSet::new(self, db);
+		self.markers.push(Arc::new(set));
+		Ok(())
+	}
+
+	/// Draw the annotations in a file on top of the plot.
+	pub fn add_annotations(&mut self, filename: &str) -> Result<(), Error> {
+		let items = try!(annotations::load_annotations(filename));
+		let set = try!(AnnotationSet::new(self, items));
+		self.annotations.push(Arc::new(set));
+		Ok(())
+	}
+
+	/// Highlight the instances of a cell, or the instance at a path of cell
+	/// names separated by slashes, and dim everything else.
+	pub fn add_highlight(&mut self, path: &str) {
+		self.highlights.push(path.split('/').filter(|s| !s.is_empty()).map(|s| s.to_owned()).collect());
+	}
+
+	/// Limit the number of hierarchy levels expanded below the plotted cell.
+	pub fn set_depth(&mut self, depth: Option<usize>) {
+		self.depth = depth;
+	}
+
+	/// Only plot the given window, in meters, rather than the whole cell.
+	pub fn set_window(&mut self, window: Option<Rect>) {
+		self.window = window;
+	}
+
+	/// Render clips of the given size, in meters, around each marker instead
+	/// of the whole plot.
+	pub fn set_marker_clips(&mut self, size: Option<f64>) {
+		self.marker_clips = size;
+	}
+
+	pub fn marker_clips(&self) -> Option<f64> {
+		self.marker_clips
+	}
+
+	/// Set the number of threads used to render tiles.
+	pub fn set_jobs(&mut self, jobs: usize) {
+		self.jobs = jobs.max(1);
+	}
+
+	fn is_plotted(&self, layer_id: u16) -> bool {
+		self.only_layers.is_empty() || self.only_layers.contains(&layer_id)
+	}
+
+	fn is_merged(&self, layer_id: u16) -> bool {
+		self.merge_all || self.merge_layers.contains(&layer_id)
+	}
+
+	/// Determine the style of a layer within the given style scopes, whose
+	/// overrides are merged after each class in the order the scopes were
+	/// defined.
+	fn get_scoped_layer_style(&self, layer_id: u16, scopes: &[usize]) -> LayerClass {
+		let mut style = LayerClass::new();
+		if let Some(classes) = self.assignments.get(&layer_id) {
+			for cls in classes {
+				if let Some(class) = self.classes.get(cls) {
+					style.merge(class);
+				}
+				for &i in scopes {
+					if let Some(class) = self.style_scopes[i].classes.get(cls) {
+						style.merge(class);
+					}
+				}
+			}
+		}
+		if let Some((color, amount)) = self.tint {
+			style.tint(color, amount);
+		}
+		style
+	}
+
+	/// Advance the progress of each style scope when descending into a cell.
+	fn enter_scopes(&self, progress: &[usize], name: &str) -> Vec<usize> {
+		self.style_scopes.iter().zip(progress.iter()).map(|(scope, &p)| {
+			let n = scope.path.len();
+			if p == n || p == SCOPE_MISSED {
+				p
+			} else if n == 1 {
+				if scope.path[0].matches(name) { 1 } else { 0 }
+			} else if scope.path[p].matches(name) {
+				p + 1
+			} else {
+				SCOPE_MISSED
+			}
+		}).collect()
+	}
+
+	/// The style scopes that apply at the given progress and override one of
+	/// the classes of a layer.
+	fn get_layer_scopes(&self, layer_id: u16, progress: &[usize]) -> Vec<usize> {
+		let classes = match self.assignments.get(&layer_id) {
+			Some(c) => c,
+			None => return Vec::new(),
+		};
+		self.style_scopes.iter().zip(progress.iter()).enumerate()
+			.filter(|&(_, (scope, &p))| p == scope.path.len() && classes.iter().any(|c| scope.classes.contains_key(c)))
+			.map(|(i, _)| i)
+			.collect()
+	}
+
+	/// Find the rule that applies to a cell, if any.
+	fn get_cell_rule(&self, name: &str) -> Option<CellRule> {
+		if self.cell_rules.is_empty() {
+			return None;
+		}
+		if let Some(&rule) = self.cell_rule_cache.borrow().get(name) {
+			return rule;
+		}
+		let rule = self.cell_rules.iter().rev().find(|r| r.1.matches(name)).map(|r| r.0);
+		self.cell_rule_cache.borrow_mut().insert(name.to_owned(), rule);
+		rule
+	}
+
+	/// The style of the boxes drawn for instances that are not expanded, a
+	/// thin gray outline unless styled otherwise.
+	fn get_unexpanded_style(&self) -> LayerClass {
+		let mut style = LayerClass::new();
+		style.general.color = Some(ColorRgb(0.5, 0.5, 0.5));
+		style.stroke.width = Some(1.0);
+		for cls in &self.unexpanded_classes {
+			if let Some(class) = self.classes.get(cls) {
+				style.merge(class);
+			}
+		}
+		style
+	}
+}
+
+/// A marker database along with the style of each of its categories.
+#[derive(Debug)]
+struct MarkerSet {
+	db: markers::Database,
+	styles: HashMap<String, LayerClass>,
+}
+
+/// The colors assigned to marker categories in turn, unless styled otherwise.
+const MARKER_COLORS: [&'static str; 8] = ["#ff0000", "#ff00ff", "#00c0ff", "#ffa000", "#00d000", "#8000ff", "#ffff00", "#ff0080"];
+
+impl MarkerSet {
+	/// Style each category with a color of its own and an outline, followed by
+	/// the classes assigned to the category or any of its parents.
+	fn new(ctx: &Context, db: markers::Database) -> MarkerSet {
+		let mut styles = HashMap::new();
+		for (i, cat) in db.categories.iter().enumerate() {
+			let mut style = LayerClass::new();
+			style.general.color = Some(parse_color(MARKER_COLORS[i % MARKER_COLORS.len()]).unwrap());
+			style.fill.alpha = Some(0.3);
+			style.fill.pattern = Some(FillPattern::Solid);
+			style.stroke.width = Some(2.0);
+
+			let mut prefixes: Vec<&str> = cat.match_indices('.').map(|(j, _)| &cat[..j]).collect();
+			prefixes.push(cat);
+			for prefix in prefixes {
+				if let Some(classes) = ctx.marker_classes.get(prefix) {
+					for cls in classes {
+						if let Some(class) = ctx.classes.get(cls) {
+							style.merge(class);
+						}
+					}
+				}
+			}
+			styles.insert(cat.clone(), style);
+		}
+		MarkerSet {
+			db: db,
+			styles: styles,
+		}
+	}
+
+	/// Find the overlay the markers refer to. Markers of a database naming a
+	/// top cell only apply to plots of that cell.
+	fn find_overlay<'a, 'b>(&self, overlays: &'b [Overlay<'a>]) -> Option<&'b Overlay<'a>> {
+		match self.db.top_cell {
+			Some(ref name) => overlays.iter().find(|o| *o.strukt.name == **name),
+			None => overlays.first(),
+		}
+	}
+}
+
+/// Annotations along with their styles.
+#[derive(Debug)]
+struct AnnotationSet {
+	items: Vec<(annotations::Annotation, LayerClass)>,
+}
+
+impl AnnotationSet {
+	/// Style each annotation with a thin black outline, followed by its
+	/// classes.
+	fn new(ctx: &Context, items: Vec<annotations::Annotation>) -> Result<AnnotationSet, Error> {
+		let mut styled = Vec::with_capacity(items.len());
+		for a in items {
+			let mut style = LayerClass::new();
+			style.general.color = Some(ColorRgb(0.0, 0.0, 0.0));
+			style.stroke.width = Some(1.0);
+			for cls in &a.classes {
+				match ctx.classes.get(&**cls) {
+					Some(class) => style.merge(class),
+					None => return Err(Error::Invalid(format!("Unknown annotation class `{}`", cls))),
+				}
+			}
+			styled.push((a, style));
+		}
+		Ok(AnnotationSet { items: styled })
+	}
+}
+
+/// How the instances of a cell are treated when expanding the hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CellRule {
+	/// Leave the instances out of the plot.
+	Hide,
+	/// Draw only a box of the cell's extents, as for cells below the depth
+	/// limit.
+	Blackbox,
+	/// Always expand the instances, regardless of the depth limit.
+	Expand,
+}
+
+/// Class overrides for the geometry within cells matching a name pattern, or
+/// reached by a path of cell names from the plotted cell.
+struct StyleScope {
+	path: Vec<pattern::Pattern>,
+	classes: HashMap<Box<str>, LayerClass>,
+}
+
+/// The progress of a style scope whose path can no longer match.
+const SCOPE_MISSED: usize = std::usize::MAX;
+
+/// How highlighted instances stand out from the rest of a plot.
+#[derive(Debug, Clone, Copy)]
+struct HighlightStyle {
+	/// Draw the bounding box of highlighted instances.
+	outline: bool,
+	/// The color of the bounding box and of the tint.
+	color: ColorRgb,
+	/// How far the geometry of highlighted instances is tinted.
+	tint: f64,
+	/// How far everything else is faded out.
+	dim: f64,
+}
+
+/// How per-layer masks are rasterized.
+#[derive(Debug, Clone, Copy)]
+struct MaskOptions {
+	/// The bit depth of the masks, either 1 or 8.
+	depth: u8,
+	/// The number of samples per pixel along each axis. More than one sample
+	/// yields anti-aliased masks.
+	samples: usize,
+}
+
+/// Thresholds below which geometry is simplified, in pixels.
+#[derive(Debug, Clone, Copy)]
+struct LevelOfDetail {
+	/// Shapes smaller than this are drawn as a single pixel.
+	shapes: f64,
+	/// Instances smaller than this are drawn as a box in the average color of
+	/// the instantiated cell.
+	instances: f64,
+	/// Drop small shapes entirely rather than drawing a pixel.
+	drop: bool,
+}
+
+enum ScaleMode {
+	/// Make the plot large enough to achieve a certain number of pixels per meter.
+	Resolution(f64),
+	/// Make the plot a specific size.
+	Size(i32,i32),
+}
+
+#[derive(Debug, Clone)]
+struct LayerClass {
+	general: LayerClassSheet,
+	fill: LayerClassSheet,
+	stroke: LayerClassSheet,
+}
+
+#[derive(Debug, Clone)]
+struct LayerClassSheet {
+	color: Option<ColorRgb>,
+	alpha: Option<f64>,
+	width: Option<f64>,
+	pattern: Option<FillPattern>,
+	dashes: Option<Vec<f64>>,
+}
+
+impl LayerClass {
+	fn new() -> LayerClass {
+		LayerClass {
+			general: LayerClassSheet::new(),
+			fill: LayerClassSheet::new(),
+			stroke: LayerClassSheet::new(),
+		}
+	}
+
+	fn merge(&mut self, other: &LayerClass) {
+		self.general.merge(&other.general);
+		self.fill.merge(&other.fill);
+		self.stroke.merge(&other.stroke);
+	}
+
+	/// Blend all colors of the class towards a tint color. An amount of 0
+	/// leaves the colors untouched, 1 replaces them with the tint color.
+	fn tint(&mut self, color: ColorRgb, amount: f64) {
+		self.general.tint(color, amount);
+		self.fill.tint(color, amount);
+		self.stroke.tint(color, amount);
+	}
+
+	fn get_fill_style(&self) -> Option<FillStyle> {
+		let mut combined = self.general.clone();
+		combined.merge(&self.fill);
+		Some(FillStyle {
+			color: match combined.color {
+				Some(c) => c,
+				None => return None,
+			},
+			alpha: combined.alpha.unwrap_or(1.0),
+			pattern: match combined.pattern {
+				Some(p) => p,
+				None => return None,
+			},
+		})
+	}
+
+	fn get_stroke_style(&self) -> Option<StrokeStyle> {
+		let mut combined = self.general.clone();
+		combined.merge(&self.stroke);
+		Some(StrokeStyle {
+			color: match combined.color {
+				Some(c) => c,
+				None => return None,
+			},
+			alpha: combined.alpha.unwrap_or(1.0),
+			width: match combined.width {
+				Some(w) => w,
+				None => return None,
+			},
+			dashes: combined.dashes,
+		})
+	}
+}
+
+impl LayerClassSheet {
+	fn new() -> LayerClassSheet {
+		LayerClassSheet {
+			color: None,
+			alpha: None,
+			width: None,
+			pattern: None,
+			dashes: None,
+		}
+	}
+
+	fn merge(&mut self, other: &LayerClassSheet) {
+		if other.color.is_some() {
+			self.color = other.color.clone();
+		}
+		if other.alpha.is_some() {
+			self.alpha = other.alpha;
+		}
+		if other.width.is_some() {
+			self.width = other.width;
+		}
+		if other.pattern.is_some() {
+			self.pattern = other.pattern.clone();
+		}
+		if other.dashes.is_some() {
+			self.dashes = other.dashes.clone();
+		}
+	}
+
+	fn tint(&mut self, color: ColorRgb, amount: f64) {
+		if let Some(c) = self.color {
+			self.color = Some(ColorRgb(
+				c.r + (color.r - c.r) * amount,
+				c.g + (color.g - c.g) * amount,
+				c.b + (color.b - c.b) * amount,
+			));
+		}
+	}
+}
+
+struct FillStyle {
+	color: ColorRgb,
+	alpha: f64,
+	pattern: FillPattern,
+}
+
+struct StrokeStyle {
+	color: ColorRgb,
+	alpha: f64,
+	width: f64,
+	dashes: Option<Vec<f64>>,
+}
+
+impl FillStyle {
+	fn style(&self) -> Style {
+		Style { alpha: self.alpha, ..Style::solid(self.color) }
+	}
+}
+
+impl StrokeStyle {
+	fn style(&self) -> Style {
+		Style {
+			color: self.color,
+			alpha: self.alpha,
+			width: self.width,
+			dashes: self.dashes.clone(),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+enum FillPattern {
+	Solid,
+}
+
+
+/// Load a stylesheet into a context.
+pub fn load_stylesheet(ctx: &mut Context, filename: &str) -> Result<(), Error> {
+	let file = match File::open(filename) {
+		Ok(f) => f,
+		Err(e) => return Err(Error::Io(format!("Unable to open stylesheet {}", filename), e)),
+	};
+	read_stylesheet(ctx, BufReader::new(file), filename)
+}
+
+/// Load a stylesheet from a reader into a context. The name stands in for the
+/// file name in errors. A stylesheet held in a string is read with
+/// `read_stylesheet(ctx, text.as_bytes(), name)`.
+pub fn read_stylesheet<R: BufRead>(ctx: &mut Context, input: R, filename: &str) -> Result<(), Error> {
+	let lines = input.lines();
+
+	// Process each line.
+	let mut scope = None;
+	let mut number = 0;
+	for line in lines {
+		number += 1;
+		let line_unwrapped = match line {
+			Ok(l) => l,
+			Err(e) => return Err(Error::Io(format!("Unable to read stylesheet {}", filename), e)),
+		};
+		let args: Vec<&str> = line_unwrapped.split_whitespace().take_while(|x| !x.starts_with("//")).collect();
+		if args.is_empty() {
+			continue;
+		}
+		if let Err(e) = load_stylesheet_line(ctx, &args, &mut scope) {
+			return Err(Error::Stylesheet(filename.to_owned(), number, e));
+		}
+	}
+	if scope.is_some() {
+		return Err(Error::Stylesheet(filename.to_owned(), number, String::from("missing `}` at the end of an `in cell` block")));
+	}
+	Ok(())
+}
+
+/// Apply one line of a stylesheet to the context. Within an `in cell` block,
+/// whose style scope is given, lines override classes.
+fn load_stylesheet_line(ctx: &mut Context, args: &[&str], scope: &mut Option<usize>) -> Result<(), String> {
+	if let Some(i) = *scope {
+		if try!(load_scoped_classes(&mut ctx.style_scopes[i], args)) {
+			*scope = None;
+		}
+		return Ok(());
+	}
+
+	let mut it = args.iter();
+	match *it.next().unwrap() {
+		"alias" => {
+			let id: u16 = try!(parse_arg(&mut it, "layer ID"));
+			let alias = try!(next_arg(&mut it, "alias"));
+			ctx.aliases.insert(alias.to_owned().into_boxed_str(), id);
+			for cls in it {
+				if ctx.assignments.contains_key(&id) {
+					ctx.assignments.get_mut(&id).unwrap().push((*cls).to_owned().into_boxed_str());
+				} else {
+					ctx.assignments.insert(id, vec![(*cls).to_owned().into_boxed_str()]);
+				}
+			}
+		},
+
+		"general" => {
+			let classname = try!(next_arg(&mut it, "class name"));
+			if !ctx.classes.contains_key(classname) {
+				ctx.classes.insert(classname.to_owned().into_boxed_str(), LayerClass::new());
+			}
+			let class = ctx.classes.get_mut(classname).unwrap();
+			try!(load_layer_class_sheet(&mut class.general, it));
+		},
+
+		"fill" => {
+			let classname = try!(next_arg(&mut it, "class name"));
+			if !ctx.classes.contains_key(classname) {
+				ctx.classes.insert(classname.to_owned().into_boxed_str(), LayerClass::new());
+			}
+			let class = ctx.classes.get_mut(classname).unwrap();
+			try!(load_layer_class_sheet(&mut class.fill, it));
+		},
+
+		"stroke" => {
+			let classname = try!(next_arg(&mut it, "class name"));
+			if !ctx.classes.contains_key(classname) {
+				ctx.classes.insert(classname.to_owned().into_boxed_str(), LayerClass::new());
+			}
+			let class = ctx.classes.get_mut(classname).unwrap();
+			try!(load_layer_class_sheet(&mut class.stroke, it));
+		},
+
+		"bgcolor" => {
+			ctx.bg_color = Some(try!(color_arg(&mut it, "bgcolor")));
+		},
+
+		"only" => {
+			for layer in it {
+				let id = try!(layer_id(ctx, layer));
+				ctx.only_layers.insert(id);
+			}
+		},
+
+		"merge" => {
+			let mut any = false;
+			for layer in it {
+				let id = try!(layer_id(ctx, layer));
+				ctx.merge_layers.insert(id);
+				any = true;
+			}
+			if !any {
+				ctx.merge_all = true;
+			}
+		},
+
+		"order" => {
+			let id = try!(layer_id(ctx, try!(next_arg(&mut it, "layer"))));
+			ctx.orders.insert(id, try!(parse_arg(&mut it, "layer order")));
+		},
+
+		"resolution" => {
+			ctx.scale = ScaleMode::Resolution(try!(parse_arg(&mut it, "resolution")));
+		},
+
+		"size" => {
+			ctx.scale = ScaleMode::Size(
+				try!(parse_arg(&mut it, "width")),
+				try!(parse_arg(&mut it, "height")),
+			);
+		},
+
+		"window" => {
+			let mut c = [0.0; 4];
+			for v in c.iter_mut() {
+				*v = try!(parse_arg::<f64>(&mut it, "window coordinate")) * 1e-6;
+			}
+			let (x0, y0, x1, y1) = (c[0], c[1], c[2], c[3]);
+			if x0 == x1 || y0 == y1 {
+				return Err(String::from("window must have a nonzero width and height"));
+			}
+			ctx.window = Some(Rect {
+				min: Point { x: x0.min(x1), y: y0.min(y1) },
+				max: Point { x: x0.max(x1), y: y0.max(y1) },
+			});
+		},
+
+		"lod" => {
+			let mut lod = LevelOfDetail { shapes: 1.0, instances: 1.0, drop: false };
+			let mut thresholds = Vec::new();
+			for arg in it {
+				match *arg {
+					"drop" => lod.drop = true,
+					x => thresholds.push(try!(parse_value(x, "level of detail threshold"))),
+				}
+			}
+			if let Some(&t) = thresholds.get(0) {
+				lod.shapes = t;
+				lod.instances = t;
+			}
+			if let Some(&t) = thresholds.get(1) {
+				lod.instances = t;
+			}
+			ctx.lod = Some(lod);
+		},
+
+		"tile" => {
+			let w: i32 = try!(parse_arg(&mut it, "tile width"));
+			let h: i32 = match it.next() {
+				Some(h) => try!(parse_value(h, "tile height")),
+				None => w,
+			};
+			if w <= 0 || h <= 0 {
+				return Err(String::from("tile size must be positive"));
+			}
+			ctx.tile = Some((w, h));
+		},
+
+		"mask" => {
+			let depth: u8 = try!(parse_arg(&mut it, "mask depth"));
+			let samples: usize = match it.next() {
+				Some(s) => try!(parse_value(s, "number of mask samples")),
+				None => 1,
+			};
+			if depth != 1 && depth != 8 {
+				return Err(String::from("mask depth must be 1 or 8"));
+			}
+			if samples == 0 {
+				return Err(String::from("number of mask samples must be positive"));
+			}
+			ctx.mask = MaskOptions { depth: depth, samples: samples };
+		},
+
+		"marker" => {
+			let category = try!(next_arg(&mut it, "marker category"));
+			let classes = it.map(|cls| (*cls).to_owned().into_boxed_str()).collect();
+			ctx.marker_classes.insert(category.to_owned().into_boxed_str(), classes);
+		},
+
+		"in" => {
+			if it.next().map(|x| *x) != Some("cell") {
+				return Err(String::from("expected `in cell PATTERN {`"));
+			}
+			let path = match it.next().map(|p| parse_cell_path(p)) {
+				Some(Ok(p)) => p,
+				Some(Err(e)) => return Err(format!("invalid cell pattern {}", e)),
+				None => return Err(String::from("expected `in cell PATTERN {`")),
+			};
+			if it.next().map(|x| *x) != Some("{") {
+				return Err(String::from("expected `{` after `in cell`"));
+			}
+			ctx.style_scopes.push(StyleScope {
+				path: path,
+				classes: HashMap::new(),
+			});
+			let i = ctx.style_scopes.len() - 1;
+			if !try!(load_scoped_classes(&mut ctx.style_scopes[i], it.as_slice())) {
+				*scope = Some(i);
+			}
+		},
+
+		"hide" | "blackbox" | "expand" => {
+			let rule = match args[0] {
+				"hide" => CellRule::Hide,
+				"blackbox" => CellRule::Blackbox,
+				_ => CellRule::Expand,
+			};
+			if it.next().map(|x| *x) != Some("cell") {
+				return Err(format!("expected `{} cell PATTERN...`", args[0]));
+			}
+			for p in it {
+				match pattern::Pattern::parse(p) {
+					Ok(p) => {
+						ctx.cell_rules.push((rule, p));
+						ctx.cell_rule_cache.borrow_mut().clear();
+					},
+					Err(e) => return Err(format!("invalid cell pattern {}", e)),
+				}
+			}
+		},
+
+		"unexpanded" => {
+			ctx.unexpanded_classes = it.map(|cls| (*cls).to_owned().into_boxed_str()).collect();
+		},
+
+		"highlight" => {
+			while let Some(opt) = it.next() {
+				match *opt {
+					"box" => ctx.highlight_style.outline = match try!(next_arg(&mut it, "highlight box")) {
+						"on" => true,
+						"off" => false,
+						x => return Err(format!("invalid highlight box `{}`, expected on or off", x)),
+					},
+					"color" => ctx.highlight_style.color = try!(color_arg(&mut it, "highlight color")),
+					"tint" => ctx.highlight_style.tint = try!(parse_arg(&mut it, "highlight tint")),
+					"dim" => ctx.highlight_style.dim = try!(parse_arg(&mut it, "highlight dim")),
+					x => return Err(format!("unknown highlight option `{}`", x)),
+				}
+			}
+		},
+
+		"margin" => {
+			ctx.margin = try!(parse_arg(&mut it, "margin"));
+		},
+
+		"tint" => {
+			let color = try!(color_arg(&mut it, "tint color"));
+			let amount = match it.next() {
+				Some(a) => try!(parse_value(a, "tint amount")),
+				None => 0.5,
+			};
+			ctx.tint = Some((color, amount));
+		},
+
+		"derive" => {
+			let name = try!(next_arg(&mut it, "derived layer name"));
+			let mut classes = Vec::new();
+			loop {
+				match it.next() {
+					Some(&"=") => break,
+					Some(cls) => classes.push((*cls).to_owned().into_boxed_str()),
+					None => return Err(format!("expected `=` in derived layer `{}`", name)),
+				}
+			}
+			let expr = match parse_layer_expr(ctx, &it.cloned().collect::<Vec<&str>>().join(" ")) {
+				Ok(e) => e,
+				Err(e) => return Err(format!("invalid derived layer `{}`: {}", name, e)),
+			};
+
+			// Derived layers are assigned virtual IDs counting down from
+			// the top of the ID range, such that they can be styled and
+			// ordered like any other layer.
+			let id = std::u16::MAX - ctx.derived.len() as u16;
+			ctx.aliases.insert(name.to_owned().into_boxed_str(), id);
+			ctx.assignments.insert(id, classes);
+			ctx.derived.push((id, expr));
+		},
+
+		x => return Err(format!("unknown stylesheet command `{}`", x)),
+	};
+	Ok(())
+}
+
+/// Take the next argument of a stylesheet command.
+fn next_arg<'a>(it: &mut std::slice::Iter<&'a str>, what: &str) -> Result<&'a str, String> {
+	match it.next() {
+		Some(arg) => Ok(*arg),
+		None => Err(format!("missing {}", what)),
+	}
+}
+
+/// Take the next argument of a stylesheet command and parse it.
+fn parse_arg<T: std::str::FromStr>(it: &mut std::slice::Iter<&str>, what: &str) -> Result<T, String> {
+	let arg = try!(next_arg(it, what));
+	parse_value(arg, what)
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, what: &str) -> Result<T, String> {
+	arg.parse().map_err(|_| format!("invalid {} `{}`", what, arg))
+}
+
+/// Take the next argument of a stylesheet command as a color.
+fn color_arg(it: &mut std::slice::Iter<&str>, what: &str) -> Result<ColorRgb, String> {
+	let arg = try!(next_arg(it, what));
+	parse_color(arg).map_err(|_| format!("invalid {} `{}`", what, arg))
+}
+
+/// Resolve a layer given by alias or ID.
+fn layer_id(ctx: &Context, layer: &str) -> Result<u16, String> {
+	match ctx.aliases.get(layer) {
+		Some(v) => Ok(*v),
+		None => parse_value(layer, "layer ID"),
+	}
+}
+
+/// Parse a cell name pattern, or a path of patterns separated by slashes.
+fn parse_cell_path(s: &str) -> Result<Vec<pattern::Pattern>, String> {
+	if s.len() >= 2 && s.starts_with('/') && s.ends_with('/') {
+		return pattern::Pattern::parse(s).map(|p| vec![p]);
+	}
+	s.split('/').filter(|p| !p.is_empty()).map(pattern::Pattern::parse).collect()
+}
+
+/// Load a line of class overrides within an `in cell` block. Returns whether
+/// the line closes the block.
+fn load_scoped_classes(scope: &mut StyleScope, args: &[&str]) -> Result<bool, String> {
+	let (args, closed) = match args.split_last() {
+		Some((&"}", rest)) => (rest, true),
+		_ => (args, false),
+	};
+	if args.is_empty() {
+		return Ok(closed);
+	}
+	if args.len() < 2 {
+		return Err(String::from("expected `general`, `fill`, or `stroke` followed by a class within `in cell`"));
+	}
+	let class = scope.classes.entry(args[1].to_owned().into_boxed_str()).or_insert_with(LayerClass::new);
+	let it = args[2..].iter();
+	try!(match args[0] {
+		"general" => load_layer_class_sheet(&mut class.general, it),
+		"fill" => load_layer_class_sheet(&mut class.fill, it),
+		"stroke" => load_layer_class_sheet(&mut class.stroke, it),
+		x => Err(format!("unexpected `{}` within `in cell`, only `general`, `fill`, and `stroke` may be overridden", x)),
+	});
+	Ok(closed)
+}
+
+fn load_layer_class_sheet(dst: &mut LayerClassSheet, mut it: std::slice::Iter<&str>) -> Result<(), String> {
+	while let Some(opt) = it.next() {
+		match *opt {
+			"color" => {
+				dst.color = Some(try!(color_arg(&mut it, "color")));
+			},
+			"alpha" => {
+				dst.alpha = Some(try!(parse_arg(&mut it, "alpha")));
+			},
+			"width" => {
+				dst.width = Some(try!(parse_arg(&mut it, "width")));
+			},
+			"dashes" => {
+				let mut dashes = Vec::new();
+				for x in it {
+					dashes.push(try!(parse_value(x, "dash width")));
+				}
+				dst.dashes = Some(dashes);
+				break;
+			},
+			"pattern" => {
+				dst.pattern = Some(match try!(next_arg(&mut it, "pattern")) {
+					"solid" => FillPattern::Solid,
+					x => return Err(format!("unknown pattern `{}`", x)),
+				});
+			}
+			x => return Err(format!("unknown style parameter `{}`", x)),
+		}
+	}
+	Ok(())
+}
+
+/// An expression describing a derived layer in terms of other layers.
+#[derive(Debug, Clone)]
+enum LayerExpr {
+	Layer(u16),
+	Not(Box<LayerExpr>),
+	Binary(boolean::Op, Box<LayerExpr>, Box<LayerExpr>),
+	/// Grow the layer by a distance in meters, or shrink it if negative.
+	Grow(Box<LayerExpr>, f64),
+}
+
+impl LayerExpr {
+	/// Collect the IDs of the layers the expression refers to.
+	fn collect_layers(&self, into: &mut HashSet<u16>) {
+		match *self {
+			LayerExpr::Layer(id) => { into.insert(id); },
+			LayerExpr::Not(ref e) | LayerExpr::Grow(ref e, _) => e.collect_layers(into),
+			LayerExpr::Binary(_, ref a, ref b) => {
+				a.collect_layers(into);
+				b.collect_layers(into);
+			},
+		}
+	}
+}
+
+/// Parse a derived layer expression such as `poly & active`,
+/// `metal1 & !metal2`, or `grow(via1 | via2, 0.05)`. The operators `!`, `&`,
+/// `^`, and `|` bind in that order and may also be written as `not`, `and`,
+/// `xor`, and `or`. Distances are given in micrometers.
+fn parse_layer_expr(ctx: &Context, s: &str) -> Result<LayerExpr, String> {
+	// Split the expression into tokens.
+	let mut tokens = Vec::new();
+	let mut word = String::new();
+	for c in s.chars() {
+		if c.is_whitespace() || "&|^!(),".contains(c) {
+			if !word.is_empty() {
+				tokens.push(std::mem::replace(&mut word, String::new()));
+			}
+			if !c.is_whitespace() {
+				tokens.push(c.to_string());
+			}
+		} else {
+			word.push(c);
+		}
+	}
+	if !word.is_empty() {
+		tokens.push(word);
+	}
+	for t in &mut tokens {
+		let op = match t.as_str() {
+			"and" => "&",
+			"or" => "|",
+			"xor" => "^",
+			"not" => "!",
+			_ => continue,
+		};
+		*t = op.to_owned();
+	}
+
+	let mut p = LayerExprParser { ctx: ctx, tokens: &tokens, pos: 0 };
+	let expr = try!(p.parse_binary(0));
+	match p.peek() {
+		Some(t) => Err(format!("unexpected `{}`", t)),
+		None => Ok(expr),
+	}
+}
+
+struct LayerExprParser<'a> {
+	ctx: &'a Context<'a>,
+	tokens: &'a [String],
+	pos: usize,
+}
+
+impl<'a> LayerExprParser<'a> {
+	fn peek(&self) -> Option<&'a str> {
+		self.tokens.get(self.pos).map(|t| t.as_str())
+	}
+
+	fn bump(&mut self) -> Option<&'a str> {
+		let t = self.peek();
+		self.pos += 1;
+		t
+	}
+
+	fn expect(&mut self, tkn: &str) -> Result<(), String> {
+		match self.bump() {
+			Some(t) if t == tkn => Ok(()),
+			Some(t) => Err(format!("expected `{}`, found `{}`", tkn, t)),
+			None => Err(format!("expected `{}`", tkn)),
+		}
+	}
+
+	/// Parse a chain of binary operators, starting at the given precedence
+	/// level.
+	fn parse_binary(&mut self, level: usize) -> Result<LayerExpr, String> {
+		const LEVELS: [(&'static str, boolean::Op); 3] = [
+			("|", boolean::Op::Or),
+			("^", boolean::Op::Xor),
+			("&", boolean::Op::And),
+		];
+		if level == LEVELS.len() {
+			return self.parse_unary();
+		}
+		let (tkn, op) = LEVELS[level];
+		let mut lhs = try!(self.parse_binary(level + 1));
+		while self.peek() == Some(tkn) {
+			self.bump();
+			// Fold `a & !b` into a single operation rather than complementing
+			// `b` against the bounding box.
+			lhs = match (op, try!(self.parse_binary(level + 1))) {
+				(boolean::Op::And, LayerExpr::Not(rhs)) => LayerExpr::Binary(boolean::Op::AndNot, Box::new(lhs), rhs),
+				(op, rhs) => LayerExpr::Binary(op, Box::new(lhs), Box::new(rhs)),
+			};
+		}
+		Ok(lhs)
+	}
+
+	fn parse_unary(&mut self) -> Result<LayerExpr, String> {
+		match self.bump() {
+			Some("!") => Ok(LayerExpr::Not(Box::new(try!(self.parse_unary())))),
+			Some("(") => {
+				let e = try!(self.parse_binary(0));
+				try!(self.expect(")"));
+				Ok(e)
+			},
+			Some(f) if f == "grow" || f == "shrink" => {
+				try!(self.expect("("));
+				let e = try!(self.parse_binary(0));
+				try!(self.expect(","));
+				let d: f64 = match self.bump().map(|t| t.parse()) {
+					Some(Ok(d)) => d,
+					_ => return Err(format!("expected distance in `{}`", f)),
+				};
+				try!(self.expect(")"));
+				let d = d * 1e-6;
+				Ok(LayerExpr::Grow(Box::new(e), if f == "grow" { d } else { -d }))
+			},
+			Some(layer) => match self.ctx.aliases.get(layer) {
+				Some(id) => Ok(LayerExpr::Layer(*id)),
+				None => match layer.parse() {
+					Ok(id) => Ok(LayerExpr::Layer(id)),
+					Err(_) => Err(format!("unknown layer `{}`", layer)),
+				},
+			},
+			None => Err(String::from("unexpected end of expression")),
+		}
+	}
+}
+
+fn parse_color(s: &str) -> Result<ColorRgb, &str> {
+	match s.chars().nth(0) {
+		Some('#') => {
+			if s.len() != 7 || !s.is_char_boundary(1) {
+				return Err(s);
+			}
+			let (_,rem) = s.split_at(1);
+			let (rs,rem) = rem.split_at(2);
+			let (gs,rem) = rem.split_at(2);
+			let (bs,rem) = rem.split_at(2);
+			let r = try!(u8::from_str_radix(rs, 16).map_err(|_| s));
+			let g = try!(u8::from_str_radix(gs, 16).map_err(|_| s));
+			let b = try!(u8::from_str_radix(bs, 16).map_err(|_| s));
+			Ok(ColorRgb(
+				r as f64 / 255.0,
+				g as f64 / 255.0,
+				b as f64 / 255.0
+			))
+		}
+		_ => Err(s),
+	}
+}
+
+
+/// A cell prepared for plotting, along with its hierarchy.
+pub struct Struct {
+	layers: Vec<Arc<Layer>>,
+	name: Box<str>,
+	boundaries: Vec<Boundary>,
+	instances: Vec<Instance>,
+	extents: Extents,
+	/// The boundaries on each layer, indexed by their bounding box.
+	shapes: HashMap<u16, RTree<usize>>,
+	/// The instances, indexed by their bounding box.
+	placements: RTree<usize>,
+	/// The area-weighted average fill color of the struct, used to draw
+	/// instances too small to show any detail.
+	tone: Option<ColorRgb>,
+	/// The filled area the tone was averaged over.
+	tone_area: f64,
+	/// The layer during whose fill pass the struct is drawn as a box when too
+	/// small to show any detail.
+	tone_layer: Option<u16>,
+	/// Whether the struct stands in for a cell below the depth limit, which
+	/// is drawn as a box of its extents rather than expanded.
+	unexpanded: bool,
+	/// Whether any instance within the struct's hierarchy is unexpanded.
+	has_unexpanded: bool,
+	/// Problems found in the hierarchy while preparing the struct, such as
+	/// references to missing cells, which did not prevent it from being
+	/// plotted.
+	warnings: Vec<String>,
+}
+
+impl Struct {
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// The problems found while preparing the struct, which was plotted
+	/// nonetheless.
+	pub fn warnings(&self) -> &[String] {
+		&self.warnings
+	}
+
+	fn new(name: Box<str>, boundaries: Vec<Boundary>, instances: Vec<Instance>) -> Struct {
+		let mut extents = Extents::new();
+		let mut layers = BTreeMap::<u16, Arc<Layer>>::new();
+		let mut buckets = HashMap::<u16, Vec<(Rect, usize)>>::new();
+		let mut fills = HashMap::<u16, Option<ColorRgb>>::new();
+		let mut tone = (0.0, 0.0, 0.0, 0.0);
+
+		for (i, b) in boundaries.iter().enumerate() {
+			let mut bbox = Extents::new();
+			for p in &b.points {
+				bbox.add_point(p);
+			}
+			extents.add_extents(&bbox, ZERO_VECTOR);
+			if !layers.contains_key(&b.layer.key) {
+				layers.insert(b.layer.key, b.layer.clone());
+			}
+			buckets.entry(b.layer.key).or_insert_with(Vec::new).push((bbox.rect, i));
+
+			let fill = *fills.entry(b.layer.key).or_insert_with(|| b.layer.style.get_fill_style().map(|fs| fs.color));
+			if let Some(c) = fill {
+				let mut area = boolean::signed_area(&b.points).abs();
+				for h in &b.holes {
+					area -= boolean::signed_area(h).abs();
+				}
+				tone.0 += c.r * area;
+				tone.1 += c.g * area;
+				tone.2 += c.b * area;
+				tone.3 += area;
+			}
+		}
+
+		let mut placed = Vec::new();
+		for (i, inst) in instances.iter().enumerate() {
+			if inst.extents.empty {
+				continue;
+			}
+			extents.add_extents(&inst.extents, ZERO_VECTOR);
+			for l in &inst.strukt.layers {
+				if !layers.contains_key(&l.key) {
+					layers.insert(l.key, l.clone());
+				}
+			}
+			placed.push((inst.extents.rect, i));
+
+			if let Some(c) = inst.strukt.tone {
+				let area = inst.strukt.tone_area * (inst.tx.va.x * inst.tx.vb.y - inst.tx.vb.x * inst.tx.va.y).abs();
+				tone.0 += c.r * area;
+				tone.1 += c.g * area;
+				tone.2 += c.b * area;
+				tone.3 += area;
+			}
+		}
+
+		// Make an ordered list of layers.
+		let mut ordered_layers: Vec<Arc<Layer>> = Vec::new();
+		for (_,l) in layers {
+			ordered_layers.push(l);
+		}
+		ordered_layers.sort_by_key(|l| l.order);
+		let tone_layer = ordered_layers.iter()
+			.find(|l| l.style.get_fill_style().is_some())
+			.map(|l| l.key);
+		let has_unexpanded = instances.iter().any(|i| i.strukt.unexpanded || i.strukt.has_unexpanded);
+
+		Struct {
+			layers: ordered_layers,
+			name: name,
+			boundaries: boundaries,
+			instances: instances,
+			extents: extents,
+			shapes: buckets.into_iter().map(|(id, items)| (id, RTree::new(items))).collect(),
+			placements: RTree::new(placed),
+			tone: if tone.3 > 0.0 {
+				Some(ColorRgb(tone.0 / tone.3, tone.1 / tone.3, tone.2 / tone.3))
+			} else {
+				None
+			},
+			tone_area: tone.3,
+			tone_layer: tone_layer,
+			unexpanded: false,
+			has_unexpanded: has_unexpanded,
+			warnings: Vec::new(),
+		}
+	}
+
+	/// Create a struct that stands in for a cell which is not expanded.
+	fn unexpanded(name: Box<str>, extents: Extents) -> Struct {
+		let mut s = Struct::new(name, Vec::new(), Vec::new());
+		s.extents = extents;
+		s.unexpanded = true;
+		s
+	}
+
+	/// Check whether the struct or any of its instances has geometry on a
+	/// layer, given by its key.
+	fn has_layer(&self, layer_key: u16) -> bool {
+		self.layers.iter().any(|l| l.key == layer_key)
+	}
+}
+
+/// A placement of a prepared struct within another.
+struct Instance {
+	strukt: Arc<Struct>,
+	tx: Transform,
+	/// The extents of the placed struct in the parent's coordinates.
+	extents: Extents,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Point {
+	pub x: f64,
+	pub y: f64,
+}
+
+impl std::ops::Sub for Point {
+	type Output = Vector;
+	fn sub(self, rhs: Point) -> Vector {
+		Vector { x: self.x - rhs.x, y: self.y - rhs.y }
+	}
+}
+
+impl std::ops::Add<Vector> for Point {
+	type Output = Point;
+	fn add(self, rhs: Vector) -> Point {
+		Point { x: self.x + rhs.x, y: self.y + rhs.y }
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Vector {
+	pub x: f64,
+	pub y: f64,
+}
+
+impl std::ops::Mul<f64> for Vector {
+	type Output = Vector;
+	fn mul(self, rhs: f64) -> Vector {
+		Vector { x: self.x * rhs, y: self.y * rhs }
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+	va: Vector,
+	vb: Vector,
+	vt: Vector,
+}
+
+impl Transform {
+	fn identity() -> Transform {
+		Transform {
+			va: Vector { x: 1.0, y: 0.0 },
+			vb: Vector { x: 0.0, y: 1.0 },
+			vt: ZERO_VECTOR,
+		}
+	}
+
+	fn scale(&mut self, sx: f64, sy: f64) {
+		self.va.x *= sx;
+		self.va.y *= sy;
+		self.vb.x *= sx;
+		self.vb.y *= sy;
+		self.vt.x *= sx;
+		self.vt.y *= sy;
+	}
+
+	fn trans(&mut self, tx: f64, ty: f64) {
+		self.vt.x += tx;
+		self.vt.y += ty;
+	}
+
+	/// Rotate counter-clockwise by an angle in radians.
+	fn rotate(&mut self, angle: f64) {
+		let (sin, cos) = angle.sin_cos();
+		let rot = |v: Vector| Vector {
+			x: cos * v.x - sin * v.y,
+			y: sin * v.x + cos * v.y,
+		};
+		self.va = rot(self.va);
+		self.vb = rot(self.vb);
+		self.vt = rot(self.vt);
+	}
+
+	fn inverse(&self) -> Transform {
+		let det = self.va.x * self.vb.y - self.vb.x * self.va.y;
+		let mut inv = Transform {
+			va: Vector { x: self.vb.y / det, y: -self.va.y / det },
+			vb: Vector { x: -self.vb.x / det, y: self.va.x / det },
+			vt: ZERO_VECTOR,
+		};
+		let t = inv * self.vt;
+		inv.vt = Vector { x: -t.x, y: -t.y };
+		inv
+	}
+}
+
+/// Compose two transforms, such that `(a * b) * p == a * (b * p)`.
+impl std::ops::Mul<Transform> for Transform {
+	type Output = Transform;
+	fn mul(self, rhs: Transform) -> Transform {
+		let t = self * Point { x: rhs.vt.x, y: rhs.vt.y };
+		Transform {
+			va: self * rhs.va,
+			vb: self * rhs.vb,
+			vt: Vector { x: t.x, y: t.y },
+		}
+	}
+}
+
+impl std::ops::Mul<Vector> for Transform {
+	type Output = Vector;
+	fn mul(self, rhs: Vector) -> Vector {
+		Vector {
+			x: self.va.x * rhs.x + self.vb.x * rhs.y,
+			y: self.va.y * rhs.x + self.vb.y * rhs.y,
+		}
+	}
+}
+
+impl std::ops::Mul<Point> for Transform {
+	type Output = Point;
+	fn mul(self, rhs: Point) -> Point {
+		Point {
+			x: self.va.x * rhs.x + self.vb.x * rhs.y + self.vt.x,
+			y: self.va.y * rhs.x + self.vb.y * rhs.y + self.vt.y,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rect {
+	pub min: Point,
+	pub max: Point,
+}
+
+impl Rect {
+	fn intersects(&self, other: &Rect) -> bool {
+		self.min.x <= other.max.x && other.min.x <= self.max.x &&
+		self.min.y <= other.max.y && other.min.y <= self.max.y
+	}
+
+	fn union(&self, other: &Rect) -> Rect {
+		Rect {
+			min: Point { x: self.min.x.min(other.min.x), y: self.min.y.min(other.min.y) },
+			max: Point { x: self.max.x.max(other.max.x), y: self.max.y.max(other.max.y) },
+		}
+	}
+
+	/// The corners of the rectangle as a closed ring, where the last point
+	/// repeats the first.
+	fn ring(&self) -> [Point; 5] {
+		let (a, b) = (self.min, self.max);
+		[a, Point { x: b.x, y: a.y }, b, Point { x: a.x, y: b.y }, a]
+	}
+
+	/// Calculate the bounding box of the rectangle after a transformation.
+	fn transformed(&self, tx: Transform) -> Rect {
+		let mut e = Extents::new();
+		e.add_point(&(tx * self.min));
+		e.add_point(&(tx * self.max));
+		e.add_point(&(tx * Point { x: self.min.x, y: self.max.y }));
+		e.add_point(&(tx * Point { x: self.max.x, y: self.min.y }));
+		e.rect
+	}
+}
+
+const ZERO_POINT: Point = Point { x: 0.0, y: 0.0 };
+pub const ZERO_VECTOR: Vector = Vector { x: 0.0, y: 0.0 };
+const ZERO_RECT: Rect = Rect { min: ZERO_POINT, max: ZERO_POINT };
+
+struct Boundary {
+	layer: Arc<Layer>,
+	datatype: u16,
+	points: Vec<Point>,
+	holes: Vec<Vec<Point>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Extents {
+	rect: Rect,
+	empty: bool,
+}
+
+impl Extents {
+	fn new() -> Extents {
+		Extents {
+			rect: ZERO_RECT,
+			empty: true,
+		}
+	}
+
+	fn add_point(&mut self, p: &Point) {
+		if self.empty {
+			self.rect = Rect { min: *p, max: *p };
+			self.empty = false;
+		} else {
+			if self.rect.min.x > p.x {
+				self.rect.min.x = p.x;
+			}
+			if self.rect.min.y > p.y {
+				self.rect.min.y = p.y;
+			}
+			if self.rect.max.x < p.x {
+				self.rect.max.x = p.x;
+			}
+			if self.rect.max.y < p.y {
+				self.rect.max.y = p.y;
+			}
+		}
+	}
+
+	fn add_extents(&mut self, other: &Extents, offset: Vector) {
+		if !other.empty {
+			self.add_point(&(other.rect.min + offset));
+			self.add_point(&(other.rect.max + offset));
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ColorRgb {
+	pub r: f64,
+	pub g: f64,
+	pub b: f64,
+}
+
+impl ColorRgb {
+	/// Format the color as `#rrggbb`.
+	fn to_hex(&self) -> String {
+		let ch = |v: f64| (v.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+		format!("#{:02x}{:02x}{:02x}", ch(self.r), ch(self.g), ch(self.b))
+	}
+
+	/// Blend the color towards another one. An amount of 0 leaves the color
+	/// untouched, 1 replaces it.
+	fn mix(&self, other: ColorRgb, amount: f64) -> ColorRgb {
+		ColorRgb(
+			self.r + (other.r - self.r) * amount,
+			self.g + (other.g - self.g) * amount,
+			self.b + (other.b - self.b) * amount,
+		)
+	}
+}
+
+#[allow(non_snake_case)]
+fn ColorRgb(r: f64, g: f64, b: f64) -> ColorRgb {
+	ColorRgb {
+		r: r,
+		g: g,
+		b: b,
+	}
+}
+
+struct Layer {
+	/// The GDS layer number, or the ID of a derived layer.
+	id: u16,
+	/// Identifies the layer among those of a plot. Equal to the ID, except for
+	/// layers styled by a scope, which are separate from the layer they
+	/// restyle.
+	key: u16,
+	order: i32,
+	style: LayerClass,
+}
+
+impl std::cmp::PartialEq for Layer {
+	fn eq(&self, other: &Layer) -> bool {
+		self.key == other.key
+	}
+}
+
+
+fn prepare(ctx: &Context, strukt: gds::Struct) -> Result<Struct, Error> {
+	let mut prep = Preparer {
+		ctx: ctx,
+		prepared: BTreeMap::new(),
+		layers: BTreeMap::new(),
+		bounds: BTreeMap::new(),
+		depth: 0,
+		scopes: ctx.enter_scopes(&vec![0; ctx.style_scopes.len()], &strukt.get_name()),
+		expanding: vec![strukt.get_name()],
+		warnings: Vec::new(),
+	};
+
+	let mut boundaries = Vec::new();
+	let mut instances = Vec::new();
+	try!(prep.collect(&strukt, &mut boundaries, &mut instances));
+
+	// Boolean operations cannot be carried out hierarchically. Flatten the
+	// layers involved in derived and merged layers and add the result to the
+	// top-level struct.
+	if !ctx.derived.is_empty() || ctx.merge_all || !ctx.merge_layers.is_empty() {
+		let mut wanted = HashSet::new();
+		for &(_, ref expr) in &ctx.derived {
+			expr.collect_layers(&mut wanted);
+		}
+		for &id in &ctx.merge_layers {
+			wanted.insert(id);
+		}
+		let mut polygons = BTreeMap::<u16, Vec<Vec<Point>>>::new();
+		try!(flatten(ctx, &strukt, Transform::identity(), &|id| ctx.merge_all || wanted.contains(&id), &mut vec![strukt.get_name()], &mut polygons));
+
+		// Evaluate the derived layers in the order they were defined, such
+		// that they may refer to each other.
+		let mut results = BTreeMap::<u16, Vec<boolean::Polygon>>::new();
+		if !ctx.derived.is_empty() {
+			let mut bbox = Extents::new();
+			for rings in polygons.values() {
+				for ring in rings {
+					for p in ring {
+						bbox.add_point(p);
+					}
+				}
+			}
+			for &(id, ref expr) in &ctx.derived {
+				let result = eval_layer_expr(expr, &polygons, &results, &bbox);
+				results.insert(id, result);
+			}
+		}
+
+		// Merge the remaining layers.
+		for (id, rings) in polygons {
+			if !results.contains_key(&id) && ctx.is_merged(id) {
+				let rings: Vec<Vec<Point>> = rings.into_iter().map(boolean::ccw).collect();
+				results.insert(id, boolean::merge(&rings));
+			}
+		}
+
+		for (id, polys) in results {
+			if !ctx.is_plotted(id) {
+				continue;
+			}
+			let layer = prep.get_layer(id);
+			for poly in polys {
+				boundaries.push(Boundary {
+					layer: layer.clone(),
+					datatype: 0,
+					points: close_ring(poly.outline),
+					holes: poly.holes.into_iter().map(close_ring).collect(),
+				});
+			}
+		}
+	}
+
+	let mut s = Struct::new(strukt.get_name().into_boxed_str(), boundaries, instances);
+	s.warnings = prep.warnings;
+	Ok(s)
+}
+
+/// The state shared while preparing a struct and its hierarchy.
+struct Preparer<'a, 'b: 'a> {
+	ctx: &'a Context<'b>,
+	/// The sub-cells prepared so far, such that each is only prepared once no
+	/// matter how often it is instantiated. With a depth limit, cells are
+	/// prepared once for each level they appear on down to the limit.
+	prepared: BTreeMap<(Box<str>, usize, Vec<usize>), Arc<Struct>>,
+	/// The layers, along with the style scopes that apply to them.
+	layers: BTreeMap<(u16, Vec<usize>), Arc<Layer>>,
+	/// The extents of the cells below the depth limit.
+	bounds: BTreeMap<Box<str>, Extents>,
+	/// The hierarchy level of the struct being collected.
+	depth: usize,
+	/// The progress of each style scope at the struct being collected.
+	scopes: Vec<usize>,
+	/// The names of the cells being expanded, from the top down, used to
+	/// detect cells which instantiate themselves.
+	expanding: Vec<String>,
+	/// The problems found so far.
+	warnings: Vec<String>,
+}
+
+impl<'a, 'b> Preparer<'a, 'b> {
+	fn get_layer(&mut self, layer_id: u16) -> Arc<Layer> {
+		let key = (layer_id, self.ctx.get_layer_scopes(layer_id, &self.scopes));
+		if let Some(l) = self.layers.get(&key) {
+			return l.clone();
+		}
+		let style = self.ctx.get_scoped_layer_style(layer_id, &key.1);
+		// println!("- create layer with style {:?}", style);
+
+		// Layers styled by a scope are assigned keys counting down below the
+		// IDs of the derived layers, and share the ID and order of the layer
+		// they override.
+		let layer_key = if key.1.is_empty() {
+			layer_id
+		} else {
+			let scoped = self.layers.keys().filter(|k| !k.1.is_empty()).count();
+			std::u16::MAX - (self.ctx.derived.len() + scoped) as u16
+		};
+		let l = Arc::new(Layer {
+			id: layer_id,
+			key: layer_key,
+			order: match self.ctx.orders.get(&layer_id) {
+				Some(v) => *v,
+				None => layer_id as i32,
+			},
+			style: style,
+		});
+		self.layers.insert(key, l.clone());
+		l
+	}
+
+	fn prepare_struct(&mut self, strukt: &gds::Struct) -> Result<Struct, Error> {
+		let mut boundaries = Vec::new();
+		let mut instances = Vec::new();
+		try!(check_recursion(&self.expanding, &strukt.get_name()));
+		self.expanding.push(strukt.get_name());
+		let result = self.collect(strukt, &mut boundaries, &mut instances);
+		self.expanding.pop();
+		try!(result);
+		Ok(Struct::new(strukt.get_name().into_boxed_str(), boundaries, instances))
+	}
+
+	/// Prepare a struct referenced from the struct being collected, unless
+	/// already prepared for the same level. Structs below the depth limit or
+	/// blackboxed are not expanded, and hidden ones are skipped.
+	fn prepare_child(&mut self, name: String, parent: &gds::Struct) -> Result<Option<Arc<Struct>>, Error> {
+		let ctx = self.ctx;
+		let rule = ctx.get_cell_rule(&name);
+		if rule == Some(CellRule::Hide) {
+			return Ok(None);
+		}
+		let level = match ctx.depth {
+			Some(n) => (self.depth + 1).min(n + 1),
+			None => 0,
+		};
+		let scopes = ctx.enter_scopes(&self.scopes, &name);
+		let key = (name.into_boxed_str(), level, scopes);
+		if let Some(c) = self.prepared.get(&key) {
+			return Ok(Some(c.clone()));
+		}
+		let s = match ctx.lib.find_struct(&key.0) {
+			Some(s) => s,
+			None => {
+				let msg = format!("Unable to find cell {} referenced in {}", key.0, parent.get_name());
+				if !self.warnings.contains(&msg) {
+					self.warnings.push(msg);
+				}
+				return Ok(None);
+			}
+		};
+		let below_limit = ctx.depth.map_or(false, |n| level > n) && rule != Some(CellRule::Expand);
+		let c = if below_limit || rule == Some(CellRule::Blackbox) {
+			let extents = try!(self.bounds(&s));
+			Struct::unexpanded(key.0.clone(), extents)
+		} else {
+			let scopes = std::mem::replace(&mut self.scopes, key.2.clone());
+			self.depth += 1;
+			let c = self.prepare_struct(&s);
+			self.depth -= 1;
+			self.scopes = scopes;
+			try!(c)
+		};
+		let c = Arc::new(c);
+		self.prepared.insert(key, c.clone());
+		Ok(Some(c))
+	}
+
+	/// Calculate the extents of a struct and all its instances without
+	/// preparing them.
+	fn bounds(&mut self, strukt: &gds::Struct) -> Result<Extents, Error> {
+		let name = strukt.get_name().into_boxed_str();
+		if let Some(&e) = self.bounds.get(&name) {
+			return Ok(e);
+		}
+		try!(check_recursion(&self.expanding, &name));
+		self.expanding.push(name.to_string());
+		let result = self.struct_bounds(strukt);
+		self.expanding.pop();
+		let extents = try!(result);
+		self.bounds.insert(name, extents);
+		Ok(extents)
+	}
+
+	fn struct_bounds(&mut self, strukt: &gds::Struct) -> Result<Extents, Error> {
+		let ctx = self.ctx;
+		let mut extents = Extents::new();
+		for elem in strukt.elems() {
+			match elem.get_kind() {
+				gds::ElemKind::Boundary => {
+					if ctx.is_plotted(elem.get_layer()) {
+						let mut shape = Extents::new();
+						for xy in elem.get_xy() {
+							shape.add_point(&Point {
+								x: xy.x as f64 * ctx.lib_units,
+								y: xy.y as f64 * ctx.lib_units,
+							});
+						}
+						if !shape.empty {
+							for d in copies(ctx, &elem) {
+								extents.add_point(&(shape.rect.min + d));
+								extents.add_point(&(shape.rect.max + d));
+							}
+						}
+					}
+				},
+				gds::ElemKind::Sref | gds::ElemKind::Aref => {
+					let name = elem.get_sname();
+					if ctx.get_cell_rule(&name) == Some(CellRule::Hide) {
+						continue;
+					}
+					if let Some(s) = ctx.lib.find_struct(name.as_str()) {
+						let e = try!(self.bounds(&s));
+						if !e.empty {
+							for tx in placements(ctx, &elem) {
+								let r = e.rect.transformed(tx);
+								extents.add_point(&r.min);
+								extents.add_point(&r.max);
+							}
+						}
+					}
+				},
+				_ => ()
+			}
+		}
+		Ok(extents)
+	}
+
+	/// Collect the elements of a struct, preparing referenced structs as
+	/// needed.
+	fn collect(&mut self, strukt: &gds::Struct, boundaries: &mut Vec<Boundary>, instances: &mut Vec<Instance>) -> Result<(), Error> {
+		let ctx = self.ctx;
+		for elem in strukt.elems() {
+			// println!("- found a {:?} on layer {}:{}", elem.get_kind(), elem.get_layer(), elem.get_type());
+			match elem.get_kind() {
+				gds::ElemKind::Boundary => {
+					// Merged layers are flattened and added to the top-level
+					// struct separately.
+					let layer_id = elem.get_layer();
+					if !ctx.is_plotted(layer_id) || ctx.is_merged(layer_id) {
+						continue;
+					}
+					let layer = self.get_layer(layer_id);
+					for d in copies(ctx, &elem) {
+						let pts = elem.get_xy().iter().map(|xy| Point {
+							x: xy.x as f64 * ctx.lib_units + d.x,
+							y: xy.y as f64 * ctx.lib_units + d.y,
+						}).collect();
+						boundaries.push(Boundary {
+							layer: layer.clone(),
+							datatype: elem.get_type(),
+							points: pts,
+							holes: Vec::new(),
+						});
+					}
+				},
+				gds::ElemKind::Sref | gds::ElemKind::Aref => {
+					let child = match try!(self.prepare_child(elem.get_sname(), strukt)) {
+						Some(c) => c,
+						None => continue,
+					};
+					for tx in placements(ctx, &elem) {
+						let mut extents = child.extents;
+						if !extents.empty {
+							extents.rect = extents.rect.transformed(tx);
+						}
+						instances.push(Instance {
+							strukt: child.clone(),
+							tx: tx,
+							extents: extents,
+						});
+					}
+				},
+				_ => ()
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Fail if a cell is about to be expanded within itself, given the names of
+/// the cells being expanded.
+fn check_recursion(expanding: &[String], name: &str) -> Result<(), Error> {
+	if expanding.iter().any(|n| n == name) {
+		let path: Vec<&str> = expanding.iter().map(|n| n.as_str()).chain(Some(name)).collect();
+		return Err(Error::Invalid(format!("Cell {} instantiates itself through {}", name, path.join("/"))));
+	}
+	Ok(())
+}
+
+/// Calculate the transforms with which an SREF or AREF element places the
+/// referenced struct. The transforms are produced one at a time, such that
+/// large arrays need not be held in memory.
+fn placements<'a>(ctx: &Context, elem: &'a gds::Elem) -> Box<Iterator<Item = Transform> + 'a> {
+	let xy: Vec<Point> = elem.get_xy().iter().map(|xy| Point {
+		x: xy.x as f64 * ctx.lib_units,
+		y: xy.y as f64 * ctx.lib_units,
+	}).collect();
+	if xy.is_empty() {
+		return Box::new(None.into_iter());
+	}
+
+	// Reflect about the x axis, then magnify, then rotate.
+	let mut base = Transform::identity();
+	if let Some(strans) = elem.get_strans() {
+		if strans.flags & 0x8000 != 0 {
+			base.scale(1.0, -1.0);
+		}
+		if strans.mag != 0.0 {
+			base.scale(strans.mag, strans.mag);
+		}
+		base.rotate(strans.angle.to_radians());
+	}
+
+	match elem.get_kind() {
+		gds::ElemKind::Aref if xy.len() >= 3 => {
+			let (cols, rows) = elem.get_colrow();
+			let (cols, rows) = (cols.max(1), rows.max(1));
+			let dc = (xy[1] - xy[0]) * (1.0 / cols as f64);
+			let dr = (xy[2] - xy[0]) * (1.0 / rows as f64);
+			let origin = xy[0];
+			Box::new((0..rows as usize * cols as usize).map(move |i| {
+				let (c, r) = (i % cols as usize, i / cols as usize);
+				let p = origin + dc * c as f64 + dr * r as f64;
+				let mut tx = base;
+				tx.trans(p.x, p.y);
+				tx
+			}))
+		},
+		_ => {
+			let origin = xy[0];
+			Box::new(copies(ctx, elem).map(move |d| {
+				let mut tx = base;
+				tx.trans(origin.x + d.x, origin.y + d.y);
+				tx
+			}))
+		}
+	}
+}
+
+/// The offsets of the copies of a repeated element, which is placed once if it
+/// is not repeated. Like placements, the copies are produced one at a time.
+#[cfg(feature = "gdsii")]
+fn copies<'a>(ctx: &Context, elem: &'a gds::Elem) -> Box<Iterator<Item = Vector> + 'a> {
+	let units = ctx.lib_units;
+	match elem.get_repetition() {
+		Some(rep) => Box::new((0..rep.len()).map(move |i| {
+			let d = rep.offset(i);
+			Vector { x: d.x as f64 * units, y: d.y as f64 * units }
+		})),
+		None => Box::new(Some(Vector { x: 0.0, y: 0.0 }).into_iter()),
+	}
+}
+
+/// The libgds reader knows no repetitions other than array references.
+#[cfg(not(feature = "gdsii"))]
+fn copies<'a>(_: &Context, _: &'a gds::Elem) -> Box<Iterator<Item = Vector> + 'a> {
+	Box::new(Some(Vector { x: 0.0, y: 0.0 }).into_iter())
+}
+
+/// Collect the polygons on the wanted layers of a struct and all its
+/// instances, transformed into the top-level coordinates.
+fn flatten(ctx: &Context, strukt: &gds::Struct, tx: Transform, wanted: &Fn(u16) -> bool, expanding: &mut Vec<String>, into: &mut BTreeMap<u16, Vec<Vec<Point>>>) -> Result<(), Error> {
+	for elem in strukt.elems() {
+		match elem.get_kind() {
+			gds::ElemKind::Boundary => {
+				let layer_id = elem.get_layer();
+				if !wanted(layer_id) {
+					continue;
+				}
+				let polygons = into.entry(layer_id).or_insert_with(Vec::new);
+				for d in copies(ctx, &elem) {
+					polygons.push(elem.get_xy().iter().map(|xy| tx * Point {
+						x: xy.x as f64 * ctx.lib_units + d.x,
+						y: xy.y as f64 * ctx.lib_units + d.y,
+					}).collect());
+				}
+			},
+			gds::ElemKind::Sref | gds::ElemKind::Aref => {
+				// Hidden and blackboxed cells contribute no geometry.
+				let name = elem.get_sname();
+				match ctx.get_cell_rule(&name) {
+					Some(CellRule::Hide) | Some(CellRule::Blackbox) => continue,
+					_ => (),
+				}
+				if let Some(s) = ctx.lib.find_struct(name.as_str()) {
+					try!(check_recursion(expanding, &name));
+					expanding.push(name);
+					for t in placements(ctx, &elem) {
+						try!(flatten(ctx, &s, tx * t, wanted, expanding, into));
+					}
+					expanding.pop();
+				}
+			},
+			_ => ()
+		}
+	}
+	Ok(())
+}
+
+/// Repeat the first point of a ring at its end, as is the convention for GDS
+/// boundaries.
+fn close_ring(mut ring: Vec<Point>) -> Vec<Point> {
+	if let Some(&p) = ring.first() {
+		ring.push(p);
+	}
+	ring
+}
+
+/// Evaluate a layer expression. Layers are looked up among the derived layers
+/// evaluated so far, and otherwise among the source polygons.
+fn eval_layer_expr(expr: &LayerExpr, polygons: &BTreeMap<u16, Vec<Vec<Point>>>, derived: &BTreeMap<u16, Vec<boolean::Polygon>>, bbox: &Extents) -> Vec<boolean::Polygon> {
+	match *expr {
+		LayerExpr::Layer(id) => match derived.get(&id) {
+			Some(polys) => polys.clone(),
+			None => boolean::merge(&eval_layer_rings(expr, polygons, derived, bbox)),
+		},
+		LayerExpr::Not(ref e) => {
+			if bbox.empty {
+				return Vec::new();
+			}
+			let r = &bbox.rect;
+			let outline = vec![
+				r.min,
+				Point { x: r.max.x, y: r.min.y },
+				r.max,
+				Point { x: r.min.x, y: r.max.y },
+			];
+			boolean::boolean(&[outline], &eval_layer_rings(e, polygons, derived, bbox), boolean::Op::AndNot)
+		},
+		LayerExpr::Binary(op, ref a, ref b) => boolean::boolean(
+			&eval_layer_rings(a, polygons, derived, bbox),
+			&eval_layer_rings(b, polygons, derived, bbox),
+			op
+		),
+		LayerExpr::Grow(ref e, d) => boolean::grow(&eval_layer_rings(e, polygons, derived, bbox), d),
+	}
+}
+
+/// Evaluate a layer expression into rings whose winding number is non-zero
+/// exactly within the area covered. The source polygons are oriented
+/// counter-clockwise, while derived layers keep their holes clockwise.
+fn eval_layer_rings(expr: &LayerExpr, polygons: &BTreeMap<u16, Vec<Vec<Point>>>, derived: &BTreeMap<u16, Vec<boolean::Polygon>>, bbox: &Extents) -> Vec<Vec<Point>> {
+	match *expr {
+		LayerExpr::Layer(id) => match (derived.get(&id), polygons.get(&id)) {
+			(Some(polys), _) => boolean::rings(polys),
+			(None, Some(rings)) => rings.iter().map(|r| boolean::ccw(r.clone())).collect(),
+			(None, None) => Vec::new(),
+		},
+		_ => boolean::rings(&eval_layer_expr(expr, polygons, derived, bbox)),
+	}
+}
+
+
+/// A prepared structure placed into a plot at an offset.
+pub struct Overlay<'a> {
+	pub strukt: &'a Struct,
+	pub offset: Vector,
+}
+
+/// Calculate the overall plot size and the transformation from layout to
+/// surface coordinates. Unless a window is given, the plot covers all
+/// overlays.
+fn plot_transform(ctx: &Context, overlays: &[Overlay]) -> (Transform, (i32, i32)) {
+	let mut extents = Extents::new();
+	match ctx.window {
+		Some(w) => {
+			extents.add_point(&w.min);
+			extents.add_point(&w.max);
+		},
+		None => for o in overlays {
+			extents.add_extents(&o.strukt.extents, o.offset);
+		},
+	}
+	let r = &extents.rect;
+	let phys_size = r.max - r.min;
+	let mut tx = Transform::identity();
+	tx.trans(-r.min.x, -r.min.y);
+	let plot_size = match ctx.scale {
+		ScaleMode::Resolution(ppm) => {
+			tx.scale(ppm,ppm);
+			let sz = tx * phys_size;
+			((sz.x + 0.5) as i32, (sz.y + 0.5) as i32)
+		},
+		ScaleMode::Size(w,h) => {
+			let fw = w as f64 / phys_size.x;
+			let fh = h as f64 / phys_size.y;
+			if fw < fh {
+				tx.scale(fw,fw);
+				(w, ((h as f64)/fw + 0.5) as i32)
+			} else {
+				tx.scale(fh,fh);
+				(((w as f64)/fh + 0.5) as i32, h)
+			}
+		},
+	};
+	tx.scale(1.0, -1.0);
+	tx.trans(0.0, plot_size.1 as f64);
+
+	let plot_size = (plot_size.0 + 2*ctx.margin, plot_size.1 + 2*ctx.margin);
+	tx.trans(ctx.margin as f64, ctx.margin as f64);
+
+	// println!("plotting struct of physical size {:?} onto {:?}", phys_size, plot_size);
+	(tx, plot_size)
+}
+
+impl<'a> Overlay<'a> {
+	/// Place a struct into a plot without an offset.
+	pub fn new(strukt: &'a Struct) -> Overlay<'a> {
+		Overlay {
+			strukt: strukt,
+			offset: ZERO_VECTOR,
+		}
+	}
+
+	/// Shift a plot transformation to the overlay's offset.
+	fn transform(&self, tx: Transform) -> Transform {
+		let mut otx = tx;
+		let d = tx * self.offset;
+		otx.trans(d.x, d.y);
+		otx
+	}
+}
+
+/// The formats a plot can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+	Png,
+	Svg,
+	/// A standalone interactive HTML page.
+	Html,
+	/// A directory with a tile pyramid and an HTML viewer.
+	Tiles,
+	/// A preview printed to the terminal.
+	Term,
+	/// An image shown in the terminal using a specific or the detected
+	/// graphics protocol.
+	Sixel,
+	Kitty,
+	Inline,
+	/// A directory with a grayscale mask per layer and datatype.
+	Masks,
+	/// A NumPy array with a mask per layer and datatype.
+	Npy,
+}
+
+impl Format {
+	pub fn from_name(name: &str) -> Option<Format> {
+		match name {
+			"png" => Some(Format::Png),
+			"svg" => Some(Format::Svg),
+			"html" => Some(Format::Html),
+			"tiles" => Some(Format::Tiles),
+			"term" => Some(Format::Term),
+			"sixel" => Some(Format::Sixel),
+			"kitty" => Some(Format::Kitty),
+			"inline" => Some(Format::Inline),
+			"masks" => Some(Format::Masks),
+			"npy" => Some(Format::Npy),
+			_ => None,
+		}
+	}
+
+	/// The extension of files written in the format.
+	pub fn extension(&self) -> &'static str {
+		match *self {
+			Format::Png => "png",
+			Format::Svg => "svg",
+			Format::Html => "html",
+			Format::Tiles => "tiles",
+			Format::Term | Format::Sixel | Format::Kitty | Format::Inline => "txt",
+			Format::Masks => "masks",
+			Format::Npy => "npy",
+		}
+	}
+}
+
+/// Write a plot in the given format.
+pub fn render(ctx: &Context, overlays: &[Overlay], format: Format, output_name: &str) -> Result<(), Error> {
+	try!(check_overlays_supported(ctx, overlays, format));
+	let result = match format {
+		Format::Png => plot(ctx, overlays, output_name),
+		Format::Svg => svg::write_svg(ctx, overlays, output_name),
+		Format::Html => html::write_html(ctx, overlays, output_name),
+		Format::Tiles => pyramid::write_pyramid(ctx, overlays, output_name),
+		Format::Term => term::write_term(ctx, overlays),
+		Format::Sixel | Format::Kitty | Format::Inline => {
+			let protocol = match format {
+				Format::Sixel => inline::Protocol::Sixel,
+				Format::Kitty => inline::Protocol::Kitty,
+				_ => inline::detect(),
+			};
+			inline::write_inline(ctx, overlays, protocol)
+		},
+		Format::Masks => masks::write_masks(ctx, overlays, output_name),
+		Format::Npy => masks::write_npy(ctx, overlays, output_name),
+	};
+	result.map_err(|e| match format {
+		Format::Term | Format::Sixel | Format::Kitty | Format::Inline => Error::Io(String::from("Unable to write preview"), e),
+		_ => Error::Io(format!("Unable to write {}", output_name), e),
+	})
+}
+
+/// Plot to an encoded PNG image in memory. Tiling is ignored; the whole plot
+/// is returned as one image.
+pub fn render_png(ctx: &Context, overlays: &[Overlay]) -> Result<Vec<u8>, Error> {
+	plot_png(ctx, overlays).map_err(|e| Error::Io(String::from("Unable to render PNG"), e))
+}
+
+/// Refuse to write a plot in a format which cannot draw the markers,
+/// annotations, or highlights that would appear on it, rather than leaving
+/// them out.
+fn check_overlays_supported(ctx: &Context, overlays: &[Overlay], format: Format) -> Result<(), Error> {
+	let name = match format {
+		Format::Svg => "SVG",
+		Format::Html => "HTML",
+		_ => return Ok(()),
+	};
+	let what = if ctx.markers.iter().any(|m| m.find_overlay(overlays).is_some()) {
+		"markers"
+	} else if !ctx.annotations.is_empty() {
+		"annotations"
+	} else if !ctx.highlights.is_empty() {
+		"highlights"
+	} else {
+		return Ok(());
+	};
+	Err(Error::Invalid(format!("{} output cannot draw {}, use PNG output instead", name, what)))
+}
+
+/// Plot to an SVG document in memory.
+pub fn render_svg(ctx: &Context, overlays: &[Overlay]) -> Result<Vec<u8>, Error> {
+	try!(check_overlays_supported(ctx, overlays, Format::Svg));
+	let mut data = Vec::new();
+	try!(svg::write_svg_to(&mut data, ctx, overlays).map_err(|e| Error::Io(String::from("Unable to render SVG"), e)));
+	Ok(data)
+}
+
+/// Plot to a buffer of 8 bit RGBA pixels, row by row, which are not
+/// premultiplied by alpha.
+pub fn render_rgba(ctx: &Context, overlays: &[Overlay]) -> Result<Image, Error> {
+	plot_rgba(ctx, overlays).map_err(|e| Error::Io(String::from("Unable to render image"), e))
+}
+
+/// An image with 8 bit RGBA pixels, row by row.
+pub struct Image {
+	pub width: usize,
+	pub height: usize,
+	pub rgba: Vec<u8>,
+}
+
+/// The size of a plot in pixels.
+pub fn plot_size(ctx: &Context, overlays: &[Overlay]) -> (i32, i32) {
+	plot_transform(ctx, overlays).1
+}
+
+/// Draw a plot onto a backend, which is expected to be of the size returned by
+/// `plot_size`.
+pub fn draw(ctx: &Context, overlays: &[Overlay], backend: &mut RenderBackend) {
+	let (tx, size) = plot_transform(ctx, overlays);
+	draw_plot(&RenderOptions::new(ctx), overlays, tx, size, backend);
+}
+
+/// Render the overlays and encode them as a PNG image.
+fn plot_png(ctx: &Context, overlays: &[Overlay]) -> io::Result<Vec<u8>> {
+	let (tx, size) = plot_transform(ctx, overlays);
+	let surface = try!(rasterize(&RenderOptions::new(ctx), overlays, tx, size));
+	let mut data = Vec::new();
+	try!(write_png(&surface, &mut data));
+	Ok(data)
+}
+
+/// Render the overlays and take the pixels from the image surface.
+fn plot_rgba(ctx: &Context, overlays: &[Overlay]) -> io::Result<Image> {
+	let (tx, size) = plot_transform(ctx, overlays);
+	let mut surface = try!(rasterize(&RenderOptions::new(ctx), overlays, tx, size));
+	let (width, height) = (surface.width().max(0) as usize, surface.height().max(0) as usize);
+	let stride = surface.stride() as usize;
+	let mut rgba = Vec::with_capacity(width * height * 4);
+	if width == 0 || height == 0 {
+		return Ok(Image { width: width, height: height, rgba: rgba });
+	}
+
+	// Cairo stores each pixel as a native endian 32 bit word with alpha in
+	// the top byte, and the colors premultiplied by alpha.
+	let data = try!(surface.data().map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+	for row in data.chunks(stride).take(height) {
+		for px in row[..width * 4].chunks(4) {
+			let v = u32::from_ne_bytes([px[0], px[1], px[2], px[3]]);
+			let a = v >> 24;
+			let channel = |c: u32| if a == 0 { 0 } else { ((c & 0xff) * 255 + a / 2) / a } as u8;
+			rgba.extend_from_slice(&[channel(v >> 16), channel(v >> 8), channel(v), a as u8]);
+		}
+	}
+	Ok(Image { width: width, height: height, rgba: rgba })
+}
+
+/// Encode an image surface as PNG.
+fn write_png<W: Write>(surface: &cairo::ImageSurface, w: &mut W) -> io::Result<()> {
+	surface.write_to_png(w).map_err(|e| match e {
+		cairo::IoError::Io(e) => e,
+		e => io::Error::new(io::ErrorKind::Other, e),
+	})
+}
+
+/// A directory for temporary files which only the current user can access. It
+/// is removed along with its contents when dropped.
+#[cfg(all(feature = "libgds", not(feature = "gdsii")))]
+struct TempDir(std::path::PathBuf);
+
+#[cfg(all(feature = "libgds", not(feature = "gdsii")))]
+impl TempDir {
+	/// Create a new directory. A directory that already exists is never
+	/// reused, since someone else may have prepared it; another name is tried
+	/// instead.
+	fn new() -> io::Result<TempDir> {
+		let mut attempts = 0;
+		loop {
+			let nanos = std::time::SystemTime::now()
+				.duration_since(std::time::UNIX_EPOCH)
+				.map(|d| d.subsec_nanos())
+				.unwrap_or(0);
+			let path = std::env::temp_dir().join(format!(
+				"gdsplot-{}-{}-{:08x}",
+				std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::SeqCst), nanos
+			));
+			match create_private_dir(&path) {
+				Ok(()) => return Ok(TempDir(path)),
+				Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 100 => attempts += 1,
+				Err(e) => return Err(e),
+			}
+		}
+	}
+}
+
+#[cfg(all(feature = "libgds", not(feature = "gdsii")))]
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
+
+#[cfg(all(unix, feature = "libgds", not(feature = "gdsii")))]
+fn create_private_dir(path: &std::path::Path) -> io::Result<()> {
+	use std::os::unix::fs::DirBuilderExt;
+	std::fs::DirBuilder::new().mode(0o700).create(path)
+}
+
+#[cfg(all(not(unix), feature = "libgds", not(feature = "gdsii")))]
+fn create_private_dir(path: &std::path::Path) -> io::Result<()> {
+	std::fs::DirBuilder::new().create(path)
+}
+
+/// Distinguishes temporary directories created concurrently.
+#[cfg(all(feature = "libgds", not(feature = "gdsii")))]
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The settings which affect how geometry is drawn. Unlike the context these
+/// can be shared across threads.
+#[derive(Debug, Clone)]
+struct RenderOptions {
+	bg_color: Option<ColorRgb>,
+	lod: Option<LevelOfDetail>,
+	markers: Vec<Arc<MarkerSet>>,
+	annotations: Vec<Arc<AnnotationSet>>,
+	highlights: Vec<Vec<String>>,
+	highlight_style: HighlightStyle,
+	unexpanded_style: LayerClass,
+}
+
+impl RenderOptions {
+	fn new(ctx: &Context) -> RenderOptions {
+		RenderOptions {
+			bg_color: ctx.bg_color,
+			lod: ctx.lod,
+			markers: ctx.markers.clone(),
+			annotations: ctx.annotations.clone(),
+			highlights: ctx.highlights.clone(),
+			highlight_style: ctx.highlight_style,
+			unexpanded_style: ctx.get_unexpanded_style(),
+		}
+	}
+}
+
+fn plot(ctx: &Context, overlays: &[Overlay], output_name: &str) -> io::Result<()> {
+	if let Some(tile) = ctx.tile {
+		return plot_tiled(ctx, overlays, output_name, tile);
+	}
+
+	let (tx, plot_size) = plot_transform(ctx, overlays);
+	let surface = try!(rasterize(&RenderOptions::new(ctx), overlays, tx, plot_size));
+
+	// Write the file.
+	write_png(&surface, &mut try!(File::create(output_name)))
+}
+
+/// Render the overlays onto a new image surface of the given size.
+fn rasterize(opts: &RenderOptions, overlays: &[Overlay], tx: Transform, size: (i32, i32)) -> io::Result<cairo::ImageSurface> {
+	let cairo_error = |e| io::Error::new(io::ErrorKind::Other, e);
+	let surface = try!(cairo::ImageSurface::create(cairo::Format::ARgb32, size.0, size.1).map_err(cairo_error));
+	// The backend has to be dropped before the pixels of the surface can be
+	// accessed.
+	{
+		let mut backend = try!(CairoBackend::new(&surface).map_err(cairo_error));
+		draw_plot(opts, overlays, tx, size, &mut backend);
+		try!(backend.status().map_err(cairo_error));
+	}
+	Ok(surface)
+}
+
+/// Draw the overlays onto a backend of the given size.
+fn draw_plot(opts: &RenderOptions, overlays: &[Overlay], tx: Transform, size: (i32, i32), backend: &mut RenderBackend) {
+	// Draw the background.
+	if let Some(bgc) = opts.bg_color {
+		backend.clear(bgc);
+	}
+
+	// Plot the overlays on top of each other. Only geometry within the
+	// visible area of the surface is emitted.
+	let view = Rect {
+		min: ZERO_POINT,
+		max: Point { x: size.0 as f64, y: size.1 as f64 },
+	};
+	// Everything but the highlighted instances is dimmed.
+	let emphasis = if opts.highlights.is_empty() {
+		FULL_EMPHASIS
+	} else {
+		Emphasis { alpha: 1.0 - opts.highlight_style.dim, tint: None }
+	};
+	for o in overlays {
+		plot_struct(opts, o.strukt, o.transform(tx), &view, backend, emphasis);
+	}
+	for o in overlays {
+		plot_unexpanded(opts, o.strukt, o.transform(tx), &view, backend);
+	}
+	if !opts.highlights.is_empty() {
+		for o in overlays {
+			plot_highlights(opts, o.strukt, o.transform(tx), &view, backend);
+		}
+	}
+
+	// Draw the annotations and markers on top.
+	for set in &opts.annotations {
+		plot_annotations(set, tx, backend);
+	}
+	for set in &opts.markers {
+		if let Some(o) = set.find_overlay(overlays) {
+			plot_markers(set, o.transform(tx), &view, backend);
+		}
+	}
+}
+
+/// Draw the markers of a database in the style of their categories. Markers
+/// too small to be seen are highlighted by a square around them.
+fn plot_markers(set: &MarkerSet, tx: Transform, view: &Rect, backend: &mut RenderBackend) {
+	for marker in &set.db.markers {
+		let r = marker.bbox.transformed(tx);
+		if !r.intersects(view) {
+			continue;
+		}
+		let style = &set.styles[&marker.category];
+
+		if let Some(fs) = style.get_fill_style() {
+			backend.set_style(&fs.style());
+			for shape in &marker.shapes {
+				match *shape {
+					markers::Shape::Polygon(ref rings) => if let Some((ring, holes)) = rings.split_first() {
+						backend.fill_polygon(ring, holes, tx);
+					},
+					markers::Shape::EdgePair((a, b), (c, d)) => backend.fill_polygon(&[a, b, c, d, a], &[], tx),
+					markers::Shape::Edge(..) => (),
+				}
+			}
+		}
+
+		if let Some(ss) = style.get_stroke_style() {
+			backend.set_style(&ss.style());
+			for shape in &marker.shapes {
+				match *shape {
+					markers::Shape::Polygon(ref rings) => for ring in rings {
+						backend.stroke_polyline(ring, true, tx);
+					},
+					markers::Shape::Edge(a, b) => backend.stroke_polyline(&[a, b], false, tx),
+					markers::Shape::EdgePair((a, b), (c, d)) => {
+						backend.stroke_polyline(&[a, b], false, tx);
+						backend.stroke_polyline(&[c, d], false, tx);
+					},
+				}
+			}
+			if r.max.x - r.min.x < 4.0 && r.max.y - r.min.y < 4.0 {
+				let c = Point { x: 0.5 * (r.min.x + r.max.x), y: 0.5 * (r.min.y + r.max.y) };
+				let square = Rect {
+					min: Point { x: c.x - 4.0, y: c.y - 4.0 },
+					max: Point { x: c.x + 4.0, y: c.y + 4.0 },
+				};
+				backend.stroke_polyline(&square.ring(), true, Transform::identity());
+			}
+		}
+	}
+}
+
+/// Draw annotations, given in plot coordinates, in their styles.
+fn plot_annotations(set: &AnnotationSet, tx: Transform, backend: &mut RenderBackend) {
+	use annotations::Kind;
+	for &(ref a, ref style) in &set.items {
+		let fs = style.get_fill_style();
+		let ss = style.get_stroke_style();
+
+		if a.kind == Kind::Text {
+			let c = match (fs, ss) {
+				(Some(fs), _) => (fs.color, fs.alpha),
+				(None, Some(ss)) => (ss.color, ss.alpha),
+				(None, None) => continue,
+			};
+			backend.set_style(&Style { alpha: c.1, ..Style::solid(c.0) });
+			backend.text(tx * a.points[0], a.size, &a.text);
+			continue;
+		}
+
+		// Outline the annotation in layout coordinates.
+		let ring = match a.kind {
+			Kind::Box => Rect { min: a.points[0], max: a.points[1] }.ring().to_vec(),
+			Kind::Circle => {
+				let c = a.points[0];
+				(0..65).map(|i| {
+					let phi = (i % 64) as f64 * std::f64::consts::PI / 32.0;
+					Point { x: c.x + a.radius * phi.cos(), y: c.y + a.radius * phi.sin() }
+				}).collect()
+			},
+			_ => Vec::new(),
+		};
+
+		if let Some(fs) = fs {
+			if !ring.is_empty() {
+				backend.set_style(&fs.style());
+				backend.fill_polygon(&ring, &[], tx);
+			}
+		}
+		if let Some(ss) = ss {
+			let stroke = ss.style();
+			backend.set_style(&stroke);
+			if ring.is_empty() {
+				backend.stroke_polyline(&a.points, false, tx);
+			} else {
+				backend.stroke_polyline(&ring, true, tx);
+			}
+
+			// Arrows get a filled head scaled to the line width.
+			if a.kind == Kind::Arrow {
+				let (p, q) = (tx * a.points[0], tx * a.points[1]);
+				let d = q - p;
+				let len = (d.x * d.x + d.y * d.y).sqrt();
+				if len > 0.0 {
+					let head = 4.0 * ss.width + 6.0;
+					let u = d * (head / len);
+					let n = Vector { x: -u.y * 0.4, y: u.x * 0.4 };
+					let b = q + u * -1.0;
+					backend.set_style(&Style { dashes: None, ..stroke });
+					backend.fill_polygon(&[q, b + n, b + n * -1.0, q], &[], Transform::identity());
+				}
+			}
+		}
+	}
+}
+
+/// Render the plot as a grid of tiles, each written to its own file. The tiles
+/// are rendered in parallel, each onto its own surface, such that memory use
+/// is bounded by the tile size rather than the plot size.
+fn plot_tiled(ctx: &Context, overlays: &[Overlay], output_name: &str, tile: (i32, i32)) -> io::Result<()> {
+	let (tx, plot_size) = plot_transform(ctx, overlays);
+	let opts = RenderOptions::new(ctx);
+	let cols = ((plot_size.0 + tile.0 - 1) / tile.0) as usize;
+	let rows = ((plot_size.1 + tile.1 - 1) / tile.1) as usize;
+	let stem = match output_name.rfind('.') {
+		Some(i) => &output_name[..i],
+		None => output_name,
+	};
+
+	let mut tiles = Vec::with_capacity(rows * cols);
+	for row in 0..rows {
+		for col in 0..cols {
+			let x0 = col as i32 * tile.0;
+			let y0 = row as i32 * tile.1;
+			let mut ttx = tx;
+			ttx.trans(-x0 as f64, -y0 as f64);
+			tiles.push(Tile {
+				tx: ttx,
+				size: (tile.0.min(plot_size.0 - x0), tile.1.min(plot_size.1 - y0)),
+				path: format!("{}_r{}_c{}.png", stem, row, col),
+			});
+		}
+	}
+	render_tiles(&opts, overlays, ctx.jobs, &tiles)
+}
+
+/// A part of a plot rendered to its own PNG file.
+struct Tile {
+	/// The transformation from layout to tile coordinates.
+	tx: Transform,
+	size: (i32, i32),
+	path: String,
+}
+
+/// Render tiles in parallel on a number of threads. The first tile that fails
+/// to render or write stops all threads.
+fn render_tiles(opts: &RenderOptions, overlays: &[Overlay], jobs: usize, tiles: &[Tile]) -> io::Result<()> {
+	let next = AtomicUsize::new(0);
+	let render_tile = |tile: &Tile| -> io::Result<()> {
+		let surface = try!(rasterize(opts, overlays, tile.tx, tile.size));
+		write_png(&surface, &mut try!(File::create(&tile.path)))
+	};
+	thread::scope(|scope| {
+		let workers: Vec<_> = (0..jobs.max(1).min(tiles.len())).map(|_| scope.spawn(|| loop {
+			let tile = match tiles.get(next.fetch_add(1, Ordering::SeqCst)) {
+				Some(t) => t,
+				None => return Ok(()),
+			};
+			if let Err(e) = render_tile(tile) {
+				next.store(tiles.len(), Ordering::SeqCst);
+				return Err(e);
+			}
+		})).collect();
+		workers.into_iter().map(|w| w.join().expect("tile renderer panicked")).collect()
+	})
+}
+
+
+/// Adjustments to the layer styles, used to set parts of a plot apart.
+#[derive(Debug, Clone, Copy)]
+struct Emphasis {
+	/// A factor applied to the opacity of fills and strokes.
+	alpha: f64,
+	/// A color to blend the layer colors towards, and by how much.
+	tint: Option<(ColorRgb, f64)>,
+}
+
+const FULL_EMPHASIS: Emphasis = Emphasis { alpha: 1.0, tint: None };
+
+impl Emphasis {
+	fn color(&self, c: ColorRgb) -> ColorRgb {
+		match self.tint {
+			Some((t, amount)) => c.mix(t, amount),
+			None => c,
+		}
+	}
+}
+
+fn plot_struct(opts: &RenderOptions, strukt: &Struct, tx: Transform, view: &Rect, backend: &mut RenderBackend, emphasis: Emphasis) {
+	for layer in &strukt.layers {
+		// Fill the geometry on this layer.
+		if let Some(fs) = layer.style.get_fill_style() {
+			let fill = Style::solid(emphasis.color(fs.color));
+			backend.begin_layer(fs.alpha * emphasis.alpha);
+			backend.set_style(&fill);
+			gather_geometry(opts, strukt, layer, tx, view, backend, Pass::Fill(&fill));
+			backend.end_layer();
+		}
+	}
+	for layer in &strukt.layers {
+		// Stroke the geometry on this layer.
+		if let Some(ss) = layer.style.get_stroke_style() {
+			let mut stroke = ss.style();
+			stroke.color = emphasis.color(ss.color);
+			stroke.alpha *= emphasis.alpha;
+			backend.set_style(&stroke);
+			gather_geometry(opts, strukt, layer, tx, view, backend, Pass::Stroke);
+		}
+	}
+}
+
+
+/// Draw the instances within a struct that are not expanded as boxes labeled
+/// with their cell name.
+fn plot_unexpanded(opts: &RenderOptions, strukt: &Struct, tx: Transform, view: &Rect, backend: &mut RenderBackend) {
+	let mut found = Vec::new();
+	find_unexpanded(strukt, tx, view, &mut found);
+	if found.is_empty() {
+		return;
+	}
+	let style = &opts.unexpanded_style;
+
+	if let Some(fs) = style.get_fill_style() {
+		backend.begin_layer(fs.alpha);
+		backend.set_style(&Style::solid(fs.color));
+		for &(s, stx) in &found {
+			backend.fill_polygon(&s.extents.rect.ring(), &[], stx);
+		}
+		backend.end_layer();
+	}
+	if let Some(ss) = style.get_stroke_style() {
+		backend.set_style(&ss.style());
+		for &(s, stx) in &found {
+			backend.stroke_polyline(&s.extents.rect.ring(), true, stx);
+		}
+
+		// Label the boxes large enough to hold the cell name.
+		for &(s, stx) in &found {
+			let r = s.extents.rect.transformed(stx);
+			if let Some(p) = label_position(&r, &s.name) {
+				backend.text(p, UNEXPANDED_FONT_SIZE, &s.name);
+			}
+		}
+	}
+}
+
+/// The font size of the cell names in boxes of unexpanded instances, in pixels.
+const UNEXPANDED_FONT_SIZE: f64 = 10.0;
+
+/// Find where to put the name of a cell into the top left corner of a box
+/// given in surface coordinates. Returns `None` if the name does not fit.
+fn label_position(r: &Rect, name: &str) -> Option<Point> {
+	let width = 0.6 * UNEXPANDED_FONT_SIZE * name.chars().count() as f64;
+	if r.max.x - r.min.x < width + 4.0 || r.max.y - r.min.y < UNEXPANDED_FONT_SIZE + 4.0 {
+		return None;
+	}
+	Some(Point { x: r.min.x + 2.0, y: r.min.y + 2.0 + UNEXPANDED_FONT_SIZE })
+}
+
+/// Collect the unexpanded instances within a struct that intersect the view.
+fn find_unexpanded<'a>(strukt: &'a Struct, tx: Transform, view: &Rect, into: &mut Vec<(&'a Struct, Transform)>) {
+	if !strukt.has_unexpanded {
+		return;
+	}
+	let window = view.transformed(tx.inverse());
+	strukt.placements.query(&window, |_, &i| {
+		let inst = &strukt.instances[i];
+		if inst.strukt.unexpanded {
+			into.push((&inst.strukt, tx * inst.tx));
+		} else {
+			find_unexpanded(&inst.strukt, tx * inst.tx, view, into);
+		}
+	});
+}
+
+/// Draw the highlighted instances within a struct on top of the dimmed plot,
+/// and outline them.
+fn plot_highlights(opts: &RenderOptions, strukt: &Struct, tx: Transform, view: &Rect, backend: &mut RenderBackend) {
+	let style = &opts.highlight_style;
+	let mut found = Vec::new();
+	let mut memo = HashMap::new();
+	for path in &opts.highlights {
+		match path.len() {
+			0 => (),
+			1 if cell_matches(&path[0], strukt) => found.push((strukt, tx)),
+			1 => find_cell_instances(&path[0], strukt, tx, &mut memo, &mut found),
+			_ if cell_matches(&path[0], strukt) => find_path_instances(&path[1..], strukt, tx, &mut found),
+			_ => (),
+		}
+	}
+
+	let emphasis = Emphasis {
+		alpha: 1.0,
+		tint: if style.tint > 0.0 { Some((style.color, style.tint)) } else { None },
+	};
+	for &(s, stx) in &found {
+		plot_struct(opts, s, stx, view, backend, emphasis);
+	}
+	if style.outline {
+		backend.set_style(&Style { width: 2.0, ..Style::solid(style.color) });
+		for &(s, stx) in &found {
+			backend.stroke_polyline(&s.extents.rect.ring(), true, stx);
+		}
+	}
+}
+
+/// Check whether a struct matches one element of a highlight path, which is
+/// either a cell name or `*` for any cell.
+fn cell_matches(pattern: &str, strukt: &Struct) -> bool {
+	pattern == "*" || pattern == &*strukt.name
+}
+
+/// Collect the instances of a cell anywhere below a struct. Only the
+/// outermost instances are collected.
+fn find_cell_instances<'a>(name: &str, strukt: &'a Struct, tx: Transform, memo: &mut HashMap<*const Struct, bool>, into: &mut Vec<(&'a Struct, Transform)>) {
+	for inst in &strukt.instances {
+		if cell_matches(name, &inst.strukt) {
+			into.push((&inst.strukt, tx * inst.tx));
+		} else if contains_cell(name, &inst.strukt, memo) {
+			find_cell_instances(name, &inst.strukt, tx * inst.tx, memo, into);
+		}
+	}
+}
+
+/// Check whether a cell is instantiated anywhere below a struct.
+fn contains_cell(name: &str, strukt: &Struct, memo: &mut HashMap<*const Struct, bool>) -> bool {
+	let key = strukt as *const Struct;
+	if let Some(&c) = memo.get(&key) {
+		return c;
+	}
+	let c = strukt.instances.iter().any(|i| cell_matches(name, &i.strukt) || contains_cell(name, &i.strukt, memo));
+	memo.insert(key, c);
+	c
+}
+
+/// Collect the instances reached by following a path of cell names down from
+/// a struct.
+fn find_path_instances<'a>(path: &[String], strukt: &'a Struct, tx: Transform, into: &mut Vec<(&'a Struct, Transform)>) {
+	for inst in &strukt.instances {
+		if cell_matches(&path[0], &inst.strukt) {
+			if path.len() == 1 {
+				into.push((&inst.strukt, tx * inst.tx));
+			} else {
+				find_path_instances(&path[1..], &inst.strukt, tx * inst.tx, into);
+			}
+		}
+	}
+}
+
+#[derive(Clone, Copy)]
+enum Pass<'a> {
+	/// Fill in the given style.
+	Fill(&'a Style),
+	Stroke,
+}
+
+/// Emit the geometry on a layer which intersects the view, which is given in
+/// surface coordinates.
+fn gather_geometry(opts: &RenderOptions, strukt: &Struct, layer: &Arc<Layer>, tx: Transform, view: &Rect, backend: &mut RenderBackend, pass: Pass) {
+	let window = view.transformed(tx.inverse());
+
+	// Boundaries.
+	if let Some(index) = strukt.shapes.get(&layer.key) {
+		index.query(&window, |bbox, &i| {
+			// Reduce shapes smaller than the detail threshold to a pixel.
+			if let Some(lod) = opts.lod {
+				let r = bbox.transformed(tx);
+				if r.max.x - r.min.x < lod.shapes && r.max.y - r.min.y < lod.shapes {
+					if !lod.drop {
+						fill_pixel(backend, 0.5 * (r.min.x + r.max.x), 0.5 * (r.min.y + r.max.y));
+					}
+					return;
+				}
+			}
+
+			let b = &strukt.boundaries[i];
+			match pass {
+				Pass::Fill(_) => backend.fill_polygon(&b.points, &b.holes, tx),
+				Pass::Stroke => {
+					backend.stroke_polyline(&b.points, true, tx);
+					for hole in &b.holes {
+						backend.stroke_polyline(hole, true, tx);
+					}
+				},
+			}
+		});
+	}
+
+	// Instances.
+	strukt.placements.query(&window, |bbox, &i| {
+		let inst = &strukt.instances[i];
+		if !inst.strukt.has_layer(layer.key) {
+			return;
+		}
+
+		// Draw instances smaller than the detail threshold as a box in the
+		// average color of the cell, once during the fill pass of its first
+		// filled layer.
+		if let Some(lod) = opts.lod {
+			let r = bbox.transformed(tx);
+			if r.max.x - r.min.x < lod.instances && r.max.y - r.min.y < lod.instances {
+				if let (Pass::Fill(fill), Some(tone)) = (pass, inst.strukt.tone) {
+					if inst.strukt.tone_layer == Some(layer.key) {
+						backend.set_style(&Style::solid(tone));
+						fill_device_rect(backend, &r);
+						backend.set_style(fill);
+					}
+				}
+				return;
+			}
+		}
+
+		gather_geometry(opts, &inst.strukt, layer, tx * inst.tx, view, backend, pass);
+	});
+}
+
+/// Fill the pixel of the surface that contains a point.
+fn fill_pixel(backend: &mut RenderBackend, x: f64, y: f64) {
+	let (x, y) = (x.floor(), y.floor());
+	fill_device_rect(backend, &Rect {
+		min: Point { x: x, y: y },
+		max: Point { x: x + 1.0, y: y + 1.0 },
+	});
+}
+
+/// Fill a rectangle given in surface coordinates, widened to cover at least
+/// one pixel.
+fn fill_device_rect(backend: &mut RenderBackend, r: &Rect) {
+	let x0 = r.min.x.floor();
+	let y0 = r.min.y.floor();
+	let widened = Rect {
+		min: Point { x: x0, y: y0 },
+		max: Point { x: r.max.x.ceil().max(x0 + 1.0), y: r.max.y.ceil().max(y0 + 1.0) },
+	};
+	backend.fill_polygon(&widened.ring(), &[], Transform::identity());
+}
+
+
+#[cfg(all(test, feature = "gdsii"))]
+mod tests {
+	use super::*;
+
+	/// A 10 µm square on layer 1.
+	fn square() -> gds::Elem {
+		let mut elem = gds::Elem::new(gds::ElemKind::Boundary);
+		elem.layer = 1;
+		elem.xy = [(0, 0), (10000, 0), (10000, 10000), (0, 10000), (0, 0)].iter()
+			.map(|&(x, y)| gds::Xy { x: x, y: y })
+			.collect();
+		elem
+	}
+
+	/// A library with a cell `TOP` holding a square.
+	fn square_library() -> Library {
+		Library::new(String::from("LIB"), (1e-3, 1e-9), vec![(String::from("TOP"), vec![square()])])
+	}
+
+	fn load_style(ctx: &mut Context, sheet: &str) {
+		read_stylesheet(ctx, sheet.as_bytes(), "test.style").unwrap();
+	}
+
+	#[test]
+	fn render_in_memory() {
+		let lib = square_library();
+		let mut ctx = Context::new(&lib);
+		load_style(&mut ctx, "alias 1 m1 m1\nfill m1 color #ff8000 alpha 0.5 pattern solid\nresolution 1e6");
+		let top = prepare_cell(&ctx, "TOP").unwrap();
+		let overlays = [Overlay::new(&top)];
+
+		let png = render_png(&ctx, &overlays).unwrap();
+		assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+		// The pixels are returned without premultiplied alpha.
+		let image = render_rgba(&ctx, &overlays).unwrap();
+		assert_eq!((image.width, image.height), (10, 10));
+		assert_eq!(image.rgba.len(), 10 * 10 * 4);
+		let px = &image.rgba[(5 * 10 + 5) * 4..][..4];
+		assert_eq!(px[0], 255);
+		assert!((px[1] as i32 - 128).abs() <= 2, "green {}", px[1]);
+		assert_eq!(px[2], 0);
+		assert!((px[3] as i32 - 128).abs() <= 1, "alpha {}", px[3]);
+	}
+
+	#[test]
+	fn svg_refuses_overlays() {
+		let lib = square_library();
+		let mut ctx = Context::new(&lib);
+		let top = prepare_cell(&ctx, "TOP").unwrap();
+		let svg = render_svg(&ctx, &[Overlay::new(&top)]).unwrap();
+		assert!(svg.starts_with(b"<?xml"));
+
+		ctx.add_highlight("TOP");
+		match render_svg(&ctx, &[Overlay::new(&top)]) {
+			Err(Error::Invalid(ref msg)) => assert!(msg.contains("cannot draw highlights"), "{}", msg),
+			r => panic!("unexpected {:?}", r.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn zero_size_window() {
+		let lib = square_library();
+		let mut ctx = Context::new(&lib);
+		match read_stylesheet(&mut ctx, "window 0 0 0 10".as_bytes(), "test.style") {
+			Err(Error::Stylesheet(_, 1, ref msg)) => assert!(msg.contains("nonzero"), "{}", msg),
+			r => panic!("unexpected {:?}", r),
+		}
+	}
+
+	#[test]
+	fn html_honours_window() {
+		let mut sref = gds::Elem::new(gds::ElemKind::Sref);
+		sref.sname = String::from("SUB");
+		sref.xy = vec![gds::Xy { x: 20000, y: 0 }];
+		let lib = Library::new(String::from("LIB"), (1e-3, 1e-9), vec![
+			(String::from("TOP"), vec![square(), sref]),
+			(String::from("SUB"), vec![square()]),
+		]);
+		let mut ctx = Context::new(&lib);
+		load_style(&mut ctx, "alias 1 m1 m1\nfill m1 color #0000ff");
+		let top = prepare_cell(&ctx, "TOP").unwrap();
+		let path = std::env::temp_dir().join(format!("gdsplot-test-{}.html", std::process::id()));
+		let name = path.to_str().unwrap();
+		let count_paths = || {
+			let mut html = String::new();
+			File::open(&path).unwrap().read_to_string(&mut html).unwrap();
+			html.matches("<path d=").count()
+		};
+
+		render(&ctx, &[Overlay::new(&top)], Format::Html, name).unwrap();
+		assert_eq!(count_paths(), 2);
+		ctx.set_window(Some(Rect { min: Point { x: 0.0, y: 0.0 }, max: Point { x: 5e-6, y: 5e-6 } }));
+		render(&ctx, &[Overlay::new(&top)], Format::Html, name).unwrap();
+		assert_eq!(count_paths(), 1);
+		std::fs::remove_file(&path).unwrap();
+
+		ctx.add_highlight("TOP");
+		match render(&ctx, &[Overlay::new(&top)], Format::Html, name) {
+			Err(Error::Invalid(ref msg)) => assert!(msg.contains("HTML output cannot draw highlights"), "{}", msg),
+			r => panic!("unexpected {:?}", r),
+		}
+	}
+
+	#[test]
+	fn scoped_style_keeps_layer_id() {
+		let mut sref = gds::Elem::new(gds::ElemKind::Sref);
+		sref.sname = String::from("SUB");
+		sref.xy = vec![gds::Xy { x: 20000, y: 0 }];
+		let lib = Library::new(String::from("LIB"), (1e-3, 1e-9), vec![
+			(String::from("TOP"), vec![square(), sref]),
+			(String::from("SUB"), vec![square()]),
+		]);
+		let mut ctx = Context::new(&lib);
+		load_style(&mut ctx, "alias 1 m1 m1\nfill m1 color #0000ff\nin cell SUB { fill m1 color #ff0000 }");
+		let top = prepare_cell(&ctx, "TOP").unwrap();
+
+		// The restyled layer is told apart by its key, but still reports the
+		// layer number for naming.
+		assert_eq!(top.layers.len(), 2);
+		assert!(top.layers.iter().all(|l| l.id == 1));
+		assert!(top.layers[0].key != top.layers[1].key);
+	}
+
+	#[test]
+	fn derived_layers_keep_holes() {
+		let rect = |layer: u16, (x0, y0, x1, y1): (i32, i32, i32, i32)| {
+			let mut elem = gds::Elem::new(gds::ElemKind::Boundary);
+			elem.layer = layer;
+			elem.xy = [(x0, y0), (x1, y0), (x1, y1), (x0, y1), (x0, y0)].iter()
+				.map(|&(x, y)| gds::Xy { x: x, y: y })
+				.collect();
+			elem
+		};
+		let lib = Library::new(String::from("LIB"), (1e-3, 1e-9), vec![(String::from("TOP"), vec![
+			rect(1, (0, 0, 10000, 10000)),
+			rect(2, (3000, 3000, 7000, 7000)),
+			rect(3, (0, 0, 10000, 10000)),
+		])]);
+		let mut ctx = Context::new(&lib);
+		load_style(&mut ctx, "derive ring = 1 & !2\nderive x = ring & 3\nderive big = grow(ring, 1)\nderive small = shrink(ring, 1)\nderive same = ring");
+		let top = prepare_cell(&ctx, "TOP").unwrap();
+
+		// The holes and filled area in square micrometers of a derived layer.
+		let layer = |name: &str| {
+			let id = ctx.aliases[name];
+			let shapes: Vec<&Boundary> = top.boundaries.iter().filter(|b| b.layer.id == id).collect();
+			let holes: usize = shapes.iter().map(|b| b.holes.len()).sum();
+			let area: f64 = shapes.iter().map(|b| {
+				boolean::signed_area(&b.points).abs() - b.holes.iter().map(|h| boolean::signed_area(h).abs()).sum::<f64>()
+			}).sum();
+			(shapes.len(), holes, (area * 1e12).round())
+		};
+		assert_eq!(layer("ring"), (1, 1, 84.0));
+		assert_eq!(layer("x"), (1, 1, 84.0));
+		assert_eq!(layer("same"), (1, 1, 84.0));
+		assert_eq!(layer("big"), (1, 1, 140.0));
+		assert_eq!(layer("small"), (1, 1, 28.0));
+	}
+
+	#[test]
+	fn stylesheet_errors_name_the_line() {
+		let lib = square_library();
+		let mut ctx = Context::new(&lib);
+		let sheet = "alias 1 m1 m1\n// comment\nfill m1 color nope\n";
+		match read_stylesheet(&mut ctx, sheet.as_bytes(), "inline") {
+			Err(Error::Stylesheet(ref name, 3, _)) => assert_eq!(name, "inline"),
+			r => panic!("unexpected {:?}", r),
+		}
+		match read_stylesheet(&mut ctx, "in cell SUB {\n".as_bytes(), "inline") {
+			Err(Error::Stylesheet(_, 1, ref msg)) => assert!(msg.contains("missing `}`"), "{}", msg),
+			r => panic!("unexpected {:?}", r),
+		}
+	}
+
+	/// A backend which records the drawing operations instead of drawing.
+	#[derive(Default)]
+	struct Recorder {
+		calls: Vec<Call>,
+	}
+
+	#[derive(Debug, PartialEq)]
+	enum Call {
+		Clear,
+		BeginLayer(f64),
+		EndLayer,
+		/// The color and opacity.
+		Style(String, f64),
+		/// The number of points of the ring.
+		Fill(usize),
+		Stroke(usize, bool),
+		Text(String),
+	}
+
+	impl RenderBackend for Recorder {
+		fn clear(&mut self, _: ColorRgb) {
+			self.calls.push(Call::Clear);
+		}
+
+		fn begin_layer(&mut self, alpha: f64) {
+			self.calls.push(Call::BeginLayer(alpha));
+		}
+
+		fn end_layer(&mut self) {
+			self.calls.push(Call::EndLayer);
+		}
+
+		fn set_style(&mut self, style: &Style) {
+			self.calls.push(Call::Style(style.color.to_hex(), style.alpha));
+		}
+
+		fn fill_polygon(&mut self, ring: &[Point], _: &[Vec<Point>], _: Transform) {
+			self.calls.push(Call::Fill(ring.len()));
+		}
+
+		fn stroke_polyline(&mut self, points: &[Point], closed: bool, _: Transform) {
+			self.calls.push(Call::Stroke(points.len(), closed));
+		}
+
+		fn text(&mut self, _: Point, _: f64, text: &str) {
+			self.calls.push(Call::Text(text.to_owned()));
+		}
+	}
+
+	#[test]
+	fn draw_through_backend() {
+		let mut second = square();
+		second.layer = 2;
+		let mut third = second.clone();
+		for xy in &mut third.xy {
+			xy.x += 20000;
+		}
+		let lib = Library::new(String::from("LIB"), (1e-3, 1e-9), vec![
+			(String::from("TOP"), vec![square(), second, third]),
+		]);
+		let mut ctx = Context::new(&lib);
+		load_style(&mut ctx, "alias 1 m1 m1\nalias 2 m2 m2\n\
+			fill m1 color #ff0000 alpha 0.5 pattern solid\nfill m2 color #0000ff alpha 0.25 pattern solid\n\
+			stroke m2 color #000000 width 1\norder 1 5\nresolution 1e6");
+		let top = prepare_cell(&ctx, "TOP").unwrap();
+		let mut rec = Recorder::default();
+		draw(&ctx, &[Overlay::new(&top)], &mut rec);
+
+		// Layer 2 is ordered first. Each layer is filled within its own group
+		// at the layer's opacity, and strokes follow all fills.
+		assert_eq!(rec.calls, vec![
+			Call::BeginLayer(0.25),
+			Call::Style(String::from("#0000ff"), 1.0),
+			Call::Fill(5),
+			Call::Fill(5),
+			Call::EndLayer,
+			Call::BeginLayer(0.5),
+			Call::Style(String::from("#ff0000"), 1.0),
+			Call::Fill(5),
+			Call::EndLayer,
+			Call::Style(String::from("#000000"), 1.0),
+			Call::Stroke(5, true),
+			Call::Stroke(5, true),
+		]);
+	}
+}
@@ -1,22 +1,18 @@
 // Copyright (c) 2016 Fabian Schuiki
-extern crate cairo;
-extern crate gds;
+extern crate gdsplot;
 extern crate getopts;
-// mod parser;
 
-use std::io::{Read, BufRead, BufReader, stderr, stdin, Write};
+use std::io::{stderr, Write};
 use std::env;
-use std::fs::File;
+use std::collections::HashMap;
 use getopts::Options;
-use std::collections::{HashMap, HashSet, BTreeMap};
-use std::rc::Rc;
-// use parser::{Parser, ByteIter, Error};
+use gdsplot::{Context, Error, Format, Library, Overlay, Vector, ZERO_VECTOR};
 
 
 fn print_usage(opts: Options) {
 	stderr().write(
 		opts.usage(
-			"usage: gdsplot [OPTIONS] FILE [CELLNAME...]"
+			"usage: gdsplot [OPTIONS] FILE [CELLNAME...]\n       gdsplot [OPTIONS] --overlay FILE:CELL[@X,Y] [+STYLESHEET...] ...\n       gdsplot [OPTIONS] -b CLIPS FILE [CELLNAME]"
 		).as_bytes()
 	).unwrap();
 }
@@ -30,6 +26,16 @@ fn main() {
 	let mut opts = Options::new();
 	opts.optflag("h", "help", "print this help page");
 	opts.optmulti("s", "style", "load the given stylesheet", "STYLESHEET");
+	opts.optopt("o", "output", "write the plot of a single cell or of an overlay to the given file", "FILE");
+	opts.optopt("f", "format", "output format (png, svg, html, tiles, term, sixel, kitty, inline, masks, npy)", "FORMAT");
+	opts.optopt("j", "jobs", "number of threads used to render tiles", "N");
+	opts.optopt("b", "batch", "render each clip listed in a CSV or JSON file", "CLIPS");
+	opts.optflag("", "overlay", "composite the FILE:CELL inputs into a single plot");
+	opts.optmulti("m", "markers", "draw the markers of a KLayout report database", "LYRDB");
+	opts.optmulti("a", "annotate", "draw the annotations in the given file", "FILE");
+	opts.optmulti("", "highlight", "highlight the instances of a cell, or the instance at a path of cell names such as TOP/CORE/ALU, and dim everything else", "PATH");
+	opts.optopt("", "depth", "expand the hierarchy only N levels deep and draw deeper instances as boxes labeled with their cell name", "N");
+	opts.optopt("", "marker-clips", "instead of the whole plot, render a clip of the given size in micrometers around each marker", "SIZE");
 	let matches = match opts.parse(args) {
 		Ok(m) => { m },
 		Err(m) => {
@@ -48,720 +54,253 @@ fn main() {
 		std::process::exit(1);
 	}
 	// println!("matches: {:?}", matches.free);
-	let filename = &matches.free[0];
-	let structs = &matches.free[1..];
 
-	// Load the GDS file to be plotted.
-	let mut rd = match gds::Reader::open_file(filename.as_str(), 0) {
-		Ok(rd) => rd,
-		Err(_) => {
-			writeln!(&mut stderr(), "Unable to open GDS file `{}`", filename).unwrap();
-			std::process::exit(1);
-		}
-	};
-	let lib = gds::Library::read(&mut rd).expect("Unable to read GDS file");
-
-	// Assemble the context from the command line arguments.
-	let mut ctx = Context::new(&lib);
-	for stylesheet in matches.opt_strs("s") {
-		// println!("loading stylesheet {}", stylesheet);
-		load_stylesheet(&mut ctx, stylesheet.as_str());
-	}
-	// println!("loaded aliases {:?}", &ctx.aliases);
-	// println!("loaded assignments {:?}", &ctx.assignments);
-	// println!("loaded classes {:?}", &ctx.classes);
-
-	// Plot the cells passed on the command line.
-	for name in structs {
-		// println!("plotting {}", name);
-		let strukt = match lib.find_struct(name) {
-			Some(s) => s,
-			None => {
-				writeln!(&mut stderr(), "Unable to find cell {}", name).unwrap();
+	// Determine the output format, either explicitly or from the extension of
+	// the output file.
+	let format = match matches.opt_str("f").or_else(|| {
+		matches.opt_str("o").and_then(|o| o.rsplit('.').next().map(|e| e.to_lowercase()))
+	}) {
+		Some(f) => match Format::from_name(f.as_str()) {
+			Some(f) => f,
+			None if matches.opt_present("f") => {
+				writeln!(&mut stderr(), "Unknown output format `{}`", f).unwrap();
 				std::process::exit(1);
-			}
-		};
-
-		// Plot the structure.
-		let s = prepare(&ctx, strukt);
-		plot(&ctx, &s);
-	}
-
-
-	// Some sample Cairo code:
-	// let mut surface = cairo::surface::Surface::create_image(cairo::surface::format::Format::ARGB32, 1000, 500);
-	// let mut cr = cairo::Cairo::create(&mut surface);
-	// cr.set_source_rgb(0.0,0.0,0.0);
-	// cr.paint();
-	// surface.write_to_png("debug.png");
-}
-
-
-struct Context<'a> {
-	lib_units: f64,
-	lib: &'a gds::Library,
-	scale: ScaleMode,
-	aliases: HashMap<Box<str>, u16>,
-	only_layers: HashSet<u16>,
-	boundary_layers: HashSet<u16>,
-	assignments: HashMap<u16, Vec<Box<str>>>,
-	classes: HashMap<Box<str>, LayerClass>,
-	bg_color: Option<ColorRgb>,
-	orders: HashMap<u16, i32>,
-	margin: i32,
-}
-
-impl<'a> Context<'a> {
-	fn new(lib: &'a gds::Library) -> Context {
-		Context {
-			lib_units: lib.get_units_in_m(),
-			lib: lib,
-			scale: ScaleMode::Size(512,512),
-			aliases: HashMap::new(),
-			only_layers: HashSet::new(),
-			boundary_layers: HashSet::new(),
-			assignments: HashMap::new(),
-			classes: HashMap::new(),
-			bg_color: None,
-			orders: HashMap::new(),
-			margin: 0,
-		}
-	}
-
-	fn get_layer_style(&self, layer_id: u16) -> LayerClass {
-		let mut style = LayerClass::new();
-		if let Some(classes) = self.assignments.get(&layer_id) {
-			for cls in classes {
-				if let Some(class) = self.classes.get(cls) {
-					style.merge(class);
-				}
-			}
-		}
-		style
-	}
-}
-
-enum ScaleMode {
-	/// Make the plot large enough to achieve a certain number of pixels per meter.
-	Resolution(f64),
-	/// Make the plot a specific size.
-	Size(i32,i32),
-}
-
-#[derive(Debug)]
-struct LayerClass {
-	general: LayerClassSheet,
-	fill: LayerClassSheet,
-	stroke: LayerClassSheet,
-}
-
-#[derive(Debug, Clone)]
-struct LayerClassSheet {
-	color: Option<ColorRgb>,
-	alpha: Option<f64>,
-	width: Option<f64>,
-	pattern: Option<FillPattern>,
-	dashes: Option<Vec<f64>>,
-}
-
-impl LayerClass {
-	fn new() -> LayerClass {
-		LayerClass {
-			general: LayerClassSheet::new(),
-			fill: LayerClassSheet::new(),
-			stroke: LayerClassSheet::new(),
-		}
-	}
-
-	fn merge(&mut self, other: &LayerClass) {
-		self.general.merge(&other.general);
-		self.fill.merge(&other.fill);
-		self.stroke.merge(&other.stroke);
-	}
-
-	fn get_fill_style(&self) -> Option<FillStyle> {
-		let mut combined = self.general.clone();
-		combined.merge(&self.fill);
-		Some(FillStyle {
-			color: match combined.color {
-				Some(c) => c,
-				None => return None,
-			},
-			alpha: combined.alpha.unwrap_or(1.0),
-			pattern: match combined.pattern {
-				Some(p) => p,
-				None => return None,
-			},
-		})
-	}
-
-	fn get_stroke_style(&self) -> Option<StrokeStyle> {
-		let mut combined = self.general.clone();
-		combined.merge(&self.stroke);
-		Some(StrokeStyle {
-			color: match combined.color {
-				Some(c) => c,
-				None => return None,
-			},
-			alpha: combined.alpha.unwrap_or(1.0),
-			width: match combined.width {
-				Some(w) => w,
-				None => return None,
 			},
-			dashes: combined.dashes,
-		})
-	}
-}
+			None => Format::Png,
+		},
+		None => Format::Png,
+	};
 
-impl LayerClassSheet {
-	fn new() -> LayerClassSheet {
-		LayerClassSheet {
-			color: None,
-			alpha: None,
-			width: None,
-			pattern: None,
-			dashes: None,
-		}
+	// Batches and marker clips write one file per clip, named after it.
+	if matches.opt_present("o") && (matches.opt_present("b") || matches.opt_present("marker-clips")) {
+		writeln!(&mut stderr(), "-o cannot be used with -b or --marker-clips, which name the files after the clips").unwrap();
+		std::process::exit(1);
 	}
 
-	fn merge(&mut self, other: &LayerClassSheet) {
-		if other.color.is_some() {
-			self.color = other.color.clone();
-		}
-		if other.alpha.is_some() {
-			self.alpha = other.alpha;
-		}
-		if other.width.is_some() {
-			self.width = other.width;
-		}
-		if other.pattern.is_some() {
-			self.pattern = other.pattern.clone();
-		}
-		if other.dashes.is_some() {
-			self.dashes = other.dashes.clone();
-		}
+	let result = if let Some(clips) = matches.opt_str("b") {
+		// Render a list of clips from a single library.
+		plot_batch(&matches, format, clips.as_str())
+	} else if matches.opt_present("overlay") {
+		// Inputs of the form `FILE:CELL` are composited into one plot.
+		plot_overlay(&matches, format)
+	} else {
+		plot_cells(&matches, format)
+	};
+	if let Err(e) = result {
+		writeln!(&mut stderr(), "{}", e).unwrap();
+		std::process::exit(1);
 	}
 }
 
-struct FillStyle {
-	color: ColorRgb,
-	alpha: f64,
-	pattern: FillPattern,
-}
-
-struct StrokeStyle {
-	color: ColorRgb,
-	alpha: f64,
-	width: f64,
-	dashes: Option<Vec<f64>>,
-}
-
-#[derive(Debug, Clone)]
-enum FillPattern {
-	Solid,
-}
-
-
-fn load_stylesheet(ctx: &mut Context, filename: &str) {
-	// Open the file.
-	let mut file = match File::open(filename) {
-		Ok(f) => f,
-		Err(e) => {
-			writeln!(&mut stderr(), "Unable to open stylesheet {}: {}", filename, e).unwrap();
-			std::process::exit(1);
-		}
-	};
-	let mut lines = BufReader::new(file).lines();
-
-	// Process each line.
-	for line in lines {
-		let line_unwrapped = line.unwrap();
-		let args: Vec<&str> = line_unwrapped.split_whitespace().take_while(|x| !x.starts_with("//")).collect();
-		if args.is_empty() {
-			continue;
-		}
-
-		let mut it = args.iter();
-		match *it.next().unwrap() {
-			"alias" => {
-				let id: u16 = it.next().unwrap().parse().unwrap();
-				let alias = *it.next().unwrap();
-				ctx.aliases.insert(alias.to_owned().into_boxed_str(), id);
-				for cls in it {
-					if ctx.assignments.contains_key(&id) {
-						ctx.assignments.get_mut(&id).unwrap().push((*cls).to_owned().into_boxed_str());
-					} else {
-						ctx.assignments.insert(id, vec![(*cls).to_owned().into_boxed_str()]);
-					}
-				}
-			},
-
-			"general" => {
-				let classname = *it.next().unwrap();
-				if !ctx.classes.contains_key(&*classname) {
-					ctx.classes.insert(classname.to_owned().into_boxed_str(), LayerClass::new());
-				}
-				let mut class = ctx.classes.get_mut(&*classname).unwrap();
-				load_layer_class_sheet(&mut class.general, it);
-			},
-
-			"fill" => {
-				let classname = *it.next().unwrap();
-				if !ctx.classes.contains_key(&*classname) {
-					ctx.classes.insert(classname.to_owned().into_boxed_str(), LayerClass::new());
-				}
-				let mut class = ctx.classes.get_mut(&*classname).unwrap();
-				load_layer_class_sheet(&mut class.fill, it);
-			},
-
-			"stroke" => {
-				let classname = *it.next().unwrap();
-				if !ctx.classes.contains_key(&*classname) {
-					ctx.classes.insert(classname.to_owned().into_boxed_str(), LayerClass::new());
-				}
-				let mut class = ctx.classes.get_mut(&*classname).unwrap();
-				load_layer_class_sheet(&mut class.stroke, it);
-			},
-
-			"bgcolor" => {
-				ctx.bg_color = Some(parse_color(*it.next().unwrap()).expect("invalid bgcolor"));
-			},
-
-			"only" => {
-				for layer in it {
-					let id: u16 = match ctx.aliases.get(*layer) {
-						Some(v) => *v,
-						None => layer.parse().expect("invalid layer ID"),
-					};
-					ctx.only_layers.insert(id);
-				}
-			},
-
-			"order" => {
-				let layer = *it.next().unwrap();
-				let id: u16 = match ctx.aliases.get(layer) {
-					Some(v) => *v,
-					None => layer.parse().expect("invalid layer ID"),
-				};
-				ctx.orders.insert(id, it.next().unwrap().parse().expect("invalid layer order"));
-			},
-
-			"resolution" => {
-				ctx.scale = ScaleMode::Resolution(it.next().unwrap().parse().expect("invalid resolution"));
-			},
-
-			"size" => {
-				ctx.scale = ScaleMode::Size(
-					it.next().unwrap().parse().expect("invalid width"),
-					it.next().unwrap().parse().expect("invalid height"),
-				);
-			},
-
-			"margin" => {
-				ctx.margin = it.next().unwrap().parse().expect("invalid margin");
-			},
 
-			x => {
-				writeln!(&mut stderr(), "{}: Unknown stylesheet command `{}`", filename, x).unwrap();
-				std::process::exit(1);
-			}
-		};
+/// Plot each cell passed on the command line to its own file, named after the
+/// cell unless a single cell is plotted to the file given with `-o`.
+fn plot_cells(matches: &getopts::Matches, format: Format) -> Result<(), Error> {
+	let output = matches.opt_str("o");
+	if output.is_some() && matches.free.len() != 2 {
+		return Err(Error::Invalid(String::from("-o needs exactly one cell, since each cell is plotted to its own file")));
 	}
-}
 
-fn load_layer_class_sheet(dst: &mut LayerClassSheet, mut it: std::slice::Iter<&str>) {
-	while let Some(opt) = it.next() {
-		match *opt {
-			"color" => {
-				dst.color = Some(parse_color(*it.next().unwrap()).expect("invalid color"));
-			},
-			"alpha" => {
-				dst.alpha = Some(it.next().unwrap().parse().expect("invalid alpha"));
-			},
-			"width" => {
-				dst.width = Some(it.next().unwrap().parse().expect("invalid width"));
-			},
-			"dashes" => {
-				dst.dashes = Some(it.map(|x| {
-					let v: f64 = x.parse().expect("invalid dash width");
-					v
-				}).collect());
-				break;
-			},
-			"pattern" => {
-				dst.pattern = Some(match *it.next().unwrap() {
-					"solid" => FillPattern::Solid,
-					x => panic!("Unknown pattern `{}`", x)
-				});
-			}
-			x => panic!("Unknown style parameter `{}`", x)
-		}
-	}
-}
+	// Load the GDS file to be plotted.
+	let lib = try!(gdsplot::load_library(&matches.free[0]));
 
-fn parse_color(s: &str) -> Result<ColorRgb, &str> {
-	match s.chars().nth(0).unwrap() {
-		'#' => {
-			if s.len() != 7 {
-				return Err(s);
-			}
-			let (_,rem) = s.split_at(1);
-			let (rs,rem) = rem.split_at(2);
-			let (gs,rem) = rem.split_at(2);
-			let (bs,rem) = rem.split_at(2);
-			let r = u8::from_str_radix(rs, 16).unwrap();
-			let g = u8::from_str_radix(gs, 16).unwrap();
-			let b = u8::from_str_radix(bs, 16).unwrap();
-			Ok(ColorRgb(
-				r as f64 / 255.0,
-				g as f64 / 255.0,
-				b as f64 / 255.0
-			))
+	// Assemble the context from the command line arguments.
+	let mut ctx = Context::new(&lib);
+	try!(configure(&mut ctx, matches));
+
+	for name in &matches.free[1..] {
+		let s = try!(gdsplot::prepare_cell(&ctx, name));
+		print_warnings(s.warnings());
+		let output_name = match output {
+			Some(ref o) => o.clone(),
+			None => format!("{}.{}", s.name(), format.extension()),
+		};
+		let overlays = [Overlay::new(&s)];
+		if ctx.marker_clips().is_some() {
+			try!(gdsplot::render_marker_clips(&mut ctx, &overlays, format, s.name()));
+		} else {
+			try!(gdsplot::render(&ctx, &overlays, format, output_name.as_str()));
 		}
-		_ => Err(s),
 	}
+	Ok(())
 }
 
 
-struct Struct {
-	layers: Vec<Rc<Layer>>,
-	name: Box<str>,
-	boundaries: Vec<Boundary>,
-	extents: Extents,
-}
-
-#[derive(Debug, Clone, Copy)]
-struct Point {
-	x: f64,
-	y: f64,
-}
-
-impl std::ops::Sub for Point {
-	type Output = Vector;
-	fn sub(self, rhs: Point) -> Vector {
-		Vector { x: self.x - rhs.x, y: self.y - rhs.y }
+/// Report the problems found while preparing a cell.
+fn print_warnings(warnings: &[String]) {
+	for w in warnings {
+		writeln!(&mut stderr(), "{}", w).unwrap();
 	}
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Vector {
-	x: f64,
-	y: f64,
-}
-
-#[derive(Debug, Clone, Copy)]
-struct Transform {
-	va: Vector,
-	vb: Vector,
-	vt: Vector,
-}
-
-impl Transform {
-	fn identity() -> Transform {
-		Transform {
-			va: Vector { x: 1.0, y: 0.0 },
-			vb: Vector { x: 0.0, y: 1.0 },
-			vt: ZERO_VECTOR,
-		}
-	}
 
-	fn scale(&mut self, sx: f64, sy: f64) {
-		self.va.x *= sx;
-		self.va.y *= sy;
-		self.vb.x *= sx;
-		self.vb.y *= sy;
-		self.vt.x *= sx;
-		self.vt.y *= sy;
+/// Load the stylesheets and apply the options given on the command line.
+fn configure(ctx: &mut Context, matches: &getopts::Matches) -> Result<(), Error> {
+	for stylesheet in matches.opt_strs("s") {
+		try!(gdsplot::load_stylesheet(ctx, stylesheet.as_str()));
 	}
-
-	fn trans(&mut self, tx: f64, ty: f64) {
-		self.vt.x += tx;
-		self.vt.y += ty;
+	for filename in matches.opt_strs("m") {
+		try!(ctx.add_markers(filename.as_str()));
 	}
-}
-
-impl std::ops::Mul<Vector> for Transform {
-	type Output = Vector;
-	fn mul(self, rhs: Vector) -> Vector {
-		Vector {
-			x: self.va.x * rhs.x + self.vb.x * rhs.y,
-			y: self.va.y * rhs.x + self.vb.y * rhs.y,
-		}
+	for filename in matches.opt_strs("a") {
+		try!(ctx.add_annotations(filename.as_str()));
 	}
-}
-
-impl std::ops::Mul<Point> for Transform {
-	type Output = Point;
-	fn mul(self, rhs: Point) -> Point {
-		Point {
-			x: self.va.x * rhs.x + self.vb.x * rhs.y + self.vt.x,
-			y: self.va.y * rhs.x + self.vb.y * rhs.y + self.vt.y,
-		}
+	for path in matches.opt_strs("highlight") {
+		ctx.add_highlight(path.as_str());
 	}
-}
-
-#[derive(Debug, Clone, Copy)]
-struct Rect {
-	min: Point,
-	max: Point,
-}
-
-const ZERO_POINT: Point = Point { x: 0.0, y: 0.0 };
-const ZERO_VECTOR: Vector = Vector { x: 0.0, y: 0.0 };
-const ZERO_RECT: Rect = Rect { min: ZERO_POINT, max: ZERO_POINT };
-
-struct Boundary {
-	layer: Rc<Layer>,
-	points: Vec<Point>,
-}
-
-#[derive(Clone, Copy)]
-struct Extents {
-	rect: Rect,
-	empty: bool,
-}
-
-impl Extents {
-	fn new() -> Extents {
-		Extents {
-			rect: ZERO_RECT,
-			empty: true,
+	if let Some(size) = matches.opt_str("marker-clips") {
+		match size.parse::<f64>() {
+			Ok(v) if v > 0.0 => ctx.set_marker_clips(Some(v * 1e-6)),
+			_ => return Err(Error::Invalid(format!("Invalid marker clip size `{}`", size))),
 		}
 	}
-
-	fn add_point(&mut self, p: &Point) {
-		if self.empty {
-			self.rect = Rect { min: *p, max: *p };
-			self.empty = false;
-		} else {
-			if self.rect.min.x > p.x {
-				self.rect.min.x = p.x;
-			}
-			if self.rect.min.y > p.y {
-				self.rect.min.y = p.y;
-			}
-			if self.rect.max.x < p.x {
-				self.rect.max.x = p.x;
-			}
-			if self.rect.max.y < p.y {
-				self.rect.max.y = p.y;
-			}
+	try!(configure_prepare(ctx, matches));
+	if let Some(jobs) = matches.opt_str("j") {
+		match jobs.parse() {
+			Ok(n) if n > 0 => ctx.set_jobs(n),
+			_ => return Err(Error::Invalid(format!("Invalid number of jobs `{}`", jobs))),
 		}
 	}
+	Ok(())
 }
 
-#[derive(Debug, Clone, Copy)]
-struct ColorRgb {
-	r: f64,
-	g: f64,
-	b: f64,
-}
-
-#[allow(non_snake_case)]
-fn ColorRgb(r: f64, g: f64, b: f64) -> ColorRgb {
-	ColorRgb {
-		r: r,
-		g: g,
-		b: b,
+/// Apply the options which affect how cells are prepared. Overlay plots
+/// prepare each input with its own context, which needs these as well.
+fn configure_prepare(ctx: &mut Context, matches: &getopts::Matches) -> Result<(), Error> {
+	if let Some(depth) = matches.opt_str("depth") {
+		match depth.parse() {
+			Ok(n) => ctx.set_depth(Some(n)),
+			_ => return Err(Error::Invalid(format!("Invalid hierarchy depth `{}`", depth))),
+		}
 	}
+	Ok(())
 }
 
-struct Layer {
-	id: u16,
-	order: i32,
-	style: LayerClass,
-}
 
-impl std::cmp::PartialEq for Layer {
-	fn eq(&self, other: &Layer) -> bool {
-		self.id == other.id
-	}
+/// One `FILE:CELL[@X,Y]` input of an overlay plot, followed by the
+/// `+STYLESHEET` arguments which apply to it only. The offset is given in
+/// micrometers.
+struct OverlayInput {
+	filename: String,
+	cell: String,
+	offset: Vector,
+	stylesheets: Vec<String>,
 }
 
-
-fn prepare(ctx: &Context, strukt: gds::Struct) -> Struct {
-	let prepared = BTreeMap::<Box<str>, Rc<Struct>>::new();
-
-	let mut layers = BTreeMap::<u16, Rc<Layer>>::new();
-	let mut extents = Extents::new();
-	let mut boundaries = Vec::new();
-
-	// Collect the elements of this struct.
-	for elem in strukt.elems() {
-		// println!("- found a {:?} on layer {}:{}", elem.get_kind(), elem.get_layer(), elem.get_type());
-
-		// Pick the layer for this element.
-		let layer_id = elem.get_layer();
-		if !ctx.only_layers.is_empty() && !ctx.only_layers.contains(&layer_id) {
-			continue;
-		}
-
-		let layer =
-			if layers.contains_key(&layer_id) {
-				layers.get(&layer_id).unwrap().clone()
-			} else {
-				let style = ctx.get_layer_style(layer_id);
-				// println!("- create layer with style {:?}", style);
-				let l = Rc::new(Layer {
-					id: layer_id,
-					order: match ctx.orders.get(&layer_id) {
-						Some(v) => *v,
-						None => layer_id as i32,
-					},
-					style: style,
-				});
-				layers.insert(layer_id, l.clone());
-				l
+/// Parse an overlay input. Since cell names cannot contain colons, the file
+/// name extends up to the last one, and may itself contain colons and other
+/// special characters.
+fn parse_overlay_input(arg: &str) -> Result<OverlayInput, String> {
+	let i = match arg.rfind(':') {
+		Some(i) => i,
+		None => return Err(format!("expected `FILE:CELL`, got `{}`", arg)),
+	};
+	let (filename, cell) = (&arg[..i], &arg[i+1..]);
+
+	let (cell, offset) = match cell.find('@') {
+		Some(i) => {
+			let mut coords = cell[i+1..].split(',');
+			let x: f64 = match coords.next().map(|c| c.parse()) {
+				Some(Ok(v)) => v,
+				_ => return Err(format!("invalid x offset in `{}`", arg)),
 			};
-
-		match elem.get_kind() {
-			gds::ElemKind::Boundary => {
-				let pts = elem.get_xy().iter().map(|xy| Point {
-					x: xy.x as f64 * ctx.lib_units,
-					y: xy.y as f64 * ctx.lib_units,
-				}).collect();
-				boundaries.push(Boundary {
-					layer: layer,
-					points: pts,
-				});
-			},
-			_ => ()
-		}
-	}
-
-	// Calculate the extents.
-	for b in &boundaries {
-		for p in &b.points {
-			extents.add_point(p);
-		}
-	}
-
-	// Make an ordered list of layers.
-	let mut ordered_layers: Vec<Rc<Layer>> = Vec::new();
-	for (_,l) in layers {
-		ordered_layers.push(l);
-	}
-	ordered_layers.sort_by_key(|l| l.order);
-
-	Struct {
-		layers: ordered_layers,
-		name: strukt.get_name().into_boxed_str(),
-		boundaries: boundaries,
-		extents: extents,
-	}
-}
-
-
-fn plot(ctx: &Context, strukt: &Struct) {
-
-	// Calculate the overall plot size and transformation.
-	let r = &strukt.extents.rect;
-	let phys_size = r.max - r.min;
-	let mut tx = Transform::identity();
-	tx.trans(-r.min.x, -r.min.y);
-	let plot_size = match ctx.scale {
-		ScaleMode::Resolution(ppm) => {
-			tx.scale(ppm,ppm);
-			let sz = tx * phys_size;
-			((sz.x + 0.5) as i32, (sz.y + 0.5) as i32)
-		},
-		ScaleMode::Size(w,h) => {
-			let fw = w as f64 / phys_size.x;
-			let fh = h as f64 / phys_size.y;
-			if fw < fh {
-				tx.scale(fw,fw);
-				(w, ((h as f64)/fw + 0.5) as i32)
-			} else {
-				tx.scale(fh,fh);
-				(((w as f64)/fh + 0.5) as i32, h)
+			let y: f64 = match coords.next().map(|c| c.parse()) {
+				Some(Ok(v)) => v,
+				_ => return Err(format!("invalid y offset in `{}`", arg)),
+			};
+			if coords.next().is_some() {
+				return Err(format!("too many offset coordinates in `{}`", arg));
 			}
+			(&cell[..i], Vector { x: x * 1e-6, y: y * 1e-6 })
 		},
+		None => (cell, ZERO_VECTOR),
 	};
-	tx.scale(1.0, -1.0);
-	tx.trans(0.0, plot_size.1 as f64);
-
-	let plot_size = (plot_size.0 + 2*ctx.margin, plot_size.1 + 2*ctx.margin);
-	tx.trans(ctx.margin as f64, ctx.margin as f64);
-
-	// println!("plotting struct of physical size {:?} onto {:?}", phys_size, plot_size);
-
-	// Prepare the plot surface.
-	let mut surface = cairo::surface::Surface::create_image(cairo::surface::format::Format::ARGB32, plot_size.0 as i32, plot_size.1 as i32);
-	let mut cr = cairo::Cairo::create(&mut surface);
-	cr.set_fill_rule(cairo::fill_rule::FillRule::EvenOdd);
-
-	// Draw the background.
-	if let Some(bgc) = ctx.bg_color {
-		cr.set_source_rgb(bgc.r, bgc.g, bgc.b);
-		cr.paint();
+	if filename.is_empty() || cell.is_empty() {
+		return Err(format!("expected `FILE:CELL`, got `{}`", arg));
 	}
 
-	// Plot.
-	plot_struct(ctx, strukt, tx, &mut cr);
-
-	// Write the file.
-	let output_name = format!("{}.png", strukt.name);
-	surface.write_to_png(output_name.as_str());
+	Ok(OverlayInput {
+		filename: filename.to_owned(),
+		cell: cell.to_owned(),
+		offset: offset,
+		stylesheets: Vec::new(),
+	})
 }
 
-
-fn plot_struct(ctx: &Context, strukt: &Struct, tx: Transform, cr: &mut cairo::Cairo) {
-	for layer in &strukt.layers {
-		// Fill the geometry on this layer.
-		if let Some(fs) = layer.style.get_fill_style() {
-			cr.push_group();
-			cr.set_source_rgb(fs.color.r, fs.color.g, fs.color.b);
-			gather_geometry(strukt, layer, tx, cr, Pass::Fill);
-			cr.pop_group_to_source();
-			cr.paint_with_alpha(fs.alpha);
-		}
-	}
-	for layer in &strukt.layers {
-		// Stroke the geometry on this layer.
-		if let Some(ss) = layer.style.get_stroke_style() {
-			cr.save();
-			cr.set_source_rgba(ss.color.r, ss.color.g, ss.color.b, ss.alpha);
-			if let Some(mut dashes) = ss.dashes {
-				cr.set_dash(&mut dashes[..], 0.0);
+/// Render each clip of a clip list to its own file, named after the clip.
+/// Clips which do not name a cell are taken from the cell given on the command
+/// line.
+fn plot_batch(matches: &getopts::Matches, format: Format, clips_file: &str) -> Result<(), Error> {
+	let lib = try!(gdsplot::load_library(&matches.free[0]));
+	let mut ctx = Context::new(&lib);
+	try!(configure(&mut ctx, matches));
+	let default_cell = matches.free.get(1).map(|c| c.as_str());
+	let warnings = try!(gdsplot::render_batch(&mut ctx, clips_file, default_cell, format));
+	print_warnings(&warnings);
+	Ok(())
+}
+
+/// Composite several cells, possibly from different GDS files, into a single
+/// plot with shared extents. Each input is styled with the global stylesheets
+/// followed by its own.
+fn plot_overlay(matches: &getopts::Matches, format: Format) -> Result<(), Error> {
+	let mut inputs: Vec<OverlayInput> = Vec::new();
+	for arg in &matches.free {
+		if arg.starts_with('+') {
+			match inputs.last_mut() {
+				Some(input) => input.stylesheets.push(arg[1..].to_owned()),
+				None => return Err(Error::Invalid(format!("Stylesheet `{}` does not follow an input", arg))),
 			}
-			cr.set_line_width(ss.width);
-			gather_geometry(strukt, layer, tx, cr, Pass::Stroke);
-			cr.restore();
+		} else {
+			inputs.push(try!(parse_overlay_input(arg)));
 		}
 	}
-}
-
 
-enum Pass {
-	Fill,
-	Stroke,
-}
-
-fn gather_geometry(strukt: &Struct, layer: &Rc<Layer>, tx: Transform, cr: &mut cairo::Cairo, pass: Pass) {
-	// Boundaries.
-	for b in &strukt.boundaries {
-		if b.layer != *layer {
-			continue;
+	// Load each GDS file only once, even if multiple cells are taken from it.
+	let mut libs = HashMap::<String, Library>::new();
+	for input in &inputs {
+		if !libs.contains_key(&input.filename) {
+			let lib = try!(gdsplot::load_library(input.filename.as_str()));
+			libs.insert(input.filename.clone(), lib);
 		}
+	}
 
-		let mut it = b.points.iter();
-		it.next();
-
-		if let Some(pt) = it.next() {
-			let p = tx * *pt;
-			cr.move_to(p.x, p.y);
-		}
+	// The plot-wide settings such as scale and background are taken from the
+	// global stylesheets.
+	let mut ctx = Context::new(&libs[&inputs[0].filename]);
+	try!(configure(&mut ctx, matches));
 
-		for pt in it {
-			let p = tx * *pt;
-			cr.line_to(p.x, p.y);
+	// Prepare each input with its own context.
+	let mut structs = Vec::new();
+	for input in &inputs {
+		let lib = &libs[&input.filename];
+		let mut input_ctx = Context::new(lib);
+		for stylesheet in matches.opt_strs("s").iter().chain(input.stylesheets.iter()) {
+			try!(gdsplot::load_stylesheet(&mut input_ctx, stylesheet.as_str()));
 		}
+		try!(configure_prepare(&mut input_ctx, matches));
+		let s = match gdsplot::prepare_cell(&input_ctx, input.cell.as_str()) {
+			Ok(s) => s,
+			Err(Error::UnknownCell(_)) => {
+				return Err(Error::Invalid(format!("Unable to find cell {} in {}", input.cell, input.filename)));
+			},
+			Err(e) => return Err(e),
+		};
+		print_warnings(s.warnings());
+		structs.push(s);
+	}
 
-		cr.close_path();
-
-		match pass {
-			Pass::Fill => cr.fill(),
-			Pass::Stroke => cr.stroke(),
-		}
+	let overlays: Vec<Overlay> = structs.iter().zip(inputs.iter()).map(|(s,i)| Overlay {
+		strukt: s,
+		offset: i.offset,
+	}).collect();
+	let output_name = match matches.opt_str("o") {
+		Some(o) => o,
+		None => format!("{}.{}", structs[0].name(), format.extension()),
+	};
+	if ctx.marker_clips().is_some() {
+		gdsplot::render_marker_clips(&mut ctx, &overlays, format, structs[0].name())
+	} else {
+		gdsplot::render(&ctx, &overlays, format, output_name.as_str())
 	}
 }
@@ -0,0 +1,363 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Marker databases such as DRC results.
+//!
+//! KLayout report databases (`.lyrdb`) list items, each assigned to a category
+//! and a cell and carrying a number of values. Boxes, polygons, edges, and
+//! edge pairs among these values become markers. Items reported in sub-cells
+//! are mapped into the top cell of the database through the cell references
+//! it records. All coordinates are in micrometers.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use {Point, Rect, Transform, Extents, close_ring};
+use xml;
+
+#[derive(Debug)]
+pub struct Database {
+	/// The cell the markers are given in, if known.
+	pub top_cell: Option<String>,
+	/// The categories in the order they are defined, sub-categories given as a
+	/// path separated by dots.
+	pub categories: Vec<String>,
+	pub markers: Vec<Marker>,
+}
+
+#[derive(Debug)]
+pub struct Marker {
+	pub category: String,
+	pub shapes: Vec<Shape>,
+	pub bbox: Rect,
+}
+
+#[derive(Debug)]
+pub enum Shape {
+	/// A polygon given as its outline followed by its holes, each a closed
+	/// ring where the last point repeats the first.
+	Polygon(Vec<Vec<Point>>),
+	Edge(Point, Point),
+	EdgePair((Point, Point), (Point, Point)),
+}
+
+pub fn load_lyrdb(filename: &str) -> Result<Database, String> {
+	let mut text = String::new();
+	if let Err(e) = File::open(filename).and_then(|mut f| f.read_to_string(&mut text)) {
+		return Err(format!("Unable to read marker database {}: {}", filename, e));
+	}
+	let root = try!(xml::parse(&text).map_err(|e| format!("{}: {}", filename, e)));
+	if root.name != "report-database" {
+		return Err(format!("{}: Not a report database", filename));
+	}
+
+	let top_cell = root.child_text("top-cell").and_then(|c| if c.is_empty() { None } else { Some(c.to_owned()) });
+
+	let mut categories = Vec::new();
+	if let Some(cats) = root.child("categories") {
+		collect_categories(cats, "", &mut categories);
+	}
+
+	// The placement of each cell within its parent.
+	let mut parents = HashMap::<String, (String, Transform)>::new();
+	if let Some(cells) = root.child("cells") {
+		for cell in cells.children.iter().filter(|c| c.name == "cell") {
+			let name = cell.child_text("name").unwrap_or("");
+			let reference = cell.child("references").and_then(|r| r.child("ref"));
+			if let Some(r) = reference {
+				let parent = r.child_text("parent").unwrap_or("");
+				let tx = parse_trans(r.child_text("trans").unwrap_or(""));
+				parents.insert(name.to_owned(), (unquote(parent), tx));
+			}
+		}
+	}
+
+	let mut markers = Vec::new();
+	if let Some(items) = root.child("items") {
+		for item in items.children.iter().filter(|c| c.name == "item") {
+			let category = item.child_text("category").map(category_path).unwrap_or_default();
+			if !categories.contains(&category) {
+				categories.push(category.clone());
+			}
+
+			// Follow the cell references up to the top cell.
+			let mut tx = Transform::identity();
+			let mut cell = item.child_text("cell").map(unquote).unwrap_or_default();
+			for _ in 0..64 {
+				if Some(&cell) == top_cell.as_ref() {
+					break;
+				}
+				match parents.get(&cell) {
+					Some(&(ref parent, ptx)) => {
+						tx = ptx * tx;
+						cell = parent.clone();
+					},
+					None => break,
+				}
+			}
+
+			let mut shapes = Vec::new();
+			if let Some(values) = item.child("values") {
+				for value in values.children.iter().filter(|c| c.name == "value") {
+					if let Some(shape) = parse_value(value.text.trim(), tx) {
+						shapes.push(shape);
+					}
+				}
+			}
+			if shapes.is_empty() {
+				continue;
+			}
+
+			let mut extents = Extents::new();
+			for shape in &shapes {
+				match *shape {
+					Shape::Polygon(ref rings) => for p in rings.iter().flat_map(|r| r.iter()) {
+						extents.add_point(p);
+					},
+					Shape::Edge(a, b) => {
+						extents.add_point(&a);
+						extents.add_point(&b);
+					},
+					Shape::EdgePair((a, b), (c, d)) => for p in &[a, b, c, d] {
+						extents.add_point(p);
+					},
+				}
+			}
+			markers.push(Marker {
+				category: category,
+				shapes: shapes,
+				bbox: extents.rect,
+			});
+		}
+	}
+
+	Ok(Database {
+		top_cell: top_cell,
+		categories: categories,
+		markers: markers,
+	})
+}
+
+fn collect_categories(cats: &xml::Element, prefix: &str, into: &mut Vec<String>) {
+	for cat in cats.children.iter().filter(|c| c.name == "category") {
+		let name = format!("{}{}", prefix, unquote(cat.child_text("name").unwrap_or("")));
+		into.push(name.clone());
+		if let Some(sub) = cat.child("categories") {
+			collect_categories(sub, &format!("{}.", name), into);
+		}
+	}
+}
+
+/// Convert a category reference of the form `'a'.b` into a path of unquoted
+/// names separated by dots.
+fn category_path(s: &str) -> String {
+	let mut parts = Vec::new();
+	let mut start = 0;
+	let mut quote = None;
+	let mut escaped = false;
+	for (i, c) in s.char_indices() {
+		match (quote, c) {
+			_ if escaped => escaped = false,
+			(Some(_), '\\') => escaped = true,
+			(Some(q), c) if c == q => quote = None,
+			(None, '\'') | (None, '"') => quote = Some(c),
+			(None, '.') => {
+				parts.push(unquote(&s[start..i]));
+				start = i + 1;
+			},
+			_ => (),
+		}
+	}
+	parts.push(unquote(&s[start..]));
+	parts.join(".")
+}
+
+/// Strip the quotes KLayout puts around names with special characters.
+fn unquote(s: &str) -> String {
+	let s = s.trim();
+	if s.len() >= 2 && (s.starts_with('\'') && s.ends_with('\'') || s.starts_with('"') && s.ends_with('"')) {
+		s[1..s.len()-1].replace("\\'", "'").replace("\\\"", "\"")
+	} else {
+		s.to_owned()
+	}
+}
+
+/// Parse a KLayout transformation such as `r90 *2 10,20` or `m45 0,5`. A
+/// mirror code gives the angle of the mirror axis.
+fn parse_trans(s: &str) -> Transform {
+	let mut tx = Transform::identity();
+	let mut angle: f64 = 0.0;
+	let mut mirror = false;
+	let mut mag = 1.0;
+	let mut disp = (0.0, 0.0);
+	for token in s.split_whitespace() {
+		if token.starts_with('r') || token.starts_with('m') {
+			mirror = token.starts_with('m');
+			angle = token[1..].parse().unwrap_or(0.0);
+		} else if token.starts_with('*') {
+			mag = token[1..].parse().unwrap_or(1.0);
+		} else if let Some(p) = parse_point(token) {
+			disp = (p.x, p.y);
+		}
+	}
+	if mirror {
+		tx.scale(1.0, -1.0);
+		angle *= 2.0;
+	}
+	tx.scale(mag, mag);
+	tx.rotate(angle.to_radians());
+	tx.trans(disp.0, disp.1);
+	tx
+}
+
+/// Parse a `x,y` coordinate pair given in micrometers.
+fn parse_point(s: &str) -> Option<Point> {
+	let mut it = s.split(',');
+	let x: f64 = match it.next().map(|v| v.trim().parse()) {
+		Some(Ok(v)) => v,
+		_ => return None,
+	};
+	let y: f64 = match it.next().map(|v| v.trim().parse()) {
+		Some(Ok(v)) => v,
+		_ => return None,
+	};
+	if it.next().is_some() {
+		return None;
+	}
+	Some(Point { x: x * 1e-6, y: y * 1e-6 })
+}
+
+/// Parse a `;` separated list of points.
+fn parse_points(s: &str, tx: Transform) -> Option<Vec<Point>> {
+	s.split(';').map(|p| parse_point(p).map(|p| tx * p)).collect()
+}
+
+/// Parse an edge given as `(x1,y1;x2,y2)`.
+fn parse_edge(s: &str, tx: Transform) -> Option<(Point, Point)> {
+	let s = s.trim();
+	if !s.starts_with('(') || !s.ends_with(')') {
+		return None;
+	}
+	match parse_points(&s[1..s.len()-1], tx) {
+		Some(ref pts) if pts.len() == 2 => Some((pts[0], pts[1])),
+		_ => None,
+	}
+}
+
+/// Parse a value of an item. Values other than geometry yield `None`.
+fn parse_value(s: &str, tx: Transform) -> Option<Shape> {
+	let colon = match s.find(':') {
+		Some(i) => i,
+		None => return None,
+	};
+	let kind = s[..colon].trim();
+	let body = s[colon+1..].trim();
+	if !body.starts_with('(') || !body.ends_with(')') {
+		return None;
+	}
+	match kind {
+		"box" => {
+			let (a, b) = match parse_edge(body, Transform::identity()) {
+				Some(e) => e,
+				None => return None,
+			};
+			let corners = vec![a, Point { x: b.x, y: a.y }, b, Point { x: a.x, y: b.y }];
+			Some(Shape::Polygon(vec![close_ring(corners.into_iter().map(|p| tx * p).collect())]))
+		},
+		"polygon" | "simple-polygon" => {
+			let rings: Option<Vec<Vec<Point>>> = body[1..body.len()-1].split('/').map(|r| parse_points(r, tx)).collect();
+			match rings {
+				Some(ref r) if r.iter().any(|r| r.len() < 3) => None,
+				Some(r) => Some(Shape::Polygon(r.into_iter().map(close_ring).collect())),
+				None => None,
+			}
+		},
+		"edge" => parse_edge(body, tx).map(|(a, b)| Shape::Edge(a, b)),
+		"edge-pair" => {
+			let split = match body.find(")/(").or_else(|| body.find(")|(")) {
+				Some(i) => i + 1,
+				None => return None,
+			};
+			match (parse_edge(&body[..split], tx), parse_edge(&body[split+1..], tx)) {
+				(Some(a), Some(b)) => Some(Shape::EdgePair(a, b)),
+				_ => None,
+			}
+		},
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_near(a: Point, x: f64, y: f64) {
+		assert!((a.x - x * 1e-6).abs() < 1e-12 && (a.y - y * 1e-6).abs() < 1e-12, "{:?} is not ({}, {})", a, x, y);
+	}
+
+	fn um(x: f64, y: f64) -> Point {
+		Point { x: x * 1e-6, y: y * 1e-6 }
+	}
+
+	#[test]
+	fn transformations() {
+		assert_near(parse_trans("") * um(1.0, 2.0), 1.0, 2.0);
+		assert_near(parse_trans("r0 10,20") * um(1.0, 2.0), 11.0, 22.0);
+		assert_near(parse_trans("r90 0,0") * um(1.0, 2.0), -2.0, 1.0);
+		assert_near(parse_trans("r180 *2 10,20") * um(1.0, 2.0), 8.0, 16.0);
+		assert_near(parse_trans("r270") * um(1.0, 0.0), 0.0, -1.0);
+		// Mirrors at the x axis, the diagonal, and the y axis.
+		assert_near(parse_trans("m0 0,0") * um(1.0, 2.0), 1.0, -2.0);
+		assert_near(parse_trans("m45 0,0") * um(1.0, 2.0), 2.0, 1.0);
+		assert_near(parse_trans("m90 5,0") * um(1.0, 2.0), 4.0, 2.0);
+		// The displacement is applied after magnification and rotation.
+		assert_near(parse_trans("*0.5 r90 -3,4") * um(2.0, 0.0), -3.0, 5.0);
+		// Unknown tokens are ignored.
+		assert_near(parse_trans("rx 1,2,3 junk") * um(1.0, 2.0), 1.0, 2.0);
+	}
+
+	#[test]
+	fn values() {
+		let tx = parse_trans("r0 100,0");
+		match parse_value("box: (0,0;2,1)", tx) {
+			Some(Shape::Polygon(ref rings)) => {
+				assert_eq!(rings.len(), 1);
+				assert_eq!(rings[0].len(), 5);
+				assert_near(rings[0][0], 100.0, 0.0);
+				assert_near(rings[0][2], 102.0, 1.0);
+			},
+			v => panic!("{:?}", v),
+		}
+		match parse_value("polygon: (0,0;4,0;4,4;0,4/1,1;1,2;2,2)", tx) {
+			Some(Shape::Polygon(ref rings)) => {
+				assert_eq!(rings.iter().map(|r| r.len()).collect::<Vec<_>>(), vec![5, 4]);
+				assert_near(rings[1][0], 101.0, 1.0);
+			},
+			v => panic!("{:?}", v),
+		}
+		match parse_value("edge: (0,0;1,1)", tx) {
+			Some(Shape::Edge(a, b)) => {
+				assert_near(a, 100.0, 0.0);
+				assert_near(b, 101.0, 1.0);
+			},
+			v => panic!("{:?}", v),
+		}
+		match parse_value("edge-pair: (0,0;1,0)|(0,2;1,2)", tx) {
+			Some(Shape::EdgePair(_, (c, _))) => assert_near(c, 100.0, 2.0),
+			v => panic!("{:?}", v),
+		}
+		assert!(parse_value("text: ('note')", tx).is_none());
+		assert!(parse_value("float: 1.5", tx).is_none());
+		assert!(parse_value("polygon: (0,0;1,1)", tx).is_none());
+		assert!(parse_value("edge: (0,0;1,1;2,2)", tx).is_none());
+	}
+
+	#[test]
+	fn names() {
+		assert_eq!(unquote(" 'a.b' "), "a.b");
+		assert_eq!(unquote("'it\\'s'"), "it's");
+		assert_eq!(unquote("plain"), "plain");
+		assert_eq!(category_path("'a.b'.c"), "a.b.c");
+		assert_eq!(category_path("width.'m1'"), "width.m1");
+		assert_eq!(category_path("'x\\'.y'"), "x'.y");
+	}
+}
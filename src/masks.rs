@@ -0,0 +1,151 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Binary and grayscale raster masks of the individual layers.
+//!
+//! Every combination of layer and datatype is rasterized into its own mask
+//! with the same transformation as the PNG output, such that the masks align
+//! with each other and with the plot pixel by pixel. The masks are written
+//! either as grayscale PNGs into a directory or as a single NumPy array.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use {Context, Overlay, Struct, Transform, Rect, Point, ZERO_POINT, plot_transform};
+use raster::{Mask, fill_rings};
+use png;
+
+/// Write one PNG per layer and datatype into a directory, named
+/// `<layer>_<datatype>.png`.
+pub fn write_masks(ctx: &Context, overlays: &[Overlay], output_dir: &str) -> io::Result<()> {
+	try!(fs::create_dir_all(output_dir));
+	let (_, size) = plot_transform(ctx, overlays);
+	render_masks(ctx, overlays, &mut |layer, datatype, pixels| {
+		let file = try!(File::create(format!("{}/{}_{}.png", output_dir, layer, datatype)));
+		png::encode_gray(&mut BufWriter::new(file), size.0 as usize, size.1 as usize, ctx.mask.depth, pixels)
+	})
+}
+
+/// Write all masks as a NumPy array of shape (channels, height, width). The
+/// layer and datatype of each channel are listed in a `.channels` file next to
+/// it, one channel per line.
+pub fn write_npy(ctx: &Context, overlays: &[Overlay], output_name: &str) -> io::Result<()> {
+	let (_, size) = plot_transform(ctx, overlays);
+	let mut channels = Vec::new();
+	try!(render_masks(ctx, overlays, &mut |layer, datatype, pixels| {
+		channels.push((layer, datatype, pixels.to_vec()));
+		Ok(())
+	}));
+
+	let descr = if ctx.mask.depth == 1 { "|b1" } else { "|u1" };
+	let mut header = format!(
+		"{{'descr': '{}', 'fortran_order': False, 'shape': ({}, {}, {}), }}",
+		descr, channels.len(), size.1, size.0
+	);
+	while (10 + header.len() + 1) % 64 != 0 {
+		header.push(' ');
+	}
+	header.push('\n');
+
+	let mut w = BufWriter::new(try!(File::create(output_name)));
+	try!(w.write_all(b"\x93NUMPY\x01\x00"));
+	try!(w.write_all(&[header.len() as u8, (header.len() >> 8) as u8]));
+	try!(w.write_all(header.as_bytes()));
+	for &(_, _, ref pixels) in &channels {
+		if ctx.mask.depth == 1 {
+			let bits: Vec<u8> = pixels.iter().map(|&v| if v >= 128 { 1 } else { 0 }).collect();
+			try!(w.write_all(&bits));
+		} else {
+			try!(w.write_all(pixels));
+		}
+	}
+
+	let stem = match output_name.rfind('.') {
+		Some(i) => &output_name[..i],
+		None => output_name,
+	};
+	let mut list = try!(File::create(format!("{}.channels", stem)));
+	for &(layer, datatype, _) in &channels {
+		try!(writeln!(list, "{} {}", layer, datatype));
+	}
+	Ok(())
+}
+
+/// Rasterize each layer and datatype in turn, ordered by layer ID, and pass
+/// the mask to a function with one byte of coverage per pixel.
+fn render_masks(ctx: &Context, overlays: &[Overlay], emit: &mut FnMut(u16, u16, &[u8]) -> io::Result<()>) -> io::Result<()> {
+	let (tx, size) = plot_transform(ctx, overlays);
+	let (width, height) = (size.0.max(0) as usize, size.1.max(0) as usize);
+
+	// Anti-aliased masks are rendered at a multiple of the resolution and
+	// scaled down afterwards.
+	let s = ctx.mask.samples;
+	let mut stx = tx;
+	stx.scale(s as f64, s as f64);
+	let view = Rect {
+		min: ZERO_POINT,
+		max: Point { x: (width * s) as f64, y: (height * s) as f64 },
+	};
+
+	// Layers restyled by a scope are drawn into the mask of the layer they
+	// restyle.
+	let mut layers = BTreeMap::<u16, Vec<u16>>::new();
+	for o in overlays {
+		for l in &o.strukt.layers {
+			let keys = layers.entry(l.id).or_insert_with(Vec::new);
+			if !keys.contains(&l.key) {
+				keys.push(l.key);
+			}
+		}
+	}
+
+	for (layer_id, keys) in layers {
+		let mut masks = BTreeMap::<u16, Mask>::new();
+		for o in overlays {
+			for &key in &keys {
+				if o.strukt.has_layer(key) {
+					collect(o.strukt, key, o.transform(stx), &view, &mut masks);
+				}
+			}
+		}
+		for (datatype, mask) in masks {
+			let mut pixels = vec![0u8; width * height];
+			for y in 0..height {
+				for x in 0..width {
+					let mut count = 0;
+					for sy in 0..s {
+						for sx in 0..s {
+							if mask.get(x * s + sx, y * s + sy) {
+								count += 1;
+							}
+						}
+					}
+					pixels[y * width + x] = ((count * 255 + s * s / 2) / (s * s)) as u8;
+				}
+			}
+			try!(emit(layer_id, datatype, &pixels));
+		}
+	}
+	Ok(())
+}
+
+/// Rasterize the shapes on a layer of a struct and all its instances into one
+/// mask per datatype.
+fn collect(strukt: &Struct, layer_key: u16, tx: Transform, view: &Rect, masks: &mut BTreeMap<u16, Mask>) {
+	let window = view.transformed(tx.inverse());
+	let (width, height) = (view.max.x as usize, view.max.y as usize);
+
+	if let Some(index) = strukt.shapes.get(&layer_key) {
+		index.query(&window, |_, &i| {
+			let b = &strukt.boundaries[i];
+			let mask = masks.entry(b.datatype).or_insert_with(|| Mask::new(width, height));
+			fill_rings(Some(&b.points).into_iter().chain(b.holes.iter()), tx, mask);
+		});
+	}
+
+	strukt.placements.query(&window, |_, &i| {
+		let inst = &strukt.instances[i];
+		if inst.strukt.has_layer(layer_key) {
+			collect(&inst.strukt, layer_key, tx * inst.tx, view, masks);
+		}
+	});
+}
@@ -0,0 +1,1341 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! OASIS input.
+//!
+//! An OASIS file is read into the same structures as a GDSII stream: shapes
+//! become boundaries and texts, and placements become structure
+//! references. Regular arrays of placements become array references, while
+//! other repeated elements keep their repetition, which is expanded when the
+//! cell is prepared for plotting. Paths, trapezoids, and circles are converted
+//! to polygons. Names given by reference number are resolved once the whole file
+//! has been read, since the name tables may come last. Properties keep their
+//! names; GDS properties stored as `S_GDS_PROPERTY` are named by their
+//! attribute number as in a GDSII stream.
+
+use std::collections::HashMap;
+use std::io::Read;
+use gdsii::{Library, Elem, ElemKind, Xy, Strans, Repetition, path_outline};
+use flate2::bufread::DeflateDecoder;
+
+const MAGIC: &'static [u8] = b"%SEMI-OASIS\r\n";
+
+/// Check whether data starts like an OASIS file.
+pub fn is_oasis(data: &[u8]) -> bool {
+	data.starts_with(MAGIC)
+}
+
+/// Parse an OASIS file.
+pub fn read(data: &[u8]) -> Result<Library, String> {
+	if !is_oasis(data) {
+		return Err(String::from("not an OASIS file"));
+	}
+	let mut s = Stream { data: data, pos: MAGIC.len(), cblock: None };
+	if try!(s.uint()) != 1 {
+		return Err(s.error("expected START record"));
+	}
+	let version = try!(s.string());
+	if version != "1.0" {
+		return Err(s.error(&format!("unsupported version `{}`", version)));
+	}
+	let unit = try!(s.real());
+	if !(unit > 0.0) {
+		return Err(s.error(&format!("invalid unit {}", unit)));
+	}
+	if try!(s.uint()) == 0 {
+		for _ in 0..12 {
+			try!(s.uint());
+		}
+	}
+
+	let mut rd = Reader {
+		names: Default::default(),
+		implicit: [0; 4],
+		cells: Vec::new(),
+		modal: Modal::new(),
+		target: None,
+	};
+	if !try!(rd.records(&mut s)) {
+		return Err(s.error("unexpected end of file, expected END record"));
+	}
+	rd.finish(unit)
+}
+
+/// A name given either directly or by reference number.
+#[derive(Debug, Clone)]
+enum Name {
+	Str(String),
+	Ref(u64),
+}
+
+/// The kinds of names defined in tables.
+#[derive(Clone, Copy)]
+enum Table {
+	CellName = 0,
+	TextString = 1,
+	PropName = 2,
+	PropString = 3,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+	Real(f64),
+	Int(i64),
+	Str(String),
+	Ref(u64),
+}
+
+/// The most copies a single repetition may describe. Larger repetitions are
+/// taken to be corrupt, since merely placing the copies would exhaust memory.
+const MAX_REPETITION: u64 = 1 << 24;
+
+/// The modal variables, which hold the values of fields omitted from a
+/// record.
+struct Modal {
+	repetition: Option<Repetition>,
+	placement_x: i64,
+	placement_y: i64,
+	placement_cell: Option<Name>,
+	layer: Option<u64>,
+	datatype: Option<u64>,
+	textlayer: Option<u64>,
+	texttype: Option<u64>,
+	text_x: i64,
+	text_y: i64,
+	text_string: Option<Name>,
+	geometry_x: i64,
+	geometry_y: i64,
+	relative: bool,
+	geometry_w: Option<i64>,
+	geometry_h: Option<i64>,
+	polygon_points: Option<Vec<(i64, i64)>>,
+	path_halfwidth: Option<i64>,
+	path_points: Option<Vec<(i64, i64)>>,
+	path_start_extension: Option<i64>,
+	path_end_extension: Option<i64>,
+	ctrapezoid_type: Option<u64>,
+	circle_radius: Option<i64>,
+	property_name: Option<Name>,
+	property_values: Option<Vec<Value>>,
+}
+
+impl Modal {
+	fn new() -> Modal {
+		Modal {
+			repetition: None,
+			placement_x: 0,
+			placement_y: 0,
+			placement_cell: None,
+			layer: None,
+			datatype: None,
+			textlayer: None,
+			texttype: None,
+			text_x: 0,
+			text_y: 0,
+			text_string: None,
+			geometry_x: 0,
+			geometry_y: 0,
+			relative: false,
+			geometry_w: None,
+			geometry_h: None,
+			polygon_points: None,
+			path_halfwidth: None,
+			path_points: None,
+			path_start_extension: None,
+			path_end_extension: None,
+			ctrapezoid_type: None,
+			circle_radius: None,
+			property_name: None,
+			property_values: None,
+		}
+	}
+}
+
+/// An element whose names are yet to be resolved.
+struct Pending {
+	elem: Elem,
+	sname: Option<Name>,
+	text: Option<Name>,
+	properties: Vec<(Name, Vec<Value>)>,
+}
+
+struct Cell {
+	name: Name,
+	elems: Vec<Pending>,
+}
+
+struct Reader {
+	/// The name tables, indexed by `Table`.
+	names: [HashMap<u64, String>; 4],
+	/// The next reference number of names defined without one.
+	implicit: [u64; 4],
+	cells: Vec<Cell>,
+	modal: Modal,
+	/// The elements created by the last element record, to which properties
+	/// are attached.
+	target: Option<(usize, usize)>,
+}
+
+impl Reader {
+	/// Read records up to the END record or the end of the stream. Returns
+	/// whether the END record was found.
+	fn records(&mut self, s: &mut Stream) -> Result<bool, String> {
+		while !s.at_end() {
+			let start = s.pos;
+			let id = try!(s.uint());
+			let is_element = match id {
+				17...27 | 33 => true,
+				_ => false,
+			};
+			if is_element && self.cells.is_empty() {
+				return Err(s.error_at(start, "element outside of a cell"));
+			}
+			match id {
+				0 => (),
+				2 => return Ok(true),
+				3...10 => {
+					let table = match (id - 3) / 2 {
+						0 => Table::CellName,
+						1 => Table::TextString,
+						2 => Table::PropName,
+						_ => Table::PropString,
+					};
+					let name = try!(s.string());
+					let number = if id % 2 == 0 {
+						try!(s.uint())
+					} else {
+						let n = self.implicit[table as usize];
+						self.implicit[table as usize] += 1;
+						n
+					};
+					self.names[table as usize].insert(number, name);
+					self.target = None;
+				},
+				11 | 12 => {
+					try!(s.string());
+					try!(s.interval());
+					try!(s.interval());
+					self.target = None;
+				},
+				13 | 14 => {
+					let name = if id == 13 { Name::Ref(try!(s.uint())) } else { Name::Str(try!(s.string())) };
+					self.cells.push(Cell { name: name, elems: Vec::new() });
+					self.modal = Modal::new();
+					self.target = None;
+				},
+				15 => self.modal.relative = false,
+				16 => self.modal.relative = true,
+				17 | 18 => try!(self.placement(s, id)),
+				19 => try!(self.text(s)),
+				20 => try!(self.rectangle(s)),
+				21 => try!(self.polygon(s)),
+				22 => try!(self.path(s)),
+				23...25 => try!(self.trapezoid(s, id)),
+				26 => try!(self.ctrapezoid(s)),
+				27 => try!(self.circle(s)),
+				28 => try!(self.property(s)),
+				29 => {
+					let name = self.modal.property_name.clone();
+					let values = self.modal.property_values.clone();
+					match (name, values) {
+						(Some(n), Some(v)) => self.attach_property(n, v),
+						_ => return Err(s.error_at(start, "PROPERTY repeated before any was given")),
+					}
+				},
+				30 | 31 => {
+					try!(s.uint());
+					try!(s.string());
+					if id == 31 {
+						try!(s.uint());
+					}
+				},
+				32 => {
+					try!(s.uint());
+					try!(s.string());
+				},
+				33 => try!(self.xgeometry(s)),
+				34 => {
+					if s.cblock.is_some() {
+						return Err(s.error_at(start, "CBLOCK within a CBLOCK"));
+					}
+					let method = try!(s.uint());
+					let uncompressed = try!(s.uint()) as usize;
+					let compressed = try!(s.uint()) as usize;
+					let bytes = try!(s.bytes(compressed));
+					if method != 0 {
+						return Err(s.error_at(start, &format!("unsupported CBLOCK compression {}", method)));
+					}
+					let mut data = Vec::new();
+					// Stop one byte past the announced size, which is enough to tell that
+					// it was wrong.
+					try!(DeflateDecoder::new(bytes).take(uncompressed as u64 + 1).read_to_end(&mut data).map_err(|e| s.error_at(start, &format!("corrupt CBLOCK, {}", e))));
+					if data.len() != uncompressed {
+						return Err(s.error_at(start, &format!("CBLOCK holds {} bytes instead of {}", data.len(), uncompressed)));
+					}
+					let mut inner = Stream { data: &data, pos: 0, cblock: Some(start) };
+					if try!(self.records(&mut inner)) {
+						return Err(inner.error("END record within a CBLOCK"));
+					}
+				},
+				_ => return Err(s.error_at(start, &format!("unknown record type {}", id))),
+			}
+		}
+		Ok(false)
+	}
+
+	/// Add a shape whose points are given relative to its position to the
+	/// current cell.
+	fn add_shapes(&mut self, kind: ElemKind, at: (i64, i64), points: &[(i64, i64)], repetition: Option<Repetition>, setup: &Fn(&mut Elem)) {
+		let mut elem = Elem::new(kind);
+		elem.xy = points.iter().map(|&(x, y)| Xy {
+			x: (at.0 + x) as i32,
+			y: (at.1 + y) as i32,
+		}).collect();
+		elem.repetition = repetition;
+		setup(&mut elem);
+		self.add(vec![Pending { elem: elem, sname: None, text: None, properties: Vec::new() }]);
+	}
+
+	fn add(&mut self, elems: Vec<Pending>) {
+		let cell = self.cells.len() - 1;
+		let list = &mut self.cells[cell].elems;
+		self.target = Some((cell, list.len()));
+		list.extend(elems);
+	}
+
+	/// Read the layer and datatype fields of a geometry record.
+	fn layer(&mut self, s: &mut Stream, info: u8) -> Result<(u16, u16), String> {
+		if info & 0x01 != 0 {
+			self.modal.layer = Some(try!(s.uint()));
+		}
+		if info & 0x02 != 0 {
+			self.modal.datatype = Some(try!(s.uint()));
+		}
+		Ok((try!(defined(self.modal.layer, "layer", s)) as u16, try!(defined(self.modal.datatype, "datatype", s)) as u16))
+	}
+
+	/// Read the position and repetition fields of a geometry record.
+	fn geometry_position(&mut self, s: &mut Stream, info: u8) -> Result<((i64, i64), Option<Repetition>), String> {
+		let relative = self.modal.relative;
+		try!(s.coordinate(info & 0x10 != 0, relative, &mut self.modal.geometry_x));
+		try!(s.coordinate(info & 0x08 != 0, relative, &mut self.modal.geometry_y));
+		let repetition = try!(self.repetition_field(s, info & 0x04 != 0));
+		Ok(((self.modal.geometry_x, self.modal.geometry_y), repetition))
+	}
+
+	fn repetition_field(&mut self, s: &mut Stream, present: bool) -> Result<Option<Repetition>, String> {
+		if !present {
+			return Ok(None);
+		}
+		let r = match try!(s.repetition()) {
+			Some(r) => r,
+			None => try!(defined(self.modal.repetition.clone(), "repetition", s)),
+		};
+		self.modal.repetition = Some(r.clone());
+		Ok(Some(r))
+	}
+
+	fn placement(&mut self, s: &mut Stream, id: u64) -> Result<(), String> {
+		let info = try!(s.byte());
+		if info & 0x80 != 0 {
+			self.modal.placement_cell = Some(if info & 0x40 != 0 {
+				Name::Ref(try!(s.uint()))
+			} else {
+				Name::Str(try!(s.string()))
+			});
+		}
+		let cell = try!(defined(self.modal.placement_cell.clone(), "placement-cell", s));
+		let (mag, angle) = if id == 17 {
+			(1.0, ((info >> 1) & 3) as f64 * 90.0)
+		} else {
+			let mag = if info & 0x04 != 0 { try!(s.real()) } else { 1.0 };
+			let angle = if info & 0x02 != 0 { try!(s.real()) } else { 0.0 };
+			(mag, angle)
+		};
+		let relative = self.modal.relative;
+		try!(s.coordinate(info & 0x20 != 0, relative, &mut self.modal.placement_x));
+		try!(s.coordinate(info & 0x10 != 0, relative, &mut self.modal.placement_y));
+		let at = (self.modal.placement_x, self.modal.placement_y);
+		let strans = Strans {
+			flags: if info & 0x01 != 0 { 0x8000 } else { 0 },
+			mag: mag,
+			angle: angle,
+		};
+		let xy = |x: i64, y: i64| Xy { x: x as i32, y: y as i32 };
+		let pending = |elem: Elem| Pending { elem: elem, sname: Some(cell.clone()), text: None, properties: Vec::new() };
+
+		let elem = match try!(self.repetition_field(s, info & 0x08 != 0)) {
+			// Regular arrays which fit become array references.
+			Some(Repetition::Grid(cols, rows, c, r)) if cols <= 0xffff && rows <= 0xffff => {
+				let mut elem = Elem::new(ElemKind::Aref);
+				elem.strans = Some(strans);
+				elem.colrow = (cols as u16, rows as u16);
+				elem.xy = vec![
+					xy(at.0, at.1),
+					xy(at.0 + cols as i64 * c.x as i64, at.1 + cols as i64 * c.y as i64),
+					xy(at.0 + rows as i64 * r.x as i64, at.1 + rows as i64 * r.y as i64),
+				];
+				elem
+			},
+			repetition => {
+				let mut elem = Elem::new(ElemKind::Sref);
+				elem.strans = Some(strans);
+				elem.xy = vec![xy(at.0, at.1)];
+				elem.repetition = repetition;
+				elem
+			},
+		};
+		self.add(vec![pending(elem)]);
+		Ok(())
+	}
+
+	fn text(&mut self, s: &mut Stream) -> Result<(), String> {
+		let info = try!(s.byte());
+		if info & 0x40 != 0 {
+			self.modal.text_string = Some(if info & 0x20 != 0 {
+				Name::Ref(try!(s.uint()))
+			} else {
+				Name::Str(try!(s.string()))
+			});
+		}
+		let text = try!(defined(self.modal.text_string.clone(), "text-string", s));
+		if info & 0x01 != 0 {
+			self.modal.textlayer = Some(try!(s.uint()));
+		}
+		if info & 0x02 != 0 {
+			self.modal.texttype = Some(try!(s.uint()));
+		}
+		let layer = try!(defined(self.modal.textlayer, "textlayer", s)) as u16;
+		let texttype = try!(defined(self.modal.texttype, "texttype", s)) as u16;
+		let relative = self.modal.relative;
+		try!(s.coordinate(info & 0x10 != 0, relative, &mut self.modal.text_x));
+		try!(s.coordinate(info & 0x08 != 0, relative, &mut self.modal.text_y));
+		let at = (self.modal.text_x, self.modal.text_y);
+
+		let mut elem = Elem::new(ElemKind::Text);
+		elem.layer = layer;
+		elem.datatype = texttype;
+		elem.xy = vec![Xy { x: at.0 as i32, y: at.1 as i32 }];
+		elem.repetition = try!(self.repetition_field(s, info & 0x04 != 0));
+		self.add(vec![Pending { elem: elem, sname: None, text: Some(text), properties: Vec::new() }]);
+		Ok(())
+	}
+
+	fn rectangle(&mut self, s: &mut Stream) -> Result<(), String> {
+		let info = try!(s.byte());
+		let (layer, datatype) = try!(self.layer(s, info));
+		if info & 0x40 != 0 {
+			self.modal.geometry_w = Some(try!(s.uint()) as i64);
+		}
+		if info & 0x80 != 0 {
+			// A square has no height of its own.
+			self.modal.geometry_h = self.modal.geometry_w;
+		} else if info & 0x20 != 0 {
+			self.modal.geometry_h = Some(try!(s.uint()) as i64);
+		}
+		let w = try!(defined(self.modal.geometry_w, "geometry-w", s));
+		let h = try!(defined(self.modal.geometry_h, "geometry-h", s));
+		let (at, repetition) = try!(self.geometry_position(s, info));
+		let ring = [(0, 0), (w, 0), (w, h), (0, h), (0, 0)];
+		self.add_shapes(ElemKind::Boundary, at, &ring, repetition, &|e| {
+			e.layer = layer;
+			e.datatype = datatype;
+		});
+		Ok(())
+	}
+
+	fn polygon(&mut self, s: &mut Stream) -> Result<(), String> {
+		let info = try!(s.byte());
+		let (layer, datatype) = try!(self.layer(s, info));
+		if info & 0x20 != 0 {
+			self.modal.polygon_points = Some(try!(s.point_list(true)));
+		}
+		let mut ring = try!(defined(self.modal.polygon_points.clone(), "polygon-point-list", s));
+		ring.push((0, 0));
+		let (at, repetition) = try!(self.geometry_position(s, info));
+		self.add_shapes(ElemKind::Boundary, at, &ring, repetition, &|e| {
+			e.layer = layer;
+			e.datatype = datatype;
+		});
+		Ok(())
+	}
+
+	fn path(&mut self, s: &mut Stream) -> Result<(), String> {
+		let info = try!(s.byte());
+		let (layer, datatype) = try!(self.layer(s, info));
+		if info & 0x40 != 0 {
+			self.modal.path_halfwidth = Some(try!(s.uint()) as i64);
+		}
+		let halfwidth = try!(defined(self.modal.path_halfwidth, "path-halfwidth", s));
+		if info & 0x80 != 0 {
+			let scheme = try!(s.uint());
+			let start = try!(extension(s, scheme >> 2 & 3, halfwidth));
+			let end = try!(extension(s, scheme & 3, halfwidth));
+			if start.is_some() {
+				self.modal.path_start_extension = start;
+			}
+			if end.is_some() {
+				self.modal.path_end_extension = end;
+			}
+		}
+		let start = try!(defined(self.modal.path_start_extension, "path-start-extension", s));
+		let end = try!(defined(self.modal.path_end_extension, "path-end-extension", s));
+		if info & 0x20 != 0 {
+			self.modal.path_points = Some(try!(s.point_list(false)));
+		}
+		let points = try!(defined(self.modal.path_points.clone(), "path-point-list", s));
+		let (at, repetition) = try!(self.geometry_position(s, info));
+
+		let xy: Vec<Xy> = points.iter().map(|&(x, y)| Xy { x: x as i32, y: y as i32 }).collect();
+		let ring: Vec<(i64, i64)> = path_outline(&xy, halfwidth as f64, (start as f64, end as f64)).into_iter().map(|p| {
+			(p.x as i64, p.y as i64)
+		}).collect();
+		if ring.is_empty() {
+			return Ok(());
+		}
+		self.add_shapes(ElemKind::Boundary, at, &ring, repetition, &|e| {
+			e.layer = layer;
+			e.datatype = datatype;
+		});
+		Ok(())
+	}
+
+	fn trapezoid(&mut self, s: &mut Stream, id: u64) -> Result<(), String> {
+		let info = try!(s.byte());
+		let (layer, datatype) = try!(self.layer(s, info));
+		if info & 0x40 != 0 {
+			self.modal.geometry_w = Some(try!(s.uint()) as i64);
+		}
+		if info & 0x20 != 0 {
+			self.modal.geometry_h = Some(try!(s.uint()) as i64);
+		}
+		let w = try!(defined(self.modal.geometry_w, "geometry-w", s));
+		let h = try!(defined(self.modal.geometry_h, "geometry-h", s));
+		let a = if id != 25 { try!(s.sint()) } else { 0 };
+		let b = if id != 24 { try!(s.sint()) } else { 0 };
+		let (at, repetition) = try!(self.geometry_position(s, info));
+
+		// The deltas move the corners along the edges of length w, or h for
+		// vertical trapezoids.
+		let ring = if info & 0x80 != 0 {
+			[(0, a.max(0)), (0, h + b.min(0)), (w, h - b.max(0)), (w, -a.min(0)), (0, a.max(0))]
+		} else {
+			[(a.max(0), h), (w + b.min(0), h), (w - b.max(0), 0), (-a.min(0), 0), (a.max(0), h)]
+		};
+		self.add_shapes(ElemKind::Boundary, at, &ring, repetition, &|e| {
+			e.layer = layer;
+			e.datatype = datatype;
+		});
+		Ok(())
+	}
+
+	fn ctrapezoid(&mut self, s: &mut Stream) -> Result<(), String> {
+		let info = try!(s.byte());
+		let (layer, datatype) = try!(self.layer(s, info));
+		if info & 0x80 != 0 {
+			self.modal.ctrapezoid_type = Some(try!(s.uint()));
+		}
+		let kind = try!(defined(self.modal.ctrapezoid_type, "ctrapezoid-type", s));
+		if kind >= CTRAPEZOIDS.len() as u64 {
+			return Err(s.error(&format!("invalid ctrapezoid type {}", kind)));
+		}
+		if info & 0x40 != 0 {
+			self.modal.geometry_w = Some(try!(s.uint()) as i64);
+		}
+		if info & 0x20 != 0 {
+			self.modal.geometry_h = Some(try!(s.uint()) as i64);
+		}
+
+		// Some types derive one dimension from the other.
+		let (w, h) = match kind {
+			16...19 | 25 => {
+				let w = try!(defined(self.modal.geometry_w, "geometry-w", s));
+				(w, w)
+			},
+			20 | 21 => {
+				let h = try!(defined(self.modal.geometry_h, "geometry-h", s));
+				(2 * h, h)
+			},
+			22 | 23 => {
+				let w = try!(defined(self.modal.geometry_w, "geometry-w", s));
+				(w, 2 * w)
+			},
+			_ => (try!(defined(self.modal.geometry_w, "geometry-w", s)), try!(defined(self.modal.geometry_h, "geometry-h", s))),
+		};
+		let (at, repetition) = try!(self.geometry_position(s, info));
+
+		let mut ring: Vec<(i64, i64)> = Vec::with_capacity(5);
+		for &(xw, xh, yw, yh) in &CTRAPEZOIDS[kind as usize] {
+			let p = (xw as i64 * w + xh as i64 * h, yw as i64 * w + yh as i64 * h);
+			if ring.last() != Some(&p) {
+				ring.push(p);
+			}
+		}
+		let first = ring[0];
+		ring.push(first);
+		self.add_shapes(ElemKind::Boundary, at, &ring, repetition, &|e| {
+			e.layer = layer;
+			e.datatype = datatype;
+		});
+		Ok(())
+	}
+
+	fn circle(&mut self, s: &mut Stream) -> Result<(), String> {
+		let info = try!(s.byte());
+		let (layer, datatype) = try!(self.layer(s, info));
+		if info & 0x20 != 0 {
+			self.modal.circle_radius = Some(try!(s.uint()) as i64);
+		}
+		let r = try!(defined(self.modal.circle_radius, "circle-radius", s)) as f64;
+		let (at, repetition) = try!(self.geometry_position(s, info));
+		let ring: Vec<(i64, i64)> = (0..CIRCLE_SEGMENTS + 1).map(|i| {
+			let phi = (i % CIRCLE_SEGMENTS) as f64 * 2.0 * ::std::f64::consts::PI / CIRCLE_SEGMENTS as f64;
+			((r * phi.cos()).round() as i64, (r * phi.sin()).round() as i64)
+		}).collect();
+		self.add_shapes(ElemKind::Boundary, at, &ring, repetition, &|e| {
+			e.layer = layer;
+			e.datatype = datatype;
+		});
+		Ok(())
+	}
+
+	/// Skip a user-defined shape, which cannot be drawn, but keep track of
+	/// the modal variables it sets.
+	fn xgeometry(&mut self, s: &mut Stream) -> Result<(), String> {
+		let info = try!(s.byte());
+		try!(s.uint());
+		try!(self.layer(s, info));
+		try!(s.string());
+		try!(self.geometry_position(s, info));
+		self.target = None;
+		Ok(())
+	}
+
+	fn property(&mut self, s: &mut Stream) -> Result<(), String> {
+		let info = try!(s.byte());
+		if info & 0x04 != 0 {
+			self.modal.property_name = Some(if info & 0x02 != 0 {
+				Name::Ref(try!(s.uint()))
+			} else {
+				Name::Str(try!(s.string()))
+			});
+		}
+		let name = try!(defined(self.modal.property_name.clone(), "last-property-name", s));
+		if info & 0x08 == 0 {
+			let count = match info >> 4 {
+				15 => try!(s.uint()),
+				n => n as u64,
+			};
+			let mut values = Vec::new();
+			for _ in 0..count {
+				values.push(try!(s.value()));
+			}
+			self.modal.property_values = Some(values);
+		}
+		let values = try!(defined(self.modal.property_values.clone(), "last-value-list", s));
+		self.attach_property(name, values);
+		Ok(())
+	}
+
+	/// Attach a property to the elements created by the last element record.
+	/// Properties of cells and of the file as a whole are dropped.
+	fn attach_property(&mut self, name: Name, values: Vec<Value>) {
+		if let Some((cell, first)) = self.target {
+			for p in &mut self.cells[cell].elems[first..] {
+				p.properties.push((name.clone(), values.clone()));
+			}
+		}
+	}
+
+	/// Resolve the names and assemble the library.
+	fn finish(self, unit: f64) -> Result<Library, String> {
+		let names = self.names;
+		let lookup = |table: Table, name: &Name| -> Result<String, String> {
+			match *name {
+				Name::Str(ref s) => Ok(s.clone()),
+				Name::Ref(n) => match names[table as usize].get(&n) {
+					Some(s) => Ok(s.clone()),
+					None => Err(format!("undefined {} reference {}", match table {
+						Table::CellName => "cell name",
+						Table::TextString => "text string",
+						Table::PropName => "property name",
+						Table::PropString => "property string",
+					}, n)),
+				},
+			}
+		};
+
+		let mut structs = Vec::with_capacity(self.cells.len());
+		for cell in self.cells {
+			let name = try!(lookup(Table::CellName, &cell.name));
+			let mut elems = Vec::with_capacity(cell.elems.len());
+			for p in cell.elems {
+				let mut elem = p.elem;
+				if let Some(ref n) = p.sname {
+					elem.sname = try!(lookup(Table::CellName, n));
+				}
+				if let Some(ref n) = p.text {
+					elem.string = try!(lookup(Table::TextString, n));
+				}
+				for (pname, values) in p.properties {
+					let pname = try!(lookup(Table::PropName, &pname));
+					let mut strings = Vec::with_capacity(values.len());
+					for v in values {
+						strings.push(match v {
+							Value::Real(x) => x.to_string(),
+							Value::Int(x) => x.to_string(),
+							Value::Str(s) => s,
+							Value::Ref(n) => try!(lookup(Table::PropString, &Name::Ref(n))),
+						});
+					}
+					if pname == "S_GDS_PROPERTY" && strings.len() == 2 {
+						let value = strings.pop().unwrap();
+						elem.properties.push((strings.pop().unwrap(), value));
+					} else {
+						elem.properties.push((pname, strings.join(" ")));
+					}
+				}
+				elems.push(elem);
+			}
+			structs.push((name, elems));
+		}
+
+		// The unit is the number of database units per micrometer.
+		Ok(Library::new(String::new(), (1.0 / unit, 1e-6 / unit), structs))
+	}
+}
+
+fn defined<T>(v: Option<T>, what: &str, s: &Stream) -> Result<T, String> {
+	match v {
+		Some(v) => Ok(v),
+		None => Err(s.error(&format!("modal variable {} used before being set", what))),
+	}
+}
+
+/// Read a path extension given by a two bit scheme: keep the modal value,
+/// flush, half the width, or explicit.
+fn extension(s: &mut Stream, scheme: u64, halfwidth: i64) -> Result<Option<i64>, String> {
+	Ok(match scheme {
+		1 => Some(0),
+		2 => Some(halfwidth),
+		3 => Some(try!(s.sint())),
+		_ => None,
+	})
+}
+
+/// The number of edges circles are approximated with.
+const CIRCLE_SEGMENTS: usize = 64;
+
+/// The corners of each ctrapezoid type, given as the factors of the width
+/// and height in x, and of the width and height in y. Triangles repeat their
+/// last corner.
+const CTRAPEZOIDS: [[(i8, i8, i8, i8); 4]; 26] = [
+	[(0, 0, 0, 0), (0, 0, 0, 1), (1, -1, 0, 1), (1, 0, 0, 0)],
+	[(0, 0, 0, 0), (0, 0, 0, 1), (1, 0, 0, 1), (1, -1, 0, 0)],
+	[(0, 0, 0, 0), (0, 1, 0, 1), (1, 0, 0, 1), (1, 0, 0, 0)],
+	[(0, 1, 0, 0), (0, 0, 0, 1), (1, 0, 0, 1), (1, 0, 0, 0)],
+	[(0, 0, 0, 0), (0, 1, 0, 1), (1, -1, 0, 1), (1, 0, 0, 0)],
+	[(0, 1, 0, 0), (0, 0, 0, 1), (1, 0, 0, 1), (1, -1, 0, 0)],
+	[(0, 0, 0, 0), (0, 1, 0, 1), (1, 0, 0, 1), (1, -1, 0, 0)],
+	[(0, 1, 0, 0), (0, 0, 0, 1), (1, -1, 0, 1), (1, 0, 0, 0)],
+	[(0, 0, 0, 0), (0, 0, 0, 1), (1, 0, -1, 1), (1, 0, 0, 0)],
+	[(0, 0, 0, 0), (0, 0, -1, 1), (1, 0, 0, 1), (1, 0, 0, 0)],
+	[(0, 0, 0, 0), (0, 0, 0, 1), (1, 0, 0, 1), (1, 0, 1, 0)],
+	[(0, 0, 1, 0), (0, 0, 0, 1), (1, 0, 0, 1), (1, 0, 0, 0)],
+	[(0, 0, 0, 0), (0, 0, 0, 1), (1, 0, -1, 1), (1, 0, 1, 0)],
+	[(0, 0, 1, 0), (0, 0, -1, 1), (1, 0, 0, 1), (1, 0, 0, 0)],
+	[(0, 0, 0, 0), (0, 0, -1, 1), (1, 0, 0, 1), (1, 0, 1, 0)],
+	[(0, 0, 1, 0), (0, 0, 0, 1), (1, 0, -1, 1), (1, 0, 0, 0)],
+	[(0, 0, 0, 0), (0, 0, 0, 1), (1, 0, 0, 0), (1, 0, 0, 0)],
+	[(0, 0, 0, 0), (0, 0, 0, 1), (1, 0, 0, 1), (1, 0, 0, 1)],
+	[(0, 0, 0, 0), (1, 0, 0, 1), (1, 0, 0, 0), (1, 0, 0, 0)],
+	[(0, 0, 0, 1), (1, 0, 0, 1), (1, 0, 0, 0), (1, 0, 0, 0)],
+	[(0, 0, 0, 0), (0, 1, 0, 1), (1, 0, 0, 0), (1, 0, 0, 0)],
+	[(0, 0, 0, 1), (1, 0, 0, 1), (0, 1, 0, 0), (0, 1, 0, 0)],
+	[(0, 0, 0, 0), (0, 0, 0, 1), (1, 0, 1, 0), (1, 0, 1, 0)],
+	[(1, 0, 0, 0), (0, 0, 1, 0), (1, 0, 0, 1), (1, 0, 0, 1)],
+	[(0, 0, 0, 0), (0, 0, 0, 1), (1, 0, 0, 1), (1, 0, 0, 0)],
+	[(0, 0, 0, 0), (0, 0, 0, 1), (1, 0, 0, 1), (1, 0, 0, 0)],
+];
+
+/// The directions of 2-deltas and 3-deltas, and of octangular g-deltas.
+const DIRECTIONS: [(i64, i64); 8] = [(1, 0), (0, 1), (-1, 0), (0, -1), (1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+struct Stream<'a> {
+	data: &'a [u8],
+	pos: usize,
+	/// The offset of the CBLOCK record the data was decompressed from.
+	cblock: Option<usize>,
+}
+
+impl<'a> Stream<'a> {
+	fn at_end(&self) -> bool {
+		self.pos >= self.data.len()
+	}
+
+	fn error(&self, msg: &str) -> String {
+		self.error_at(self.pos, msg)
+	}
+
+	fn error_at(&self, pos: usize, msg: &str) -> String {
+		match self.cblock {
+			Some(c) => format!("{} at byte {} of the CBLOCK at byte {}", msg, pos, c),
+			None => format!("{} at byte {}", msg, pos),
+		}
+	}
+
+	fn byte(&mut self) -> Result<u8, String> {
+		match self.data.get(self.pos) {
+			Some(&b) => {
+				self.pos += 1;
+				Ok(b)
+			},
+			None => Err(self.error("unexpected end of data")),
+		}
+	}
+
+	fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+		if self.data.len() - self.pos < n {
+			return Err(self.error(&format!("truncated data, {} bytes expected", n)));
+		}
+		let b = &self.data[self.pos..self.pos+n];
+		self.pos += n;
+		Ok(b)
+	}
+
+	/// Read an unsigned integer, stored in groups of 7 bits with the least
+	/// significant first and the top bit set on all but the last byte.
+	fn uint(&mut self) -> Result<u64, String> {
+		let start = self.pos;
+		let mut v = 0u64;
+		let mut shift = 0;
+		loop {
+			let b = try!(self.byte());
+			if shift > 63 || (shift > 56 && (b & 0x7f) >> (64 - shift) != 0) {
+				return Err(self.error_at(start, "integer too large"));
+			}
+			v |= ((b & 0x7f) as u64) << shift;
+			if b & 0x80 == 0 {
+				return Ok(v);
+			}
+			shift += 7;
+		}
+	}
+
+	/// Read a signed integer, whose lowest bit is the sign.
+	fn sint(&mut self) -> Result<i64, String> {
+		let v = try!(self.uint());
+		let m = (v >> 1) as i64;
+		Ok(if v & 1 != 0 { -m } else { m })
+	}
+
+	fn real(&mut self) -> Result<f64, String> {
+		let start = self.pos;
+		Ok(match try!(self.uint()) {
+			0 => try!(self.uint()) as f64,
+			1 => -(try!(self.uint()) as f64),
+			2 => 1.0 / try!(self.uint()) as f64,
+			3 => -1.0 / try!(self.uint()) as f64,
+			4 => try!(self.uint()) as f64 / try!(self.uint()) as f64,
+			5 => -(try!(self.uint()) as f64) / try!(self.uint()) as f64,
+			6 => {
+				let b = try!(self.bytes(4));
+				let bits = b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24;
+				f32::from_bits(bits) as f64
+			},
+			7 => {
+				let b = try!(self.bytes(8));
+				let mut bits = 0u64;
+				for (i, &byte) in b.iter().enumerate() {
+					bits |= (byte as u64) << (8 * i);
+				}
+				f64::from_bits(bits)
+			},
+			t => return Err(self.error_at(start, &format!("invalid real type {}", t))),
+		})
+	}
+
+	fn string(&mut self) -> Result<String, String> {
+		let len = try!(self.uint()) as usize;
+		let b = try!(self.bytes(len));
+		Ok(String::from_utf8_lossy(b).into_owned())
+	}
+
+	/// Skip a layer or datatype interval.
+	fn interval(&mut self) -> Result<(), String> {
+		match try!(self.uint()) {
+			0 => (),
+			1...3 => {
+				try!(self.uint());
+			},
+			4 => {
+				try!(self.uint());
+				try!(self.uint());
+			},
+			t => return Err(self.error(&format!("invalid interval type {}", t))),
+		}
+		Ok(())
+	}
+
+	/// Read a coordinate if present, which replaces or, in relative mode,
+	/// offsets the modal value.
+	fn coordinate(&mut self, present: bool, relative: bool, modal: &mut i64) -> Result<(), String> {
+		if present {
+			let v = try!(self.sint());
+			*modal = if relative { *modal + v } else { v };
+		}
+		Ok(())
+	}
+
+	fn gdelta(&mut self) -> Result<(i64, i64), String> {
+		let v = try!(self.uint());
+		if v & 1 == 0 {
+			let d = DIRECTIONS[(v >> 1 & 7) as usize];
+			let m = (v >> 4) as i64;
+			Ok((d.0 * m, d.1 * m))
+		} else {
+			let x = (v >> 2) as i64;
+			let x = if v & 2 != 0 { -x } else { x };
+			Ok((x, try!(self.sint())))
+		}
+	}
+
+	/// Read a point list, returning the points relative to the first, which
+	/// is the origin. Manhattan polygons get the corner implied before the
+	/// origin.
+	fn point_list(&mut self, polygon: bool) -> Result<Vec<(i64, i64)>, String> {
+		let start = self.pos;
+		let kind = try!(self.uint());
+		let count = try!(self.uint());
+		let mut points = vec![(0, 0)];
+		let (mut x, mut y) = (0i64, 0i64);
+		let mut prev = (0i64, 0i64);
+		for i in 0..count {
+			let d = match kind {
+				0 | 1 => {
+					let v = try!(self.sint());
+					if (i % 2 == 0) == (kind == 0) { (v, 0) } else { (0, v) }
+				},
+				2 => {
+					let v = try!(self.uint());
+					let d = DIRECTIONS[(v & 3) as usize];
+					let m = (v >> 2) as i64;
+					(d.0 * m, d.1 * m)
+				},
+				3 => {
+					let v = try!(self.uint());
+					let d = DIRECTIONS[(v & 7) as usize];
+					let m = (v >> 3) as i64;
+					(d.0 * m, d.1 * m)
+				},
+				4 => try!(self.gdelta()),
+				5 => {
+					let g = try!(self.gdelta());
+					prev = (prev.0 + g.0, prev.1 + g.1);
+					prev
+				},
+				t => return Err(self.error_at(start, &format!("invalid point list type {}", t))),
+			};
+			x += d.0;
+			y += d.1;
+			points.push((x, y));
+		}
+		if polygon && (kind == 0 || kind == 1) {
+			let horizontal_next = (count % 2 == 0) == (kind == 0);
+			points.push(if horizontal_next { (0, y) } else { (x, 0) });
+		}
+		Ok(points)
+	}
+
+	/// Read a repetition. Returns `None` if the previous one is to be
+	/// reused.
+	fn repetition(&mut self) -> Result<Option<Repetition>, String> {
+		let start = self.pos;
+		let kind = try!(self.uint());
+		Ok(Some(match kind {
+			0 => return Ok(None),
+			1 => {
+				let cols = try!(self.count(2));
+				let rows = try!(self.count(2));
+				let dx = try!(self.spacing());
+				let dy = try!(self.spacing());
+				try!(self.grid(start, cols, rows, (dx, 0), (0, dy)))
+			},
+			2 => {
+				let cols = try!(self.count(2));
+				let dx = try!(self.spacing());
+				try!(self.grid(start, cols, 1, (dx, 0), (0, 0)))
+			},
+			3 => {
+				let rows = try!(self.count(2));
+				let dy = try!(self.spacing());
+				try!(self.grid(start, 1, rows, (0, 0), (0, dy)))
+			},
+			4...7 | 10 | 11 => {
+				let n = try!(self.count(1));
+				if n >= MAX_REPETITION {
+					return Err(self.error_at(start, &format!("repetition of {} copies exceeds the limit of {}", n + 1, MAX_REPETITION)));
+				}
+				let grid = if kind == 5 || kind == 7 || kind == 11 { try!(self.spacing()) } else { 1 };
+				let mut offsets = vec![Xy { x: 0, y: 0 }];
+				let (mut x, mut y) = (0i64, 0i64);
+				for _ in 0..n {
+					let d = match kind {
+						4 | 5 => (try!(self.spacing()), 0),
+						6 | 7 => (0, try!(self.spacing())),
+						_ => try!(self.gdelta()),
+					};
+					x += try!(self.in_range(start, d.0)) as i64 * grid;
+					y += try!(self.in_range(start, d.1)) as i64 * grid;
+					offsets.push(Xy { x: try!(self.in_range(start, x)), y: try!(self.in_range(start, y)) });
+				}
+				Repetition::List(offsets)
+			},
+			8 => {
+				let cols = try!(self.count(2));
+				let rows = try!(self.count(2));
+				let c = try!(self.gdelta());
+				let r = try!(self.gdelta());
+				try!(self.grid(start, cols, rows, c, r))
+			},
+			9 => {
+				let cols = try!(self.count(2));
+				let c = try!(self.gdelta());
+				try!(self.grid(start, cols, 1, c, (0, 0)))
+			},
+			t => return Err(self.error_at(start, &format!("invalid repetition type {}", t))),
+		}))
+	}
+
+	/// Read the number of copies along one dimension of a repetition, which
+	/// is stored less the given minimum.
+	fn count(&mut self, min: u64) -> Result<u64, String> {
+		let start = self.pos;
+		match try!(self.uint()).checked_add(min) {
+			Some(n) => Ok(n),
+			None => Err(self.error_at(start, "repetition count too large")),
+		}
+	}
+
+	/// Read the spacing of a repetition.
+	fn spacing(&mut self) -> Result<i64, String> {
+		let start = self.pos;
+		let v = try!(self.uint());
+		if v > ::std::i32::MAX as u64 {
+			return Err(self.error_at(start, "repetition reaches beyond the coordinate range"));
+		}
+		Ok(v as i64)
+	}
+
+	/// Check that a displacement of a repetition fits into a coordinate.
+	fn in_range(&self, start: usize, v: i64) -> Result<i32, String> {
+		if v < ::std::i32::MIN as i64 || v > ::std::i32::MAX as i64 {
+			return Err(self.error_at(start, "repetition reaches beyond the coordinate range"));
+		}
+		Ok(v as i32)
+	}
+
+	/// Assemble a grid repetition, checking that the number of copies is
+	/// within the limit and that all of them are within the coordinate range.
+	fn grid(&self, start: usize, cols: u64, rows: u64, c: (i64, i64), r: (i64, i64)) -> Result<Repetition, String> {
+		match cols.checked_mul(rows) {
+			Some(n) if n <= MAX_REPETITION => (),
+			_ => return Err(self.error_at(start, &format!("repetition of {} by {} copies exceeds the limit of {}", cols, rows, MAX_REPETITION))),
+		}
+		let c = Xy { x: try!(self.in_range(start, c.0)), y: try!(self.in_range(start, c.1)) };
+		let r = Xy { x: try!(self.in_range(start, r.0)), y: try!(self.in_range(start, r.1)) };
+		let reach = |a: i32, b: i32| (cols - 1) as i64 * (a as i64).abs() + (rows - 1) as i64 * (b as i64).abs();
+		try!(self.in_range(start, reach(c.x, r.x)));
+		try!(self.in_range(start, reach(c.y, r.y)));
+		Ok(Repetition::Grid(cols as u32, rows as u32, c, r))
+	}
+
+	fn value(&mut self) -> Result<Value, String> {
+		let start = self.pos;
+		Ok(match try!(self.uint()) {
+			0...7 => {
+				// Reals share their type codes with the value types.
+				self.pos = start;
+				Value::Real(try!(self.real()))
+			},
+			8 => Value::Int(try!(self.uint()) as i64),
+			9 => Value::Int(try!(self.sint())),
+			10...12 => Value::Str(try!(self.string())),
+			13...15 => Value::Ref(try!(self.uint())),
+			t => return Err(self.error_at(start, &format!("invalid property value type {}", t))),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn uint(out: &mut Vec<u8>, mut v: u64) {
+		while v >= 0x80 {
+			out.push(v as u8 | 0x80);
+			v >>= 7;
+		}
+		out.push(v as u8);
+	}
+
+	fn sint(out: &mut Vec<u8>, v: i64) {
+		uint(out, (v.abs() as u64) << 1 | (v < 0) as u64);
+	}
+
+	fn uints(values: &[u64]) -> Vec<u8> {
+		let mut out = Vec::new();
+		for &v in values {
+			uint(&mut out, v);
+		}
+		out
+	}
+
+	fn string(out: &mut Vec<u8>, s: &str) {
+		uint(out, s.len() as u64);
+		out.extend_from_slice(s.as_bytes());
+	}
+
+	/// A file with a single cell `TOP` holding the given records, at 1000
+	/// database units per micrometer.
+	fn file(body: &[u8]) -> Vec<u8> {
+		let mut f = MAGIC.to_vec();
+		uint(&mut f, 1);
+		string(&mut f, "1.0");
+		uint(&mut f, 0);
+		uint(&mut f, 1000);
+		for _ in 0..13 {
+			uint(&mut f, 0);
+		}
+		uint(&mut f, 14);
+		string(&mut f, "TOP");
+		f.extend_from_slice(body);
+		uint(&mut f, 2);
+		f
+	}
+
+	fn elems(body: &[u8]) -> Vec<Elem> {
+		let lib = read(&file(body)).unwrap();
+		let top = lib.find_struct("TOP").unwrap();
+		top.elems().cloned().collect()
+	}
+
+	fn read_error(body: &[u8]) -> String {
+		match read(&file(body)) {
+			Ok(_) => panic!("file accepted"),
+			Err(e) => e,
+		}
+	}
+
+	fn xy(elem: &Elem) -> Vec<(i32, i32)> {
+		elem.get_xy().iter().map(|p| (p.x, p.y)).collect()
+	}
+
+	/// A 10 by 5 rectangle on layer 1 at (100, 200), followed by the given
+	/// repetition if any.
+	fn rectangle(repetition: Option<&[u8]>) -> Vec<u8> {
+		let mut r = vec![20, if repetition.is_some() { 0x7f } else { 0x7b }, 1, 0, 10, 5];
+		sint(&mut r, 100);
+		sint(&mut r, 200);
+		if let Some(rep) = repetition {
+			r.extend_from_slice(rep);
+		}
+		r
+	}
+
+	fn offsets(elem: &Elem) -> Vec<(i32, i32)> {
+		let rep = elem.get_repetition().unwrap();
+		(0..rep.len()).map(|i| rep.offset(i)).map(|p| (p.x, p.y)).collect()
+	}
+
+	#[test]
+	fn rectangles() {
+		let e = elems(&rectangle(None));
+		assert_eq!(e.len(), 1);
+		assert_eq!(e[0].get_kind(), ElemKind::Boundary);
+		assert_eq!((e[0].get_layer(), e[0].get_type()), (1, 0));
+		assert_eq!(xy(&e[0]), vec![(100, 200), (110, 200), (110, 205), (100, 205), (100, 200)]);
+		assert!(e[0].get_repetition().is_none());
+	}
+
+	#[test]
+	fn point_lists() {
+		let polygon = |list: &[u8]| {
+			let mut r = vec![21, 0x23, 1, 0];
+			r.extend_from_slice(list);
+			let e = elems(&r);
+			xy(&e[0])
+		};
+		// Manhattan lists, horizontal or vertical first, with the implied
+		// corner.
+		assert_eq!(polygon(&[0, 2, 20, 10]), vec![(0, 0), (10, 0), (10, 5), (0, 5), (0, 0)]);
+		assert_eq!(polygon(&[1, 2, 10, 20]), vec![(0, 0), (0, 5), (10, 5), (10, 0), (0, 0)]);
+		// 2-deltas: east, north, west.
+		assert_eq!(polygon(&[2, 3, 10 << 2, 5 << 2 | 1, 10 << 2 | 2]), vec![(0, 0), (10, 0), (10, 5), (0, 5), (0, 0)]);
+		// 3-deltas: east, north-east, west.
+		assert_eq!(polygon(&[3, 3, 10 << 3, 5 << 3 | 4, 15 << 3 | 2]), vec![(0, 0), (10, 0), (15, 5), (0, 5), (0, 0)]);
+		// g-deltas in both forms.
+		assert_eq!(polygon(&uints(&[4, 3, 10 << 4, 3 << 2 | 1, 14, 13 << 2 | 3, 5])), vec![(0, 0), (10, 0), (13, 7), (0, 5), (0, 0)]);
+		// Double deltas, each adding to the previous delta.
+		assert_eq!(polygon(&uints(&[5, 2, 10 << 4, 10 << 2 | 3, 10])), vec![(0, 0), (10, 0), (10, 5), (0, 0)]);
+
+		let mut r = vec![21, 0x23, 1, 0];
+		r.extend_from_slice(&[6, 1, 0]);
+		assert!(read_error(&r).starts_with("invalid point list type 6"));
+	}
+
+	#[test]
+	fn paths_become_boundaries() {
+		// A path of half-width 1 extended by its half-width on both ends.
+		let e = elems(&[22, 0xe3, 1, 0, 1, 2 << 2 | 2, 0, 1, 20]);
+		assert_eq!(e.len(), 1);
+		assert_eq!(e[0].get_kind(), ElemKind::Boundary);
+		assert_eq!(xy(&e[0]), vec![(-1, 1), (11, 1), (11, -1), (-1, -1), (-1, 1)]);
+
+		// Explicit extensions.
+		let e = elems(&[22, 0xe3, 1, 0, 1, 3 << 2 | 3, 4, 6, 0, 1, 20]);
+		assert_eq!(xy(&e[0]), vec![(-2, 1), (13, 1), (13, -1), (-2, -1), (-2, 1)]);
+	}
+
+	#[test]
+	fn ctrapezoids() {
+		let ctrapezoid = |kind: u8, w: u8, h: u8| {
+			let e = elems(&[26, 0xe3, 1, 0, kind, w, h]);
+			xy(&e[0])
+		};
+		assert_eq!(ctrapezoid(0, 10, 5), vec![(0, 0), (0, 5), (5, 5), (10, 0), (0, 0)]);
+		assert_eq!(ctrapezoid(16, 4, 0), vec![(0, 0), (0, 4), (4, 0), (0, 0)]);
+		assert_eq!(ctrapezoid(20, 0, 3), vec![(0, 0), (3, 3), (6, 0), (0, 0)]);
+		assert_eq!(ctrapezoid(25, 4, 0), vec![(0, 0), (0, 4), (4, 4), (4, 0), (0, 0)]);
+
+		// Every type yields a closed ring enclosing some area. Types 8 to 15
+		// are the upright ones, taller than wide.
+		for kind in 0..26 {
+			let ring = if kind >= 8 && kind < 16 { ctrapezoid(kind, 4, 8) } else { ctrapezoid(kind, 8, 4) };
+			assert_eq!(ring.first(), ring.last(), "type {}", kind);
+			let area: i64 = ring.windows(2).map(|w| w[0].0 as i64 * w[1].1 as i64 - w[1].0 as i64 * w[0].1 as i64).sum();
+			assert!(area != 0, "type {}", kind);
+		}
+		assert!(read_error(&[26, 0xe3, 1, 0, 26, 8, 4]).starts_with("invalid ctrapezoid type 26"));
+	}
+
+	#[test]
+	fn repetitions() {
+		let rep = |bytes: &[u8]| offsets(&elems(&rectangle(Some(bytes)))[0]);
+		assert_eq!(rep(&[1, 1, 0, 10, 20]), vec![(0, 0), (10, 0), (20, 0), (0, 20), (10, 20), (20, 20)]);
+		assert_eq!(rep(&[2, 0, 10]), vec![(0, 0), (10, 0)]);
+		assert_eq!(rep(&[3, 1, 20]), vec![(0, 0), (0, 20), (0, 40)]);
+		assert_eq!(rep(&[4, 1, 10, 5]), vec![(0, 0), (10, 0), (15, 0)]);
+		assert_eq!(rep(&[5, 1, 10, 1, 2]), vec![(0, 0), (10, 0), (30, 0)]);
+		assert_eq!(rep(&[6, 0, 7]), vec![(0, 0), (0, 7)]);
+		assert_eq!(rep(&[7, 0, 3, 2]), vec![(0, 0), (0, 6)]);
+		assert_eq!(rep(&uints(&[8, 0, 0, 10 << 4, 10 << 4 | 2])), vec![(0, 0), (10, 0), (0, 10), (10, 10)]);
+		assert_eq!(rep(&[9, 0, 5 << 4 | 8]), vec![(0, 0), (5, 5)]);
+		assert_eq!(rep(&[10, 0, 3 << 2 | 1, 14]), vec![(0, 0), (3, 7)]);
+		assert_eq!(rep(&[11, 0, 2, 3 << 2 | 1, 14]), vec![(0, 0), (6, 14)]);
+
+		// Type 0 reuses the previous repetition.
+		let mut body = rectangle(Some(&[2, 0, 10]));
+		body.extend(rectangle(Some(&[0])));
+		let e = elems(&body);
+		assert_eq!(offsets(&e[1]), vec![(0, 0), (10, 0)]);
+		let e = read_error(&rectangle(Some(&[0])));
+		assert!(e.starts_with("modal variable repetition used before being set"), "{}", e);
+	}
+
+	#[test]
+	fn repetition_limits() {
+		// 20000 by 20000 copies.
+		let mut rep = vec![1];
+		uint(&mut rep, 19998);
+		uint(&mut rep, 19998);
+		rep.extend_from_slice(&[10, 10]);
+		let e = read_error(&rectangle(Some(&rep)));
+		assert!(e.starts_with("repetition of 20000 by 20000 copies exceeds the limit"), "{}", e);
+
+		// A count which overflows when the implied two are added.
+		let mut rep = vec![2];
+		uint(&mut rep, ::std::u64::MAX);
+		rep.push(10);
+		let e = read_error(&rectangle(Some(&rep)));
+		assert!(e.starts_with("repetition count too large"), "{}", e);
+
+		// A list longer than the limit is refused before it is read.
+		let mut rep = vec![4];
+		uint(&mut rep, MAX_REPETITION);
+		let e = read_error(&rectangle(Some(&rep)));
+		assert!(e.contains("exceeds the limit"), "{}", e);
+
+		// Copies beyond the coordinate range.
+		let mut rep = vec![2, 0];
+		uint(&mut rep, 1 << 40);
+		let e = read_error(&rectangle(Some(&rep)));
+		assert!(e.starts_with("repetition reaches beyond the coordinate range"), "{}", e);
+		let mut rep = vec![2];
+		uint(&mut rep, 1000);
+		uint(&mut rep, 1 << 30);
+		let e = read_error(&rectangle(Some(&rep)));
+		assert!(e.starts_with("repetition reaches beyond the coordinate range"), "{}", e);
+	}
+
+	#[test]
+	fn placements() {
+		let mut body = Vec::new();
+		// CELLNAME and TEXTSTRING with the implicit reference number 0.
+		uint(&mut body, 3);
+		string(&mut body, "SUB");
+		uint(&mut body, 5);
+		string(&mut body, "label");
+		// A regular array becomes an array reference.
+		body.extend_from_slice(&[17, 0xc8, 0, 1, 1, 0, 10, 20]);
+		// Other repetitions stay with a single structure reference.
+		body.extend_from_slice(&[17, 0x08, 4, 0, 10]);
+		// A text at (5, 6) naming its string by reference.
+		body.extend_from_slice(&[19, 0x7b, 0, 2, 3, 10, 12]);
+
+		let e = elems(&body);
+		assert_eq!(e.len(), 3);
+		assert_eq!(e[0].get_kind(), ElemKind::Aref);
+		assert_eq!(e[0].get_sname(), "SUB");
+		assert_eq!(e[0].get_colrow(), (3, 2));
+		assert_eq!(xy(&e[0]), vec![(0, 0), (30, 0), (0, 40)]);
+		assert_eq!(e[1].get_kind(), ElemKind::Sref);
+		assert_eq!(offsets(&e[1]), vec![(0, 0), (10, 0)]);
+		assert_eq!(e[2].get_kind(), ElemKind::Text);
+		assert_eq!(e[2].get_string(), "label");
+		assert_eq!((e[2].get_layer(), e[2].get_type()), (2, 3));
+		assert_eq!(xy(&e[2]), vec![(5, 6)]);
+	}
+
+	#[test]
+	fn cblocks() {
+		// Three rectangles compressed with DEFLATE.
+		let inner = [rectangle(None), rectangle(None), rectangle(None)].concat();
+		let compressed = [0x13, 0xa9, 0x66, 0x64, 0xe0, 0x62, 0x3d, 0xc1, 0x38, 0x81, 0x59, 0x04, 0x0b, 0x0b, 0x00];
+		let mut body = vec![34, 0];
+		uint(&mut body, inner.len() as u64);
+		uint(&mut body, compressed.len() as u64);
+		body.extend_from_slice(&compressed);
+		let e = elems(&body);
+		assert_eq!(e.len(), 3);
+		assert_eq!(xy(&e[2]), vec![(100, 200), (110, 200), (110, 205), (100, 205), (100, 200)]);
+
+		// The uncompressed size has to match.
+		body[2] += 1;
+		let e = read_error(&body);
+		assert!(e.starts_with("CBLOCK holds 30 bytes instead of 31"), "{}", e);
+
+		// Errors within the block name the offset within it.
+		let mut body = vec![34, 0, 2, 7];
+		body.extend_from_slice(&[0x01, 0x02, 0x00, 0xfd, 0xff, 20, 0x23]);
+		let e = read_error(&body);
+		assert!(e.contains("of the CBLOCK at byte"), "{}", e);
+	}
+
+	#[test]
+	fn truncated_files() {
+		let data = file(&rectangle(Some(&[1, 1, 0, 10, 20])));
+		for len in MAGIC.len()..data.len() {
+			assert!(read(&data[..len]).is_err(), "prefix of {} bytes accepted", len);
+		}
+	}
+}
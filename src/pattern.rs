@@ -0,0 +1,214 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Patterns matched against cell names.
+//!
+//! A pattern is either a glob such as `fill_*` or `dcap_x[124]`, which has to
+//! match the entire name, or a regular expression enclosed in slashes such as
+//! `/^sram_\d+$/`, which may match anywhere within the name. Regular
+//! expressions use the syntax of the regex crate. Globs are translated into
+//! regular expressions as well, such that matching takes time linear in the
+//! length of the name.
+
+use regex::{self, Regex};
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+	source: String,
+	regex: Regex,
+}
+
+impl Pattern {
+	/// Parse a glob, or a regular expression if enclosed in slashes.
+	pub fn parse(s: &str) -> Result<Pattern, String> {
+		if s.len() >= 2 && s.starts_with('/') && s.ends_with('/') {
+			Pattern::regex(&s[1..s.len()-1])
+		} else {
+			Pattern::glob(s)
+		}
+	}
+
+	pub fn glob(s: &str) -> Result<Pattern, String> {
+		let mut re = String::from("^(?s:");
+		let mut it = s.chars().peekable();
+		while let Some(c) = it.next() {
+			match c {
+				'*' => re.push_str(".*"),
+				'?' => re.push('.'),
+				'[' => try!(glob_class(&mut it, &mut re).map_err(|e| format!("{} in `{}`", e, s))),
+				'\\' => match it.next() {
+					Some(c) => push_literal(&mut re, c),
+					None => return Err(format!("trailing `\\` in `{}`", s)),
+				},
+				c => push_literal(&mut re, c),
+			}
+		}
+		re.push_str(")$");
+		match Regex::new(&re) {
+			Ok(r) => Ok(Pattern { source: s.to_owned(), regex: r }),
+			Err(e) => Err(format!("{} in `{}`", error_message(&e), s)),
+		}
+	}
+
+	pub fn regex(s: &str) -> Result<Pattern, String> {
+		match Regex::new(s) {
+			Ok(r) => Ok(Pattern { source: format!("/{}/", s), regex: r }),
+			Err(e) => Err(format!("{} in `/{}/`", error_message(&e), s)),
+		}
+	}
+
+	pub fn matches(&self, s: &str) -> bool {
+		self.regex.is_match(s)
+	}
+}
+
+impl ::std::fmt::Display for Pattern {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		write!(f, "{}", self.source)
+	}
+}
+
+/// Translate a character class of a glob after its opening `[` into the
+/// regular expression. A leading `!` inverts the class.
+fn glob_class<I: Iterator<Item = char>>(it: &mut ::std::iter::Peekable<I>, re: &mut String) -> Result<(), String> {
+	re.push('[');
+	if it.peek() == Some(&'!') {
+		it.next();
+		re.push('^');
+	}
+	let mut first = true;
+	loop {
+		let c = match it.next() {
+			Some(']') if !first => {
+				re.push(']');
+				return Ok(());
+			},
+			Some('\\') => match it.next() {
+				Some(c) => match class_escape(c) {
+					Some(ranges) => {
+						re.push_str(ranges);
+						first = false;
+						continue;
+					},
+					None => c,
+				},
+				None => return Err(String::from("unterminated character class")),
+			},
+			Some(c) => c,
+			None => return Err(String::from("unterminated character class")),
+		};
+		first = false;
+		push_class_char(re, c);
+		if it.peek() == Some(&'-') {
+			it.next();
+			match it.next() {
+				Some(']') => {
+					re.push_str("\\-]");
+					return Ok(());
+				},
+				Some(d) => {
+					if d < c {
+						return Err(format!("invalid range `{}-{}`", c, d));
+					}
+					re.push('-');
+					push_class_char(re, d);
+				},
+				None => return Err(String::from("unterminated character class")),
+			}
+		}
+	}
+}
+
+/// The ranges denoted by an escape such as `\d` within a class, if any.
+fn class_escape(c: char) -> Option<&'static str> {
+	match c {
+		'd' => Some("0-9"),
+		'w' => Some("a-zA-Z0-9_"),
+		's' => Some(" \\t\\n\\r"),
+		_ => None,
+	}
+}
+
+fn push_literal(re: &mut String, c: char) {
+	let mut buf = [0; 4];
+	re.push_str(&regex::escape(c.encode_utf8(&mut buf)));
+}
+
+/// Append a character to a class, escaping the ones with a meaning there.
+fn push_class_char(re: &mut String, c: char) {
+	if c.is_ascii_punctuation() {
+		re.push('\\');
+	}
+	re.push(c);
+}
+
+/// The gist of a regex error, which the regex crate spreads across several
+/// lines pointing into the expression.
+fn error_message(e: &regex::Error) -> String {
+	let msg = e.to_string();
+	let gist = msg.lines().filter_map(|l| l.trim().strip_prefix("error: ")).last().map(|l| l.to_owned());
+	gist.unwrap_or(msg)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn matches(pattern: &str, name: &str) -> bool {
+		Pattern::parse(pattern).unwrap().matches(name)
+	}
+
+	#[test]
+	fn globs_match_whole_name() {
+		assert!(matches("fill_*", "fill_x4"));
+		assert!(!matches("fill_*", "dfill_x4"));
+		assert!(matches("dcap_x?", "dcap_x8"));
+		assert!(!matches("dcap_x?", "dcap_x16"));
+		assert!(matches("a.b", "a.b"));
+		assert!(!matches("a.b", "aXb"));
+		assert!(matches("a\\*", "a*"));
+		assert!(!matches("a\\*", "ab"));
+	}
+
+	#[test]
+	fn glob_classes() {
+		assert!(matches("dcap_x[124]", "dcap_x2"));
+		assert!(!matches("dcap_x[124]", "dcap_x3"));
+		assert!(matches("x[!0-9]", "xa"));
+		assert!(!matches("x[!0-9]", "x5"));
+		assert!(matches("x[]]", "x]"));
+		assert!(matches("x[a-]", "x-"));
+		assert!(matches("x[\\d]", "x7"));
+		assert!(matches("x[^]", "x^"));
+		assert!(Pattern::parse("x[ab").is_err());
+		assert!(Pattern::parse("x[z-a]").is_err());
+		assert!(Pattern::parse("x\\").is_err());
+	}
+
+	#[test]
+	fn regexes_match_anywhere() {
+		assert!(matches("/sram/", "u_sram_0"));
+		assert!(matches("/^sram_\\d+$/", "sram_12"));
+		assert!(!matches("/^sram_\\d+$/", "sram_12a"));
+		assert!(matches("/^(?:inv|buf)_x[0-9]{1,2}$/", "buf_x16"));
+		assert!(!matches("/^(?:inv|buf)_x[0-9]{1,2}$/", "nand_x1"));
+		assert_eq!(Pattern::parse("/a|b/").unwrap().to_string(), "/a|b/");
+	}
+
+	#[test]
+	fn invalid_regexes() {
+		assert!(Pattern::parse("/a{3,1}/").is_err());
+		assert!(Pattern::parse("/(a/").is_err());
+		assert!(Pattern::parse("/a)/").is_err());
+		assert!(Pattern::parse("/*a/").is_err());
+		let e = Pattern::parse("/a{3,1}/").unwrap_err();
+		assert!(!e.contains('\n'), "{}", e);
+		assert!(e.ends_with("in `/a{3,1}/`"), "{}", e);
+	}
+
+	#[test]
+	fn no_exponential_backtracking() {
+		let name: String = ::std::iter::repeat('a').take(64).collect();
+		assert!(!matches("/^(a|a)*(a|a)*b$/", &name));
+		assert!(!matches("*a*a*a*a*a*a*a*a*b", &name));
+	}
+}
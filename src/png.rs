@@ -0,0 +1,79 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! A minimal PNG encoder for images rendered without Cairo, which are written
+//! as uncompressed grayscale PNGs.
+
+use std::io::{self, Write};
+
+/// Write a grayscale image with a bit depth of 1 or 8, given one byte per
+/// pixel. At a depth of 1 bit, values of 128 and above are white. The image
+/// data is stored uncompressed.
+pub fn encode_gray<W: Write>(w: &mut W, width: usize, height: usize, depth: u8, pixels: &[u8]) -> io::Result<()> {
+	let mut raw = Vec::new();
+	for row in pixels.chunks(width).take(height) {
+		raw.push(0);
+		if depth == 1 {
+			for byte in row.chunks(8) {
+				let mut packed = 0u8;
+				for (i, &v) in byte.iter().enumerate() {
+					if v >= 128 {
+						packed |= 0x80 >> i;
+					}
+				}
+				raw.push(packed);
+			}
+		} else {
+			raw.extend_from_slice(row);
+		}
+	}
+
+	// Wrap the data in a zlib stream of stored blocks.
+	let mut z = vec![0x78, 0x01];
+	let mut blocks = raw.chunks(65535).peekable();
+	if blocks.peek().is_none() {
+		z.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+	}
+	while let Some(block) = blocks.next() {
+		let len = block.len() as u16;
+		z.push(if blocks.peek().is_none() { 1 } else { 0 });
+		z.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+		z.extend_from_slice(block);
+	}
+	z.extend_from_slice(&to_be32(adler32(&raw)));
+
+	let mut header = Vec::new();
+	header.extend_from_slice(&to_be32(width as u32));
+	header.extend_from_slice(&to_be32(height as u32));
+	header.extend_from_slice(&[depth, 0, 0, 0, 0]);
+	try!(w.write_all(b"\x89PNG\r\n\x1a\n"));
+	try!(write_chunk(w, b"IHDR", &header));
+	try!(write_chunk(w, b"IDAT", &z));
+	write_chunk(w, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8], data: &[u8]) -> io::Result<()> {
+	try!(w.write_all(&to_be32(data.len() as u32)));
+	try!(w.write_all(kind));
+	try!(w.write_all(data));
+	let mut crc = !0u32;
+	for &b in kind.iter().chain(data.iter()) {
+		crc ^= b as u32;
+		for _ in 0..8 {
+			crc = if crc & 1 != 0 { 0xedb88320 ^ (crc >> 1) } else { crc >> 1 };
+		}
+	}
+	w.write_all(&to_be32(!crc))
+}
+
+fn adler32(data: &[u8]) -> u32 {
+	let (mut a, mut b) = (1u32, 0u32);
+	for &v in data {
+		a = (a + v as u32) % 65521;
+		b = (b + a) % 65521;
+	}
+	b << 16 | a
+}
+
+fn to_be32(v: u32) -> [u8; 4] {
+	[(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+}
@@ -0,0 +1,168 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Deep-zoom tile pyramid export.
+//!
+//! The plot is rendered at its full resolution as well as at successively
+//! halved resolutions, each level cut into square tiles stored as
+//! `<level>/<x>/<y>.png`. Level 0 fits into a single tile. A static HTML
+//! viewer is written alongside the tiles, such that the result can be browsed
+//! straight from disk.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use {Context, Overlay, Tile, RenderOptions, plot_transform, render_tiles};
+
+/// The width and height of a tile, in pixels.
+const TILE_SIZE: i32 = 256;
+
+pub fn write_pyramid(ctx: &Context, overlays: &[Overlay], output_dir: &str) -> io::Result<()> {
+	let (tx, size) = plot_transform(ctx, overlays);
+	let largest = size.0.max(size.1) as f64 / TILE_SIZE as f64;
+	let max_zoom = if largest > 1.0 { largest.log2().ceil() as i32 } else { 0 };
+
+	// Each level halves the resolution of the one above it.
+	let mut tiles = Vec::new();
+	for z in 0..max_zoom+1 {
+		let f = 0.5f64.powi(max_zoom - z);
+		let mut ltx = tx;
+		ltx.scale(f, f);
+		let lw = (size.0 as f64 * f).ceil() as i32;
+		let lh = (size.1 as f64 * f).ceil() as i32;
+		for x in 0..(lw + TILE_SIZE - 1) / TILE_SIZE {
+			try!(fs::create_dir_all(format!("{}/{}/{}", output_dir, z, x)));
+			for y in 0..(lh + TILE_SIZE - 1) / TILE_SIZE {
+				let mut ttx = ltx;
+				ttx.trans((-x * TILE_SIZE) as f64, (-y * TILE_SIZE) as f64);
+				tiles.push(Tile {
+					tx: ttx,
+					size: (TILE_SIZE.min(lw - x * TILE_SIZE), TILE_SIZE.min(lh - y * TILE_SIZE)),
+					path: format!("{}/{}/{}/{}.png", output_dir, z, x, y),
+				});
+			}
+		}
+	}
+	try!(render_tiles(&RenderOptions::new(ctx), overlays, ctx.jobs, &tiles));
+
+	// Write the viewer.
+	let config = format!(
+		"{{\"tileSize\":{},\"maxZoom\":{},\"width\":{},\"height\":{}}}",
+		TILE_SIZE, max_zoom, size.0, size.1
+	);
+	let background = match ctx.bg_color {
+		Some(c) => c.to_hex(),
+		None => String::from("#ffffff"),
+	};
+	let title = match overlays.first() {
+		Some(o) => o.strukt.name.replace('&', "&amp;").replace('<', "&lt;"),
+		None => String::new(),
+	};
+	let html = VIEWER
+		.replace("{{TITLE}}", &title)
+		.replace("{{BACKGROUND}}", &background)
+		.replace("{{CONFIG}}", &config);
+	let mut file = try!(File::create(format!("{}/index.html", output_dir)));
+	try!(file.write_all(html.as_bytes()));
+	Ok(())
+}
+
+const VIEWER: &'static str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{TITLE}}</title>
+<style>
+html, body { margin: 0; height: 100%; overflow: hidden; background: {{BACKGROUND}}; }
+#view { position: absolute; left: 0; top: 0; right: 0; bottom: 0; cursor: grab; }
+#view img { position: absolute; user-select: none; -webkit-user-drag: none; }
+</style>
+</head>
+<body>
+<div id="view"></div>
+<script>
+var cfg = {{CONFIG}};
+var view = document.getElementById("view");
+var tiles = {};
+
+// The size of a full-resolution pixel on screen, and the screen position of
+// the plot's top left corner.
+var scale = 1, ox = 0, oy = 0;
+
+function fit() {
+	scale = Math.min(view.clientWidth / cfg.width, view.clientHeight / cfg.height);
+	ox = (view.clientWidth - cfg.width * scale) / 2;
+	oy = (view.clientHeight - cfg.height * scale) / 2;
+}
+
+function draw() {
+	// Pick the coarsest level that still has enough detail.
+	var z = Math.max(0, Math.min(cfg.maxZoom, Math.ceil(cfg.maxZoom + Math.log2(scale))));
+	var f = Math.pow(2, cfg.maxZoom - z);
+	var s = scale * f;
+	var ts = cfg.tileSize;
+	var lw = Math.ceil(cfg.width / f), lh = Math.ceil(cfg.height / f);
+	var x0 = Math.max(0, Math.floor(-ox / s / ts));
+	var y0 = Math.max(0, Math.floor(-oy / s / ts));
+	var x1 = Math.min(Math.ceil(lw / ts), Math.ceil((view.clientWidth - ox) / s / ts));
+	var y1 = Math.min(Math.ceil(lh / ts), Math.ceil((view.clientHeight - oy) / s / ts));
+
+	var keep = {};
+	for (var x = x0; x < x1; x++) {
+		for (var y = y0; y < y1; y++) {
+			var key = z + "/" + x + "/" + y;
+			var img = tiles[key];
+			if (!img) {
+				img = document.createElement("img");
+				img.src = key + ".png";
+				view.appendChild(img);
+				tiles[key] = img;
+			}
+			var l = Math.floor(ox + x * ts * s), t = Math.floor(oy + y * ts * s);
+			img.style.left = l + "px";
+			img.style.top = t + "px";
+			img.style.width = (Math.floor(ox + Math.min(lw, (x + 1) * ts) * s) - l) + "px";
+			img.style.height = (Math.floor(oy + Math.min(lh, (y + 1) * ts) * s) - t) + "px";
+			keep[key] = true;
+		}
+	}
+	for (var k in tiles) {
+		if (!keep[k]) {
+			view.removeChild(tiles[k]);
+			delete tiles[k];
+		}
+	}
+}
+
+view.addEventListener("wheel", function(e) {
+	e.preventDefault();
+	var k = Math.pow(2, -e.deltaY / 300);
+	scale *= k;
+	ox = e.clientX - (e.clientX - ox) * k;
+	oy = e.clientY - (e.clientY - oy) * k;
+	draw();
+}, { passive: false });
+
+var drag = null;
+view.addEventListener("mousedown", function(e) {
+	drag = { x: e.clientX - ox, y: e.clientY - oy };
+	view.style.cursor = "grabbing";
+});
+window.addEventListener("mousemove", function(e) {
+	if (drag) {
+		ox = e.clientX - drag.x;
+		oy = e.clientY - drag.y;
+		draw();
+	}
+});
+window.addEventListener("mouseup", function() {
+	drag = null;
+	view.style.cursor = "";
+});
+view.addEventListener("dblclick", function() { fit(); draw(); });
+window.addEventListener("resize", draw);
+
+fit();
+draw();
+</script>
+</body>
+</html>
+"##;
@@ -0,0 +1,121 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! A scanline rasterizer for output formats which need to know exactly which
+//! pixels are covered, independent of Cairo. Pixels are sampled at their
+//! centers.
+
+use {Transform, Point};
+
+/// A bit mask of covered pixels.
+pub struct Mask {
+	pub width: usize,
+	pub height: usize,
+	bits: Vec<u64>,
+}
+
+impl Mask {
+	pub fn new(width: usize, height: usize) -> Mask {
+		Mask {
+			width: width,
+			height: height,
+			bits: vec![0; (width * height + 63) / 64],
+		}
+	}
+
+	pub fn get(&self, x: usize, y: usize) -> bool {
+		let i = y * self.width + x;
+		self.bits[i / 64] & 1 << (i % 64) != 0
+	}
+
+	/// Mark the pixel containing a point, if it lies within the mask.
+	pub fn set(&mut self, x: f64, y: f64) {
+		if x >= 0.0 && y >= 0.0 && x < self.width as f64 && y < self.height as f64 {
+			self.mark(x as usize, y as usize);
+		}
+	}
+
+	fn mark(&mut self, x: usize, y: usize) {
+		let i = y * self.width + x;
+		self.bits[i / 64] |= 1 << (i % 64);
+	}
+}
+
+/// Mark the pixels whose centers lie inside a set of rings, according to the
+/// even-odd rule. Returns whether any pixel was covered.
+pub fn fill_rings<'a, I: Iterator<Item=&'a Vec<Point>>>(rings: I, tx: Transform, mask: &mut Mask) -> bool {
+	let mut edges = Vec::new();
+	let mut ymin = mask.height as f64;
+	let mut ymax = 0.0f64;
+	for ring in rings {
+		let points: Vec<Point> = ring.iter().map(|p| tx * *p).collect();
+		for pair in points.windows(2) {
+			ymin = ymin.min(pair[0].y);
+			ymax = ymax.max(pair[0].y);
+			edges.push((pair[0], pair[1]));
+		}
+	}
+
+	let mut covered = false;
+	let mut xs = Vec::new();
+	let y0 = (ymin - 0.5).ceil().max(0.0) as usize;
+	let y1 = ((ymax - 0.5).floor() + 1.0).max(0.0).min(mask.height as f64) as usize;
+	for y in y0..y1 {
+		let yc = y as f64 + 0.5;
+		xs.clear();
+		for &(a, b) in &edges {
+			if (a.y <= yc) != (b.y <= yc) {
+				let x = a.x + (yc - a.y) / (b.y - a.y) * (b.x - a.x);
+				if x.is_finite() {
+					xs.push(x);
+				}
+			}
+		}
+		xs.sort_by(f64::total_cmp);
+		for span in xs.chunks(2) {
+			if span.len() < 2 {
+				break;
+			}
+			let x0 = (span[0] - 0.5).ceil().max(0.0) as usize;
+			let x1 = (span[1] - 0.5).ceil().max(0.0).min(mask.width as f64) as usize;
+			for x in x0..x1 {
+				mask.mark(x, y);
+				covered = true;
+			}
+		}
+	}
+	covered
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fill_square() {
+		let ring = vec![
+			Point { x: 1.0, y: 1.0 }, Point { x: 3.0, y: 1.0 },
+			Point { x: 3.0, y: 3.0 }, Point { x: 1.0, y: 3.0 }, Point { x: 1.0, y: 1.0 },
+		];
+		let mut mask = Mask::new(4, 4);
+		assert!(fill_rings(Some(&ring).into_iter(), Transform::identity(), &mut mask));
+		for y in 0..4 {
+			for x in 0..4 {
+				assert_eq!(mask.get(x, y), x >= 1 && x < 3 && y >= 1 && y < 3, "pixel {} {}", x, y);
+			}
+		}
+	}
+
+	#[test]
+	fn degenerate_transform() {
+		// A zero-size window scales the layout infinitely, which turns the
+		// edge crossings into NaN.
+		let ring = vec![
+			Point { x: 0.0, y: 0.0 }, Point { x: 1.0, y: 0.0 },
+			Point { x: 1.0, y: 1.0 }, Point { x: 0.0, y: 1.0 }, Point { x: 0.0, y: 0.0 },
+		];
+		let mut tx = Transform::identity();
+		tx.scale(f64::INFINITY, 4.0);
+		let mut mask = Mask::new(4, 4);
+		assert!(!fill_rings(Some(&ring).into_iter(), tx, &mut mask));
+	}
+}
@@ -0,0 +1,101 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! A static R-tree used to cull geometry outside the visible window.
+//!
+//! The tree is bulk-loaded once with the Sort-Tile-Recursive algorithm and
+//! never modified afterwards, which keeps the nodes densely packed.
+
+use std::cmp::Ordering;
+use Rect;
+
+/// The maximum number of entries in a node.
+const FANOUT: usize = 16;
+
+pub struct RTree<T> {
+	items: Vec<(Rect, T)>,
+	/// The levels of the tree, starting with the leaves. Each node covers a
+	/// range of entries in the level below, or of items in case of a leaf.
+	levels: Vec<Vec<Node>>,
+}
+
+struct Node {
+	rect: Rect,
+	first: usize,
+	last: usize,
+}
+
+impl<T> RTree<T> {
+	pub fn new(mut items: Vec<(Rect, T)>) -> RTree<T> {
+		let mut levels = Vec::new();
+		if !items.is_empty() {
+			sort_tiles(&mut items, |i| &i.0);
+			let mut nodes = pack(&items, |i| &i.0);
+			while nodes.len() > 1 {
+				sort_tiles(&mut nodes, |n| &n.rect);
+				let parents = pack(&nodes, |n| &n.rect);
+				levels.push(nodes);
+				nodes = parents;
+			}
+			levels.push(nodes);
+		}
+		RTree {
+			items: items,
+			levels: levels,
+		}
+	}
+
+	/// Call a function for each item whose rectangle intersects the window.
+	pub fn query<F: FnMut(&Rect, &T)>(&self, window: &Rect, mut f: F) {
+		if let Some(top) = self.levels.len().checked_sub(1) {
+			for i in 0..self.levels[top].len() {
+				self.visit(top, i, window, &mut f);
+			}
+		}
+	}
+
+	fn visit<F: FnMut(&Rect, &T)>(&self, level: usize, index: usize, window: &Rect, f: &mut F) {
+		let node = &self.levels[level][index];
+		if !node.rect.intersects(window) {
+			return;
+		}
+		for i in node.first..node.last {
+			if level == 0 {
+				let (ref rect, ref item) = self.items[i];
+				if rect.intersects(window) {
+					f(rect, item);
+				}
+			} else {
+				self.visit(level - 1, i, window, f);
+			}
+		}
+	}
+}
+
+/// Order entries such that consecutive runs of `FANOUT` entries are close to
+/// each other: sort by x into vertical slices, then sort each slice by y.
+fn sort_tiles<E, F: Fn(&E) -> &Rect>(entries: &mut [E], rect: F) {
+	let cx = |e: &E| { let r = rect(e); r.min.x + r.max.x };
+	let cy = |e: &E| { let r = rect(e); r.min.y + r.max.y };
+	entries.sort_by(|a,b| cx(a).partial_cmp(&cx(b)).unwrap_or(Ordering::Equal));
+	let nodes = (entries.len() + FANOUT - 1) / FANOUT;
+	let slices = (nodes as f64).sqrt().ceil() as usize;
+	let slice_len = if slices > 0 { slices * FANOUT } else { FANOUT };
+	for slice in entries.chunks_mut(slice_len) {
+		slice.sort_by(|a,b| cy(a).partial_cmp(&cy(b)).unwrap_or(Ordering::Equal));
+	}
+}
+
+/// Group runs of `FANOUT` entries into nodes.
+fn pack<E, F: Fn(&E) -> &Rect>(entries: &[E], rect: F) -> Vec<Node> {
+	entries.chunks(FANOUT).enumerate().map(|(i, chunk)| {
+		let mut r = *rect(&chunk[0]);
+		for e in &chunk[1..] {
+			r = r.union(rect(e));
+		}
+		Node {
+			rect: r,
+			first: i * FANOUT,
+			last: i * FANOUT + chunk.len(),
+		}
+	}).collect()
+}
@@ -0,0 +1,196 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! SVG output.
+//!
+//! Every prepared struct is written once per layer into the `<defs>` section
+//! and then placed with `<use>` wherever it is instantiated, such that cells
+//! which are instantiated many times do not inflate the output. Geometry is
+//! written in micrometers; the plot transformation is applied at the top.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use {Context, Overlay, Struct, Boundary, Point, Rect, Transform, ZERO_POINT, UNEXPANDED_FONT_SIZE, plot_transform, find_unexpanded, label_position};
+use html::escape;
+
+pub fn write_svg(ctx: &Context, overlays: &[Overlay], output_name: &str) -> io::Result<()> {
+	let file = try!(File::create(output_name));
+	write_svg_to(&mut BufWriter::new(file), ctx, overlays)
+}
+
+pub fn write_svg_to<W: Write>(w: &mut W, ctx: &Context, overlays: &[Overlay]) -> io::Result<()> {
+	let (tx, size) = plot_transform(ctx, overlays);
+
+	try!(writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+	try!(writeln!(w, "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" fill-rule=\"evenodd\">", size.0, size.1));
+	if let Some(bgc) = ctx.bg_color {
+		try!(writeln!(w, "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>", bgc.to_hex()));
+	}
+
+	// Define each struct once.
+	let mut ids = HashMap::<*const Struct, usize>::new();
+	try!(writeln!(w, "<defs>"));
+	for o in overlays {
+		try!(write_defs(w, o.strukt, &mut ids));
+	}
+	try!(writeln!(w, "</defs>"));
+
+	// Fill and stroke the layers of each overlay.
+	for o in overlays {
+		let id = ids[&(o.strukt as *const Struct)];
+		let mut otx = o.transform(tx);
+		otx.va = otx.va * 1e-6;
+		otx.vb = otx.vb * 1e-6;
+		let m = matrix(otx);
+
+		for layer in &o.strukt.layers {
+			if let Some(fs) = layer.style.get_fill_style() {
+				try!(writeln!(w,
+					"<g fill=\"{}\" stroke=\"none\" opacity=\"{}\" transform=\"{}\"><use xlink:href=\"#c{}_l{}\"/></g>",
+					fs.color.to_hex(), fs.alpha, m, id, layer.key
+				));
+			}
+		}
+		for layer in &o.strukt.layers {
+			if let Some(ss) = layer.style.get_stroke_style() {
+				let dashes = match ss.dashes {
+					Some(ref d) => format!(" stroke-dasharray=\"{}\"", d.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")),
+					None => String::new(),
+				};
+				try!(writeln!(w,
+					"<g fill=\"none\" stroke=\"{}\" stroke-opacity=\"{}\" stroke-width=\"{}\"{} transform=\"{}\"><use xlink:href=\"#c{}_l{}\"/></g>",
+					ss.color.to_hex(), ss.alpha, ss.width, dashes, m, id, layer.key
+				));
+			}
+		}
+	}
+
+	// Draw the instances that are not expanded as labeled boxes.
+	let style = ctx.get_unexpanded_style();
+	let view = Rect {
+		min: ZERO_POINT,
+		max: Point { x: size.0 as f64, y: size.1 as f64 },
+	};
+	let fill = match style.get_fill_style() {
+		Some(fs) => format!("fill=\"{}\" fill-opacity=\"{}\"", fs.color.to_hex(), fs.alpha),
+		None => String::from("fill=\"none\""),
+	};
+	let stroke = style.get_stroke_style();
+	for o in overlays {
+		let otx = o.transform(tx);
+		let mut found = Vec::new();
+		find_unexpanded(o.strukt, otx, &view, &mut found);
+		for (s, stx) in found {
+			let r = &s.extents.rect;
+			let corners = [r.min, r.min, Point { x: r.max.x, y: r.min.y }, r.max, Point { x: r.min.x, y: r.max.y }];
+			let mut d = String::new();
+			for (i, p) in corners.iter().skip(1).enumerate() {
+				let p = stx * *p;
+				d.push_str(&format!("{}{} {}", if i == 0 { "M" } else { "L" }, num(p.x), num(p.y)));
+			}
+			d.push('Z');
+			match stroke {
+				Some(ref ss) => {
+					try!(writeln!(w,
+						"<path d=\"{}\" {} stroke=\"{}\" stroke-opacity=\"{}\" stroke-width=\"{}\"/>",
+						d, fill, ss.color.to_hex(), ss.alpha, ss.width
+					));
+					if let Some(p) = label_position(&r.transformed(stx), &s.name) {
+						try!(writeln!(w,
+							"<text x=\"{}\" y=\"{}\" font-size=\"{}\" font-family=\"sans-serif\" fill=\"{}\" fill-opacity=\"{}\">{}</text>",
+							num(p.x), num(p.y), UNEXPANDED_FONT_SIZE, ss.color.to_hex(), ss.alpha, escape(&s.name)
+						));
+					}
+				},
+				None => try!(writeln!(w, "<path d=\"{}\" {} stroke=\"none\"/>", d, fill)),
+			}
+		}
+	}
+
+	try!(writeln!(w, "</svg>"));
+	Ok(())
+}
+
+/// Write the definitions for a struct and all structs it instantiates, unless
+/// already written. Returns the number identifying the struct's definitions.
+fn write_defs<W: Write>(w: &mut W, strukt: &Struct, ids: &mut HashMap<*const Struct, usize>) -> io::Result<usize> {
+	let key = strukt as *const Struct;
+	if let Some(&id) = ids.get(&key) {
+		return Ok(id);
+	}
+
+	let mut children = Vec::with_capacity(strukt.instances.len());
+	for inst in &strukt.instances {
+		children.push(try!(write_defs(w, &inst.strukt, ids)));
+	}
+	let id = ids.len();
+	ids.insert(key, id);
+
+	let mut by_layer = HashMap::<u16, Vec<&Boundary>>::new();
+	for b in &strukt.boundaries {
+		by_layer.entry(b.layer.key).or_insert_with(Vec::new).push(b);
+	}
+
+	try!(writeln!(w, "<!-- {} -->", comment_text(&strukt.name)));
+	for layer in &strukt.layers {
+		try!(writeln!(w, "<g id=\"c{}_l{}\">", id, layer.key));
+		if let Some(boundaries) = by_layer.get(&layer.key) {
+			for b in boundaries {
+				let mut d = String::new();
+				path_data(&mut d, &b.points);
+				for hole in &b.holes {
+					path_data(&mut d, hole);
+				}
+				try!(writeln!(w, "<path d=\"{}\" vector-effect=\"non-scaling-stroke\"/>", d));
+			}
+		}
+		for (inst, &child) in strukt.instances.iter().zip(children.iter()) {
+			if inst.strukt.has_layer(layer.key) {
+				let mut itx = inst.tx;
+				itx.vt = itx.vt * 1e6;
+				try!(writeln!(w, "<use xlink:href=\"#c{}_l{}\" transform=\"{}\"/>", child, layer.key, matrix(itx)));
+			}
+		}
+		try!(writeln!(w, "</g>"));
+	}
+	Ok(id)
+}
+
+/// Break up runs of hyphens in text placed into a comment, which must not
+/// contain `--`.
+fn comment_text(s: &str) -> String {
+	let mut text = String::with_capacity(s.len());
+	for c in s.chars() {
+		if c == '-' && text.ends_with('-') {
+			text.push(' ');
+		}
+		text.push(c);
+	}
+	text
+}
+
+/// Append a closed ring of points, where the last point repeats the first, to
+/// path data in micrometers.
+fn path_data(d: &mut String, points: &[Point]) {
+	for (i, p) in points.iter().skip(1).enumerate() {
+		d.push_str(if i == 0 { "M" } else { "L" });
+		d.push_str(&num(p.x * 1e6));
+		d.push(' ');
+		d.push_str(&num(p.y * 1e6));
+	}
+	d.push('Z');
+}
+
+fn matrix(tx: Transform) -> String {
+	format!("matrix({} {} {} {} {} {})",
+		tx.va.x, tx.va.y,
+		tx.vb.x, tx.vb.y,
+		num(tx.vt.x), num(tx.vt.y))
+}
+
+/// Format a number with limited precision and without trailing zeros.
+fn num(v: f64) -> String {
+	let s = format!("{:.6}", v);
+	let s = s.trim_right_matches('0').trim_right_matches('.');
+	if s == "-0" { String::from("0") } else { s.to_owned() }
+}
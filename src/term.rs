@@ -0,0 +1,162 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! Terminal preview output.
+//!
+//! The plot is rasterized into a grid of character cells, each showing two
+//! pixels stacked on top of each other by means of the Unicode upper half
+//! block and 24-bit ANSI foreground and background colors. The layers are
+//! drawn in their fill color only. The result is written to stdout.
+
+use std::env;
+use std::io::{self, BufWriter, Write};
+use {Context, Overlay, Struct, Extents, Transform, Rect, Point, ColorRgb, ZERO_POINT};
+use raster::{Mask, fill_rings};
+
+pub fn write_term(ctx: &Context, overlays: &[Overlay]) -> io::Result<()> {
+	// Determine the area to be plotted.
+	let mut extents = Extents::new();
+	match ctx.window {
+		Some(w) => {
+			extents.add_point(&w.min);
+			extents.add_point(&w.max);
+		},
+		None => for o in overlays {
+			extents.add_extents(&o.strukt.extents, o.offset);
+		},
+	}
+	if extents.empty {
+		return Ok(());
+	}
+	let r = extents.rect;
+	let phys_size = r.max - r.min;
+
+	// Fit the plot to the width of the terminal, and to its height if known.
+	// Each character covers one pixel horizontally and two vertically.
+	let (columns, lines) = terminal_size();
+	let columns = columns.unwrap_or(80);
+	let mut f = columns as f64 / phys_size.x;
+	if let Some(lines) = lines {
+		f = f.min((2 * lines.max(2) - 2) as f64 / phys_size.y);
+	}
+	let width = ((phys_size.x * f).ceil() as usize).max(1).min(columns);
+	let height = ((phys_size.y * f).ceil() as usize).max(1);
+	let height = height + height % 2;
+	let mut tx = Transform::identity();
+	tx.trans(-r.min.x, -r.min.y);
+	tx.scale(f, -f);
+	tx.trans(0.0, height as f64);
+
+	// Composite the layers in their fill colors.
+	let view = Rect {
+		min: ZERO_POINT,
+		max: Point { x: width as f64, y: height as f64 },
+	};
+	let base = ctx.bg_color.unwrap_or(ColorRgb(0.0, 0.0, 0.0));
+	let mut pixels: Vec<Option<ColorRgb>> = vec![ctx.bg_color; width * height];
+	for o in overlays {
+		let otx = o.transform(tx);
+		for layer in &o.strukt.layers {
+			let fs = match layer.style.get_fill_style() {
+				Some(fs) => fs,
+				None => continue,
+			};
+			let mut mask = Mask::new(width, height);
+			fill_layer(o.strukt, layer.key, otx, &view, &mut mask);
+			for (i, px) in pixels.iter_mut().enumerate() {
+				if mask.get(i % width, i / width) {
+					let c = px.unwrap_or(base);
+					*px = Some(ColorRgb(
+						c.r + (fs.color.r - c.r) * fs.alpha,
+						c.g + (fs.color.g - c.g) * fs.alpha,
+						c.b + (fs.color.b - c.b) * fs.alpha,
+					));
+				}
+			}
+		}
+	}
+
+	// Emit two rows of pixels per line of text.
+	let stdout = io::stdout();
+	let mut w = BufWriter::new(stdout.lock());
+	for y in 0..height/2 {
+		for x in 0..width {
+			let top = pixels[2*y * width + x];
+			let bottom = pixels[(2*y+1) * width + x];
+			match (top, bottom) {
+				(Some(t), Some(b)) => try!(write!(w, "\x1b[38;2;{}m\x1b[48;2;{}m\u{2580}", ansi(t), ansi(b))),
+				(Some(t), None) => try!(write!(w, "\x1b[49m\x1b[38;2;{}m\u{2580}", ansi(t))),
+				(None, Some(b)) => try!(write!(w, "\x1b[49m\x1b[38;2;{}m\u{2584}", ansi(b))),
+				(None, None) => try!(write!(w, "\x1b[0m ")),
+			}
+		}
+		try!(writeln!(w, "\x1b[0m"));
+	}
+	Ok(())
+}
+
+/// Determine the number of columns and lines of the terminal. The terminal
+/// is asked first, through whichever of stdout, stderr, and stdin is
+/// connected to it, and the `COLUMNS` and `LINES` variables are used if none
+/// is.
+fn terminal_size() -> (Option<usize>, Option<usize>) {
+	match tty_size() {
+		Some((columns, lines)) => (Some(columns), Some(lines)),
+		None => (env_size("COLUMNS"), env_size("LINES")),
+	}
+}
+
+/// Read a terminal dimension from the environment.
+fn env_size(var: &str) -> Option<usize> {
+	env::var(var).ok().and_then(|v| v.trim().parse().ok()).and_then(|n| if n > 0 { Some(n) } else { None })
+}
+
+#[cfg(unix)]
+fn tty_size() -> Option<(usize, usize)> {
+	for &fd in &[libc::STDOUT_FILENO, libc::STDERR_FILENO, libc::STDIN_FILENO] {
+		let mut ws = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+		if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut ws as *mut libc::winsize) } == 0 && ws.ws_col > 0 && ws.ws_row > 0 {
+			return Some((ws.ws_col as usize, ws.ws_row as usize));
+		}
+	}
+	None
+}
+
+#[cfg(not(unix))]
+fn tty_size() -> Option<(usize, usize)> {
+	None
+}
+
+fn ansi(c: ColorRgb) -> String {
+	let q = |v: f64| (v.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+	format!("{};{};{}", q(c.r), q(c.g), q(c.b))
+}
+
+/// Mark the pixels covered by the geometry on a layer of a struct and all its
+/// instances. Shapes and instances smaller than a pixel mark the pixel they
+/// fall into, such that they remain visible.
+fn fill_layer(strukt: &Struct, layer_key: u16, tx: Transform, view: &Rect, mask: &mut Mask) {
+	let window = view.transformed(tx.inverse());
+
+	if let Some(index) = strukt.shapes.get(&layer_key) {
+		index.query(&window, |bbox, &i| {
+			let b = &strukt.boundaries[i];
+			let r = bbox.transformed(tx);
+			if !fill_rings(Some(&b.points).into_iter().chain(b.holes.iter()), tx, mask) {
+				mask.set(0.5 * (r.min.x + r.max.x), 0.5 * (r.min.y + r.max.y));
+			}
+		});
+	}
+
+	strukt.placements.query(&window, |bbox, &i| {
+		let inst = &strukt.instances[i];
+		if !inst.strukt.has_layer(layer_key) {
+			return;
+		}
+		let r = bbox.transformed(tx);
+		if r.max.x - r.min.x < 1.0 && r.max.y - r.min.y < 1.0 {
+			mask.set(0.5 * (r.min.x + r.max.x), 0.5 * (r.min.y + r.max.y));
+			return;
+		}
+		fill_layer(&inst.strukt, layer_key, tx * inst.tx, view, mask);
+	});
+}
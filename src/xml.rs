@@ -0,0 +1,224 @@
+// Copyright (c) 2016 Fabian Schuiki
+
+//! A small XML reader that builds a tree of elements. Attributes, comments,
+//! processing instructions, and the document type are skipped, since none of
+//! the files read by gdsplot rely on them.
+
+pub struct Element {
+	pub name: String,
+	pub children: Vec<Element>,
+	/// The text directly within the element, with entities resolved.
+	pub text: String,
+}
+
+impl Element {
+	/// Find the first child element with the given name.
+	pub fn child(&self, name: &str) -> Option<&Element> {
+		self.children.iter().find(|c| c.name == name)
+	}
+
+	/// The trimmed text of the first child element with the given name.
+	pub fn child_text(&self, name: &str) -> Option<&str> {
+		self.child(name).map(|c| c.text.trim())
+	}
+}
+
+/// Parse a document and return its root element.
+pub fn parse(s: &str) -> Result<Element, String> {
+	let mut p = Parser { s: s, pos: 0 };
+	try!(p.skip_misc());
+	let root = try!(p.element());
+	try!(p.skip_misc());
+	if p.pos < p.s.len() {
+		return Err(p.error("content after the root element"));
+	}
+	Ok(root)
+}
+
+struct Parser<'a> {
+	s: &'a str,
+	pos: usize,
+}
+
+impl<'a> Parser<'a> {
+	fn error(&self, msg: &str) -> String {
+		let line = self.s[..self.pos].matches('\n').count() + 1;
+		format!("{} on line {}", msg, line)
+	}
+
+	fn rest(&self) -> &'a str {
+		&self.s[self.pos..]
+	}
+
+	/// Skip past the next occurrence of a string.
+	fn skip_past(&mut self, end: &str) -> Result<(), String> {
+		match self.rest().find(end) {
+			Some(i) => {
+				self.pos += i + end.len();
+				Ok(())
+			},
+			None => Err(self.error(&format!("missing `{}`", end))),
+		}
+	}
+
+	/// Skip whitespace, comments, processing instructions, and declarations.
+	fn skip_misc(&mut self) -> Result<(), String> {
+		loop {
+			let trimmed = self.rest().trim_left();
+			self.pos = self.s.len() - trimmed.len();
+			if trimmed.starts_with("<?") {
+				try!(self.skip_past("?>"));
+			} else if trimmed.starts_with("<!--") {
+				try!(self.skip_past("-->"));
+			} else if trimmed.starts_with("<!") {
+				try!(self.skip_past(">"));
+			} else {
+				return Ok(());
+			}
+		}
+	}
+
+	fn element(&mut self) -> Result<Element, String> {
+		if !self.rest().starts_with('<') {
+			return Err(self.error("expected an element"));
+		}
+		self.pos += 1;
+		let name_len = self.rest().find(|c: char| c.is_whitespace() || c == '>' || c == '/').unwrap_or(self.rest().len());
+		let name = self.rest()[..name_len].to_owned();
+		self.pos += name_len;
+
+		// Skip the attributes, minding quoted values.
+		let mut quote = None;
+		let empty = loop {
+			let c = match self.rest().chars().next() {
+				Some(c) => c,
+				None => return Err(self.error(&format!("unterminated tag `{}`", name))),
+			};
+			self.pos += c.len_utf8();
+			match (quote, c) {
+				(Some(q), c) if c == q => quote = None,
+				(Some(_), _) => (),
+				(None, '"') | (None, '\'') => quote = Some(c),
+				(None, '>') => break false,
+				(None, '/') if self.rest().starts_with('>') => {
+					self.pos += 1;
+					break true;
+				},
+				_ => (),
+			}
+		};
+		let mut elem = Element {
+			name: name,
+			children: Vec::new(),
+			text: String::new(),
+		};
+		if empty {
+			return Ok(elem);
+		}
+
+		// Parse the content up to the closing tag.
+		loop {
+			let rest = self.rest();
+			if rest.starts_with("</") {
+				try!(self.skip_past(">"));
+				return Ok(elem);
+			} else if rest.starts_with("<!--") {
+				try!(self.skip_past("-->"));
+			} else if rest.starts_with("<![CDATA[") {
+				self.pos += 9;
+				let start = self.pos;
+				try!(self.skip_past("]]>"));
+				elem.text.push_str(&self.s[start..self.pos-3]);
+			} else if rest.starts_with("<?") {
+				try!(self.skip_past("?>"));
+			} else if rest.starts_with('<') {
+				elem.children.push(try!(self.element()));
+			} else if rest.is_empty() {
+				return Err(self.error(&format!("unterminated element `{}`", elem.name)));
+			} else {
+				let len = rest.find('<').unwrap_or(rest.len());
+				elem.text.push_str(&unescape(&rest[..len]));
+				self.pos += len;
+			}
+		}
+	}
+}
+
+/// Resolve the predefined and numeric character entities.
+fn unescape(s: &str) -> String {
+	let mut out = String::with_capacity(s.len());
+	let mut rest = s;
+	while let Some(i) = rest.find('&') {
+		out.push_str(&rest[..i]);
+		rest = &rest[i..];
+		let end = match rest.find(';') {
+			Some(e) => e,
+			None => break,
+		};
+		let entity = &rest[1..end];
+		let c = match entity {
+			"lt" => Some('<'),
+			"gt" => Some('>'),
+			"amp" => Some('&'),
+			"quot" => Some('"'),
+			"apos" => Some('\''),
+			_ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32),
+			_ if entity.starts_with('#') => entity[1..].parse().ok().and_then(::std::char::from_u32),
+			_ => None,
+		};
+		match c {
+			Some(c) => {
+				out.push(c);
+				rest = &rest[end+1..];
+			},
+			None => {
+				out.push('&');
+				rest = &rest[1..];
+			}
+		}
+	}
+	out.push_str(rest);
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn elements_and_text() {
+		let doc = parse("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE db>\n<!-- header -->\n<db kind=\"drc\">\n\t<name> top </name>\n\t<items><item/><item><cell>A</cell></item></items>\n</db>\n").unwrap();
+		assert_eq!(doc.name, "db");
+		assert_eq!(doc.child_text("name"), Some("top"));
+		assert_eq!(doc.child("missing").map(|c| c.name.clone()), None);
+		let items = doc.child("items").unwrap();
+		assert_eq!(items.children.len(), 2);
+		assert!(items.children[0].children.is_empty());
+		assert_eq!(items.children[1].child_text("cell"), Some("A"));
+	}
+
+	#[test]
+	fn attributes_are_skipped() {
+		let doc = parse("<a x='1>2' y=\"/>\"><b z=\"a/b\"/>text</a>").unwrap();
+		assert_eq!(doc.children.len(), 1);
+		assert_eq!(doc.children[0].name, "b");
+		assert_eq!(doc.text, "text");
+	}
+
+	#[test]
+	fn entities_and_cdata() {
+		let doc = parse("<v>a &lt;b&gt; &amp; &quot;c&apos; &#65;&#x42; &unknown; & <!-- x --><![CDATA[<&amp;>]]></v>").unwrap();
+		assert_eq!(doc.text, "a <b> & \"c' AB &unknown; & <&amp;>");
+		assert_eq!(unescape("&#xzz; &#1114112;"), "&#xzz; &#1114112;");
+	}
+
+	#[test]
+	fn errors_name_the_line() {
+		assert_eq!(parse("").err(), Some(String::from("expected an element on line 1")));
+		assert_eq!(parse("<a>\n<b>").err(), Some(String::from("unterminated element `b` on line 2")));
+		assert_eq!(parse("<a\nx=\"1").err(), Some(String::from("unterminated tag `a` on line 2")));
+		assert_eq!(parse("<a/>\n<b/>").err(), Some(String::from("content after the root element on line 2")));
+		assert_eq!(parse("<a><!-- x</a>").err(), Some(String::from("missing `-->` on line 1")));
+		assert_eq!(parse("text").err(), Some(String::from("expected an element on line 1")));
+	}
+}